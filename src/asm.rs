@@ -0,0 +1,731 @@
+use std::collections::HashMap;
+
+use crate::{error::VMError, trap_code::TrapCode};
+
+/// Labels of a program mapped to their addresses
+pub type SymbolTable = HashMap<String, u16>;
+
+/// Block of consecutive words of a program, with the address of the first one
+pub type Segment = (u16, Vec<u16>);
+
+const FIVE_BIT_MASK: u16 = 0b11111;
+const SIX_BIT_MASK: u16 = 0b11_1111;
+const EIGHT_BIT_MASK: u16 = 0b1111_1111;
+const NINE_BIT_MASK: u16 = 0b1_1111_1111;
+const ELEVEN_BIT_MASK: u16 = 0b111_1111_1111;
+
+/// A single line of source code split into its sections
+struct Line {
+    /// Number of the line in the source, starting from 1
+    number: usize,
+    /// Index of the `.ORIG` block the line belongs to
+    segment: usize,
+    /// Memory address where the line is assembled
+    address: u16,
+    /// Mnemonic or directive, always in uppercase
+    op: String,
+    operands: Vec<String>,
+}
+
+/// Assembles LC-3 source code into the words of a program.
+///
+/// The source is made of blocks that start with an `.ORIG` directive and may
+/// finish with `.END`, each one placed at its own address. It supports labels,
+/// comments starting with `;`, every instruction of the LC-3 (including the
+/// trap aliases like `PUTS` or `HALT`) and the `.FILL`, `.BLKW` and `.STRINGZ`
+/// directives.
+///
+/// ### Returns
+///
+/// A Result containing the segments of the program in the order they appear
+/// in the source, or a VMError::Assembly with the line where assembling failed.
+/// Segments that overlap are an error too.
+pub fn assemble(src: &str) -> Result<Vec<Segment>, VMError> {
+    assemble_with_symbols(src).map(|(segments, _)| segments)
+}
+
+/// Assembles LC-3 source code the same way `assemble` does, also returning
+/// the address of every label in the source.
+///
+/// ### Returns
+///
+/// A Result containing the segments of the program and its symbol table, or
+/// a VMError::Assembly with the line where assembling failed.
+pub fn assemble_with_symbols(src: &str) -> Result<(Vec<Segment>, SymbolTable), VMError> {
+    let (origins, lines, symbols) = first_pass(src)?;
+    let mut segments: Vec<Segment> = origins.into_iter().map(|o| (o, Vec::new())).collect();
+    for line in &lines {
+        let err = |msg: String| VMError::Assembly(format!("line {}: {}", line.number, msg));
+        let (_, words) = segments
+            .get_mut(line.segment)
+            .ok_or(err(String::from("line outside of an .ORIG block")))?;
+        encode_line(line, &symbols, words).map_err(err)?;
+    }
+    check_overlaps(&segments)?;
+    Ok((segments, symbols))
+}
+
+/// Makes sure that no two segments share a memory address
+fn check_overlaps(segments: &[Segment]) -> Result<(), VMError> {
+    let mut ranges: Vec<(usize, usize)> = segments
+        .iter()
+        .map(|(origin, words)| {
+            let start = usize::from(*origin);
+            (start, start.saturating_add(words.len()))
+        })
+        .collect();
+    ranges.sort();
+    for pair in ranges.windows(2) {
+        if let [(start, end), (next_start, _)] = pair
+            && next_start < end
+        {
+            return Err(VMError::Assembly(format!(
+                "the segment at x{:04X} overlaps the one at x{:04X}",
+                next_start, start
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Encodes the words of a segment as an image, with the origin first and
+/// every word in big-endian, the format of the `.obj` files the VM loads
+pub fn image_bytes(origin: u16, words: &[u16]) -> Vec<u8> {
+    std::iter::once(origin)
+        .chain(words.iter().copied())
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+/// Parses a text image: an `.ORIG` line followed by one hexadecimal word per
+/// line (`1021`, `x1021` or `0x1021`). Lines may start with a `label:` and
+/// anything after a `;` is a comment. Labels are only for the reader, they
+/// are not resolved.
+///
+/// ### Returns
+///
+/// A Result containing the origin of the image and its words, or a
+/// VMError::Assembly with the line where parsing failed.
+pub fn parse_text_image(src: &str) -> Result<(u16, Vec<u16>), VMError> {
+    let mut origin = None;
+    let mut words = Vec::new();
+
+    for (index, text) in src.lines().enumerate() {
+        let number = index.wrapping_add(1);
+        let err = |msg: String| VMError::Assembly(format!("line {}: {}", number, msg));
+        let code = text.split(';').next().unwrap_or_default().trim();
+        let code = match code.split_once(':') {
+            Some((_, rest)) => rest.trim(),
+            None => code,
+        };
+        if code.is_empty() {
+            continue;
+        }
+
+        match origin {
+            None => {
+                let address = code
+                    .strip_prefix(".ORIG")
+                    .or_else(|| code.strip_prefix(".orig"))
+                    .ok_or(err(String::from("the image must start with .ORIG")))?;
+                origin = Some(parse_hex_word(address.trim()).map_err(err)?);
+            }
+            Some(_) => words.push(parse_hex_word(code).map_err(err)?),
+        }
+    }
+
+    let origin = origin.ok_or(VMError::Assembly(String::from("missing .ORIG")))?;
+    Ok((origin, words))
+}
+
+/// Parses a symbol file in the format of the `.sym` files of the LC-3
+/// toolchain, where every symbol is a line with its name and its address in
+/// hexadecimal, commented out with `//`:
+///
+/// ```text
+/// // Symbol table
+/// // Scope level 0:
+/// //    Symbol Name       Page Address
+/// //    ----------------  ------------
+/// //    START             3000
+/// ```
+///
+/// ### Returns
+///
+/// The symbols found, and a warning for every line that could not be
+/// parsed. Those lines are skipped.
+pub fn parse_symbol_file(src: &str) -> (SymbolTable, Vec<String>) {
+    let mut symbols = SymbolTable::new();
+    let mut warnings = Vec::new();
+
+    for (index, text) in src.lines().enumerate() {
+        let line = text.trim();
+        let line = line.strip_prefix("//").unwrap_or(line).trim();
+        let is_header = line.starts_with("Symbol table")
+            || line.starts_with("Scope level")
+            || line.starts_with("Symbol Name")
+            || line.starts_with('-');
+        if line.is_empty() || is_header {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let symbol = match (tokens.next(), tokens.next(), tokens.next()) {
+            (Some(name), Some(address), None) => {
+                parse_hex_word(address).map(|address| (name.to_string(), address))
+            }
+            _ => Err(format!("expected a name and an address, found [{}]", line)),
+        };
+        match symbol {
+            Ok((name, address)) => {
+                symbols.insert(name, address);
+            }
+            Err(msg) => warnings.push(format!("line {}: {}", index.wrapping_add(1), msg)),
+        }
+    }
+
+    (symbols, warnings)
+}
+
+/// Parses a word written in hexadecimal, with or without an `x` or `0x` prefix
+fn parse_hex_word(token: &str) -> Result<u16, String> {
+    let hex = token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix(['x', 'X']))
+        .unwrap_or(token);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("invalid word [{}]", token))
+}
+
+/// Goes through the source to find the origin of every segment, the address
+/// of every line and the address of each label.
+fn first_pass(src: &str) -> Result<(Vec<u16>, Vec<Line>, SymbolTable), VMError> {
+    let mut origins = Vec::new();
+    // Whether the lines are inside of an .ORIG block
+    let mut in_segment = false;
+    let mut address: u16 = 0;
+    let mut lines = Vec::new();
+    let mut symbols = HashMap::new();
+
+    for (index, text) in src.lines().enumerate() {
+        let number = index.wrapping_add(1);
+        let err = |msg: String| VMError::Assembly(format!("line {}: {}", number, msg));
+        let mut tokens = tokenize(text).map_err(err)?.into_iter();
+        let Some(mut first) = tokens.next() else {
+            continue;
+        };
+
+        // Anything that is not a known operation is a label for the line
+        if !is_operation(&first) {
+            let label = first.trim_end_matches(':').to_string();
+            if !in_segment {
+                return Err(err(format!("label [{}] found outside of .ORIG", label)));
+            }
+            if symbols.insert(label.clone(), address).is_some() {
+                return Err(err(format!("label [{}] is defined twice", label)));
+            }
+            match tokens.next() {
+                Some(token) => first = token,
+                None => continue,
+            }
+        }
+
+        let op = first.to_uppercase();
+        let operands: Vec<String> = tokens.collect();
+        match (op.as_str(), in_segment) {
+            (".ORIG", _) => {
+                let value = operand(&operands, 0).and_then(parse_number).map_err(err)?;
+                let value = word(value).map_err(err)?;
+                origins.push(value);
+                in_segment = true;
+                address = value;
+                continue;
+            }
+            (_, false) => return Err(err(String::from("code must be inside of an .ORIG block"))),
+            (".END", true) => {
+                in_segment = false;
+                continue;
+            }
+            _ => {}
+        }
+
+        let size = line_size(&op, &operands).map_err(err)?;
+        lines.push(Line {
+            number,
+            segment: origins.len().saturating_sub(1),
+            address,
+            op,
+            operands,
+        });
+        address = address.wrapping_add(size);
+    }
+
+    if origins.is_empty() {
+        return Err(VMError::Assembly(String::from("missing .ORIG directive")));
+    }
+    Ok((origins, lines, symbols))
+}
+
+/// Returns the amount of words a line takes in memory
+fn line_size(op: &str, operands: &[String]) -> Result<u16, String> {
+    match op {
+        ".BLKW" => {
+            let count = operand(operands, 0).and_then(parse_number)?;
+            u16::try_from(count).map_err(|_| format!("invalid .BLKW size [{}]", count))
+        }
+        ".STRINGZ" => {
+            let chars = parse_string(operand(operands, 0)?)?;
+            // One word per character plus the null terminator
+            u16::try_from(chars.len().wrapping_add(1))
+                .map_err(|_| String::from("string is too long"))
+        }
+        _ => Ok(1),
+    }
+}
+
+/// Encodes a line and pushes the resulting words into `words`
+fn encode_line(line: &Line, symbols: &SymbolTable, words: &mut Vec<u16>) -> Result<(), String> {
+    let ops = &line.operands;
+    let reg = |index: usize| operand(ops, index).and_then(parse_register);
+    let offset = |index: usize, bits: u32, mask: u16| {
+        operand(ops, index).and_then(|o| pc_offset(o, line.address, symbols, bits, mask))
+    };
+
+    let instr = match line.op.as_str() {
+        ".FILL" => {
+            let token = operand(ops, 0)?;
+            match symbols.get(token) {
+                Some(address) => *address,
+                None => word(parse_number(token)?)?,
+            }
+        }
+        ".BLKW" => {
+            words.extend(std::iter::repeat_n(
+                0,
+                usize::from(line_size(&line.op, ops)?),
+            ));
+            return Ok(());
+        }
+        ".STRINGZ" => {
+            let chars = parse_string(operand(ops, 0)?)?;
+            words.extend(chars.into_iter().map(u16::from));
+            words.push(0);
+            return Ok(());
+        }
+        "ADD" | "AND" => {
+            let base = if line.op == "ADD" { 0x1000 } else { 0x5000 };
+            let second = operand(ops, 2)?;
+            let source = match parse_register(second) {
+                Ok(sr2) => sr2,
+                Err(_) => 1 << 5 | field(parse_number(second)?, 5, FIVE_BIT_MASK)?,
+            };
+            base | reg(0)? << 9 | reg(1)? << 6 | source
+        }
+        "NOT" => 0x9000 | reg(0)? << 9 | reg(1)? << 6 | SIX_BIT_MASK,
+        "JMP" => 0xC000 | reg(0)? << 6,
+        "RET" => 0xC1C0,
+        "JSR" => 0x4800 | offset(0, 11, ELEVEN_BIT_MASK)?,
+        "JSRR" => 0x4000 | reg(0)? << 6,
+        "LD" => 0x2000 | reg(0)? << 9 | offset(1, 9, NINE_BIT_MASK)?,
+        "LDI" => 0xA000 | reg(0)? << 9 | offset(1, 9, NINE_BIT_MASK)?,
+        "LEA" => 0xE000 | reg(0)? << 9 | offset(1, 9, NINE_BIT_MASK)?,
+        "ST" => 0x3000 | reg(0)? << 9 | offset(1, 9, NINE_BIT_MASK)?,
+        "STI" => 0xB000 | reg(0)? << 9 | offset(1, 9, NINE_BIT_MASK)?,
+        "LDR" | "STR" => {
+            let base = if line.op == "LDR" { 0x6000 } else { 0x7000 };
+            let offset6 = field(parse_number(operand(ops, 2)?)?, 6, SIX_BIT_MASK)?;
+            base | reg(0)? << 9 | reg(1)? << 6 | offset6
+        }
+        "TRAP" => {
+            let vector = parse_number(operand(ops, 0)?)?;
+            0xF000
+                | u16::try_from(vector)
+                    .ok()
+                    .filter(|v| *v <= EIGHT_BIT_MASK)
+                    .ok_or(format!("invalid trap vector [{}]", vector))?
+        }
+        "RTI" => 0x8000,
+        op => match trap_alias(op) {
+            Some(vector) => 0xF000 | vector,
+            None => branch_flags(op)? << 9 | offset(0, 9, NINE_BIT_MASK)?,
+        },
+    };
+    words.push(instr);
+    Ok(())
+}
+
+/// Splits a line into its tokens, leaving out the comments.
+/// Tokens are separated by whitespaces or commas, and a string
+/// between double quotes is kept as a single token with its quotes.
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => break,
+            '"' => {
+                current.push(c);
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    current.push(c);
+                    match c {
+                        '\\' => current.extend(chars.next()),
+                        '"' => {
+                            closed = true;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                if !closed {
+                    return Err(String::from("unterminated string"));
+                }
+            }
+            c if c.is_whitespace() || c == ',' => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+/// Tells if a token is an instruction mnemonic, a trap alias or a directive
+fn is_operation(token: &str) -> bool {
+    let op = token.to_uppercase();
+    let mnemonics = [
+        "ADD", "AND", "NOT", "JMP", "RET", "JSR", "JSRR", "LD", "LDI", "LEA", "ST", "STI", "LDR",
+        "STR", "TRAP", "RTI",
+    ];
+    op.starts_with('.')
+        || mnemonics.contains(&op.as_str())
+        || trap_alias(&op).is_some()
+        || branch_flags(&op).is_ok()
+}
+
+/// Returns the trap vector for the aliases of the trap routines
+fn trap_alias(op: &str) -> Option<u16> {
+    TrapCode::all()
+        .iter()
+        .find(|trap_code| trap_code.name() == op)
+        .map(TrapCode::vector)
+}
+
+/// Returns the nzp section for a branch mnemonic. A branch without
+/// flags is taken always.
+fn branch_flags(op: &str) -> Result<u16, String> {
+    let flags = op
+        .strip_prefix("BR")
+        .ok_or(format!("unknown instruction [{}]", op))?;
+    if flags.is_empty() {
+        return Ok(0b111);
+    }
+    let mut nzp = 0;
+    let mut last = 0;
+    for c in flags.chars() {
+        let bit = match c {
+            'N' => 0b100,
+            'Z' => 0b010,
+            'P' => 0b001,
+            _ => return Err(format!("unknown instruction [{}]", op)),
+        };
+        // The flags must be written in the nzp order and only once
+        if bit >= last && last != 0 {
+            return Err(format!("unknown instruction [{}]", op));
+        }
+        nzp |= bit;
+        last = bit;
+    }
+    Ok(nzp)
+}
+
+/// Returns the operand at `index` or an error if it is missing
+fn operand(operands: &[String], index: usize) -> Result<&str, String> {
+    operands
+        .get(index)
+        .map(String::as_str)
+        .ok_or(format!("missing operand number {}", index.wrapping_add(1)))
+}
+
+/// Parses a register name, from R0 to R7
+fn parse_register(token: &str) -> Result<u16, String> {
+    let number = token
+        .strip_prefix(['R', 'r'])
+        .and_then(|n| n.parse::<u16>().ok())
+        .filter(|n| *n <= 7);
+    number.ok_or(format!("invalid register [{}]", token))
+}
+
+/// Parses a number written in decimal (`#10`, `10`) or hexadecimal (`x0A`)
+fn parse_number(token: &str) -> Result<i32, String> {
+    let parsed = if let Some(hex) = token.strip_prefix(['x', 'X']) {
+        match hex.strip_prefix('-') {
+            Some(hex) => i32::from_str_radix(hex, 16).map(i32::wrapping_neg),
+            None => i32::from_str_radix(hex, 16),
+        }
+    } else {
+        token.strip_prefix('#').unwrap_or(token).parse::<i32>()
+    };
+    parsed.map_err(|_| format!("invalid number [{}]", token))
+}
+
+/// Parses a string literal between double quotes into its bytes,
+/// replacing the escape sequences
+fn parse_string(token: &str) -> Result<Vec<u8>, String> {
+    let inner = token
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or(format!("invalid string [{}]", token))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(other) => other,
+                None => return Err(String::from("invalid escape sequence")),
+            },
+            c => c,
+        };
+        bytes.push(u8::try_from(c).map_err(|_| format!("non ASCII character [{}]", c))?);
+    }
+    Ok(bytes)
+}
+
+/// Computes the PC offset from the instruction at `address` to the operand,
+/// which can be either a label or a number
+fn pc_offset(
+    token: &str,
+    address: u16,
+    symbols: &SymbolTable,
+    bits: u32,
+    mask: u16,
+) -> Result<u16, String> {
+    let offset = match symbols.get(token) {
+        // The offset is relative to the incremented PC
+        Some(target) => i32::from(*target)
+            .wrapping_sub(i32::from(address))
+            .wrapping_sub(1),
+        None => parse_number(token).map_err(|_| format!("unknown label [{}]", token))?,
+    };
+    field(offset, bits, mask)
+}
+
+/// Checks that `value` fits in a signed field of `bits` bits and
+/// returns it in two's complement, masked to the size of the field
+fn field(value: i32, bits: u32, mask: u16) -> Result<u16, String> {
+    let max = 1_i32.checked_shl(bits.saturating_sub(1)).unwrap_or(0);
+    if value >= max || value < max.wrapping_neg() {
+        return Err(format!("value [{}] does not fit in {} bits", value, bits));
+    }
+    Ok(word(value)? & mask)
+}
+
+/// Converts a number into a word, representing negative values in two's complement
+fn word(value: i32) -> Result<u16, String> {
+    u16::try_from(value)
+        .or_else(|_| u16::try_from(value.wrapping_add(0x1_0000)))
+        .map_err(|_| format!("value [{}] does not fit in a word", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test if every kind of operand gets encoded into the
+    /// right section of the instructions
+    fn assemble_encodes_instructions() {
+        let src = "
+            .ORIG x3000
+            ADD R0, R1, R2
+            ADD R0, R1, #-1
+            AND R3, R3, #0
+            NOT R0, R1
+            LDR R1, R6, #-2
+            STR R1, R6, #3
+            JMP R2
+            RET
+            JSRR R3
+            TRAP x25
+            .END
+        ";
+        let [(origin, words)] = assemble(src).unwrap().try_into().unwrap();
+
+        assert_eq!(origin, 0x3000);
+        assert_eq!(
+            words,
+            vec![
+                0x1042, 0x107F, 0x56E0, 0x907F, 0x63BE, 0x7383, 0xC080, 0xC1C0, 0x40C0, 0xF025
+            ]
+        );
+    }
+
+    #[test]
+    /// Test if the labels are turned into offsets relative
+    /// to the incremented PC, forwards and backwards
+    fn assemble_resolves_labels() {
+        let src = "
+            .ORIG x3000
+    LOOP    ADD R0, R0, #-1
+            BRp LOOP
+            LEA R0, MSG
+            JSR LOOP
+            PUTS
+            HALT
+    MSG:    .STRINGZ \"hi\"
+            .END
+        ";
+        let [(_, words)] = assemble(src).unwrap().try_into().unwrap();
+
+        assert_eq!(
+            words,
+            vec![
+                0x103F, 0x03FE, 0xE003, 0x4FFC, 0xF022, 0xF025, 0x0068, 0x0069, 0x0000
+            ]
+        );
+    }
+
+    #[test]
+    /// Test if the directives reserve the expected words
+    fn assemble_handles_directives() {
+        let src = "
+            .ORIG x4000
+    DATA    .FILL xBEEF
+            .FILL #-2
+            .BLKW 2
+            .FILL DATA
+        ";
+        let [(origin, words)] = assemble(src).unwrap().try_into().unwrap();
+
+        assert_eq!(origin, 0x4000);
+        assert_eq!(words, vec![0xBEEF, 0xFFFE, 0x0000, 0x0000, 0x4000]);
+    }
+
+    #[test]
+    /// Test if every .ORIG block becomes a segment at its own address,
+    /// with labels that can be used from the other blocks
+    fn assemble_splits_orig_blocks_into_segments() {
+        let src = "
+            .ORIG x3000
+            LDI R0, PTR
+            HALT
+    PTR     .FILL DATA
+            .END
+
+            .ORIG x4000
+    DATA    .FILL x1234
+            .END
+        ";
+        let segments = assemble(src).unwrap();
+
+        assert_eq!(
+            segments,
+            vec![
+                (0x3000, vec![0xA001, 0xF025, 0x4000]),
+                (0x4000, vec![0x1234])
+            ]
+        );
+    }
+
+    #[test]
+    /// Test if the symbol table has the address of every label
+    fn assemble_with_symbols_returns_labels() {
+        let src = ".ORIG x3000\nSTART ADD R0, R0, #1\nBR START\nMSG: .STRINGZ \"ab\"\nEND HALT";
+        let (_, symbols) = assemble_with_symbols(src).unwrap();
+
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols.get("START"), Some(&0x3000));
+        assert_eq!(symbols.get("MSG"), Some(&0x3002));
+        assert_eq!(symbols.get("END"), Some(&0x3005));
+    }
+
+    #[test]
+    /// Test if segments that share addresses are rejected
+    fn assemble_rejects_overlapping_segments() {
+        let src = ".ORIG x3000\n.BLKW 4\n.END\n.ORIG x3003\n.FILL 1\n.END";
+
+        assert!(matches!(assemble(src), Err(VMError::Assembly(_))));
+    }
+
+    #[test]
+    /// Test if errors report the line where they happened
+    fn assemble_reports_errors_with_line_number() {
+        let missing_label = ".ORIG x3000\nBRz NOWHERE";
+        let out_of_range = ".ORIG x3000\nADD R0, R0, #16";
+        let no_orig = "ADD R0, R0, #1";
+        let after_end = ".ORIG x3000\n.END\nADD R0, R0, #1";
+
+        for src in [missing_label, out_of_range, no_orig, after_end] {
+            match assemble(src) {
+                Err(VMError::Assembly(msg)) => assert!(msg.starts_with("line ")),
+                _ => panic!("expected an Assembly error for {:?}", src),
+            }
+        }
+    }
+
+    #[test]
+    /// Test if the text images skip comments and labels and accept
+    /// words with or without a prefix
+    fn parse_text_image_reads_words() {
+        let src = "
+            ; Adds one to R0 and halts
+            .ORIG x3000
+    START:  1021    ; ADD R0, R0, #1
+            xF025   ; HALT
+            0xBEEF
+        ";
+        let (origin, words) = parse_text_image(src).unwrap();
+
+        assert_eq!(origin, 0x3000);
+        assert_eq!(words, vec![0x1021, 0xF025, 0xBEEF]);
+    }
+
+    #[test]
+    /// Test if text images without an origin or with words that are
+    /// not hexadecimal get rejected
+    fn parse_text_image_rejects_invalid_images() {
+        assert!(matches!(
+            parse_text_image("1021"),
+            Err(VMError::Assembly(_))
+        ));
+        assert!(matches!(
+            parse_text_image(".ORIG x3000\nHALT"),
+            Err(VMError::Assembly(_))
+        ));
+        assert!(matches!(
+            parse_text_image("; empty"),
+            Err(VMError::Assembly(_))
+        ));
+    }
+
+    #[test]
+    /// Test if the symbols of a .sym file are parsed, skipping
+    /// the header and the malformed lines
+    fn parse_symbol_file_skips_malformed_lines() {
+        let src = "// Symbol table
+// Scope level 0:
+//\tSymbol Name       Page Address
+//\t----------------  ------------
+//\tSTART             3000
+//\tLOOP              x3004
+//\tBROKEN
+//\tBAD               zzzz
+";
+        let (symbols, warnings) = parse_symbol_file(src);
+
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.get("START"), Some(&0x3000));
+        assert_eq!(symbols.get("LOOP"), Some(&0x3004));
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("line 7"));
+    }
+}
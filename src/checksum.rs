@@ -0,0 +1,221 @@
+use crate::error::VMError;
+
+/// Initial hash values, the first 32 bits of the fractional parts
+/// of the square roots of the first 8 primes
+const INITIAL_HASH: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Round constants, the first 32 bits of the fractional parts
+/// of the cube roots of the first 64 primes
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 digest calculated a piece at a time, so a file can be hashed
+/// while it is read instead of keeping all of it in memory
+pub struct Sha256 {
+    hash: [u32; 8],
+    /// Bytes that do not fill a block of 64 yet
+    pending: Vec<u8>,
+    /// Number of bytes hashed so far
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self {
+            hash: INITIAL_HASH,
+            pending: Vec::with_capacity(64),
+            len: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the bytes to the ones hashed so far
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.len = self
+            .len
+            .wrapping_add(u64::try_from(bytes.len()).unwrap_or(u64::MAX));
+        self.pending.extend_from_slice(bytes);
+        let full = self.pending.len().saturating_sub(self.pending.len() % 64);
+        for block in self.pending.chunks_exact(64) {
+            compress(&mut self.hash, block);
+        }
+        self.pending.drain(..full);
+    }
+
+    /// Returns the digest of every byte given to `update`
+    pub fn finish(mut self) -> [u8; 32] {
+        // Pad the message so its length is a multiple of 64 bytes, ending
+        // with its original length in bits
+        let bit_len = self.len.wrapping_mul(8);
+        let mut msg = std::mem::take(&mut self.pending);
+        msg.push(0x80);
+        while msg.len().checked_rem(64) != Some(56) {
+            msg.push(0);
+        }
+        msg.extend_from_slice(&bit_len.to_be_bytes());
+        for block in msg.chunks_exact(64) {
+            compress(&mut self.hash, block);
+        }
+
+        let mut digest = [0u8; 32];
+        for (out, word) in digest.chunks_exact_mut(4).zip(self.hash) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+/// Calculates the SHA-256 digest of the bytes
+pub fn sha256(bytes: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+/// Mixes a block of 64 bytes into the hash
+fn compress(hash: &mut [u32; 8], block: &[u8]) {
+    let mut schedule: Vec<u32> = block
+        .chunks_exact(4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap_or_default()))
+        .collect();
+    while schedule.len() < 64 {
+        // Each new word depends on the ones 16, 15, 7 and 2 places before it
+        let [.., w16, w15, _, _, _, _, _, _, _, w7, _, _, _, _, w2, _] = schedule.as_slice() else {
+            break;
+        };
+        let s0 = w15.rotate_right(7) ^ w15.rotate_right(18) ^ (w15 >> 3);
+        let s1 = w2.rotate_right(17) ^ w2.rotate_right(19) ^ (w2 >> 10);
+        let word = w16.wrapping_add(s0).wrapping_add(*w7).wrapping_add(s1);
+        schedule.push(word);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *hash;
+    for (k, w) in ROUND_CONSTANTS.iter().zip(&schedule) {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let choice = (e & f) ^ (!e & g);
+        let temp1 = h
+            .wrapping_add(s1)
+            .wrapping_add(choice)
+            .wrapping_add(*k)
+            .wrapping_add(*w);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let majority = (a & b) ^ (a & c) ^ (b & c);
+        let temp2 = s0.wrapping_add(majority);
+
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(temp1);
+        d = c;
+        c = b;
+        b = a;
+        a = temp1.wrapping_add(temp2);
+    }
+
+    for (word, new) in hash.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(new);
+    }
+}
+
+/// Returns the SHA-256 digest of the bytes as a lowercase hexadecimal string
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    to_hex(&sha256(bytes))
+}
+
+/// Writes a digest as a lowercase hexadecimal string
+fn to_hex(digest: &[u8; 32]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Checks that the SHA-256 digest of the bytes is the expected one. The
+/// expected digest can be followed by anything, like the file name that
+/// `sha256sum` writes after it.
+///
+/// ### Returns
+///
+/// A Result that is a VMError::ChecksumMismatch with both digests if they
+/// are not the same.
+pub fn verify_sha256(bytes: &[u8], expected: &str) -> Result<(), VMError> {
+    verify_digest(&sha256(bytes), expected)
+}
+
+/// Checks that a digest already calculated, like the one of a `Sha256`,
+/// is the expected one, in the same way as `verify_sha256`.
+pub fn verify_digest(digest: &[u8; 32], expected: &str) -> Result<(), VMError> {
+    let expected = expected
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    let actual = to_hex(digest);
+    if expected != actual {
+        return Err(VMError::ChecksumMismatch(expected, actual));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test the digests against known SHA-256 values, including a
+    /// message that needs two blocks
+    fn sha256_matches_known_digests() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    /// Test if hashing the bytes in pieces of any size gives the same
+    /// digest as hashing all of them at once
+    fn sha256_in_pieces_matches_whole_digest() {
+        let bytes: Vec<u8> = (0..=255).cycle().take(1000).collect();
+
+        for piece in [1, 7, 63, 64, 65, 1000] {
+            let mut hasher = Sha256::new();
+            for chunk in bytes.chunks(piece) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finish(), sha256(&bytes));
+        }
+    }
+
+    #[test]
+    /// Test if the verification accepts the right digest in any case and
+    /// followed by a file name, and rejects a different one
+    fn verify_sha256_compares_digests() {
+        let digest = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD  abc.obj";
+
+        assert!(verify_sha256(b"abc", digest).is_ok());
+        assert!(matches!(
+            verify_sha256(b"abd", digest),
+            Err(VMError::ChecksumMismatch(..))
+        ));
+    }
+}
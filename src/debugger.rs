@@ -0,0 +1,155 @@
+use crate::{hardware::Register, utils::parse_u16};
+
+/// A command of the debugger, already parsed from the line the user wrote.
+/// Addresses and numbers can be written in decimal or hexadecimal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugCommand {
+    /// `step [N]`: runs N instructions, 1 if it is not given
+    Step(u16),
+    /// `continue`: runs until a breakpoint or until the VM stops
+    Continue,
+    /// `break ADDR`: adds a breakpoint
+    Break(u16),
+    /// `delete ADDR`: removes a breakpoint
+    Delete(u16),
+    /// `regs`: shows the registers
+    Regs,
+    /// `mem ADDR [LEN]`: shows LEN memory locations, 1 if it is not given
+    Mem { address: u16, len: u16 },
+    /// `set REG VAL`: changes the value of a register
+    Set(Register, u16),
+    /// `disasm ADDR [LEN]`: disassembles LEN words, 1 if it is not given
+    Disasm { address: u16, len: u16 },
+    /// `quit`: leaves the debugger
+    Quit,
+}
+
+/// Parses a line written in the debugger into its command.
+///
+/// ### Returns
+///
+/// A Result containing the command, or a message explaining why the line
+/// is not a valid one.
+pub fn parse_command(line: &str) -> Result<DebugCommand, String> {
+    let mut words = line.split_whitespace();
+    let Some(name) = words.next() else {
+        return Err(String::from("empty command"));
+    };
+    let args: Vec<&str> = words.collect();
+    let number = |arg: &str| parse_u16(arg).map_err(|e| format!("{:?}", e));
+
+    let command = match (name, args.as_slice()) {
+        ("step", []) => DebugCommand::Step(1),
+        ("step", [count]) => DebugCommand::Step(number(count)?),
+        ("continue", []) => DebugCommand::Continue,
+        ("break", [address]) => DebugCommand::Break(number(address)?),
+        ("delete", [address]) => DebugCommand::Delete(number(address)?),
+        ("regs", []) => DebugCommand::Regs,
+        ("mem", [address]) => DebugCommand::Mem {
+            address: number(address)?,
+            len: 1,
+        },
+        ("mem", [address, len]) => DebugCommand::Mem {
+            address: number(address)?,
+            len: number(len)?,
+        },
+        ("set", [reg, value]) => {
+            let reg = reg.parse::<Register>().map_err(|e| format!("{:?}", e))?;
+            DebugCommand::Set(reg, number(value)?)
+        }
+        ("disasm", [address]) => DebugCommand::Disasm {
+            address: number(address)?,
+            len: 1,
+        },
+        ("disasm", [address, len]) => DebugCommand::Disasm {
+            address: number(address)?,
+            len: number(len)?,
+        },
+        ("quit", []) => DebugCommand::Quit,
+        (
+            "step" | "continue" | "break" | "delete" | "regs" | "mem" | "set" | "disasm" | "quit",
+            _,
+        ) => return Err(format!("wrong arguments for [{}]", name)),
+        _ => return Err(format!("unknown command [{}]", name)),
+    };
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test if every command is parsed, with and without
+    /// its optional arguments
+    fn parse_command_parses_every_command() {
+        let cases = [
+            ("step", DebugCommand::Step(1)),
+            ("step 10", DebugCommand::Step(10)),
+            ("continue", DebugCommand::Continue),
+            ("break x3004", DebugCommand::Break(0x3004)),
+            ("delete 12292", DebugCommand::Delete(0x3004)),
+            ("regs", DebugCommand::Regs),
+            (
+                "mem x3000",
+                DebugCommand::Mem {
+                    address: 0x3000,
+                    len: 1,
+                },
+            ),
+            (
+                "  mem   x3000   0x10  ",
+                DebugCommand::Mem {
+                    address: 0x3000,
+                    len: 16,
+                },
+            ),
+            ("set r5 xFFFF", DebugCommand::Set(Register::R5, 0xFFFF)),
+            ("set PC x4000", DebugCommand::Set(Register::PC, 0x4000)),
+            (
+                "disasm x3000",
+                DebugCommand::Disasm {
+                    address: 0x3000,
+                    len: 1,
+                },
+            ),
+            (
+                "disasm x3000 8",
+                DebugCommand::Disasm {
+                    address: 0x3000,
+                    len: 8,
+                },
+            ),
+            ("quit", DebugCommand::Quit),
+        ];
+
+        for (line, expected) in cases {
+            assert_eq!(parse_command(line), Ok(expected), "parsing [{}]", line);
+        }
+    }
+
+    #[test]
+    /// Test if malformed lines are rejected with a message
+    fn parse_command_rejects_malformed_lines() {
+        let lines = [
+            "",
+            "   ",
+            "jump x3000",
+            "step ten",
+            "step 1 2",
+            "continue now",
+            "break",
+            "break x10000",
+            "mem",
+            "mem x3000 1 2",
+            "set R8 1",
+            "set R1",
+            "disasm",
+            "quit now",
+        ];
+
+        for line in lines {
+            assert!(parse_command(line).is_err(), "parsing [{}]", line);
+        }
+    }
+}
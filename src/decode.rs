@@ -0,0 +1,379 @@
+use crate::{error::VMError, hardware::Register};
+
+const THREE_BIT_MASK: u16 = 0b111;
+const FIVE_BIT_MASK: u16 = 0b11111;
+
+/// Second operand of ADD and AND
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    Immediate(i16),
+}
+
+/// An instruction split into the fields of its operation. Offsets are
+/// already sign extended and relative to the incremented PC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodedInstr {
+    /// Branch when any of the condition flags in `nzp` is set
+    Br {
+        nzp: u16,
+        offset: i16,
+    },
+    Add {
+        dr: Register,
+        sr1: Register,
+        operand: Operand,
+    },
+    Ld {
+        dr: Register,
+        offset: i16,
+    },
+    St {
+        sr: Register,
+        offset: i16,
+    },
+    Jsr {
+        offset: i16,
+    },
+    Jsrr {
+        base: Register,
+    },
+    And {
+        dr: Register,
+        sr1: Register,
+        operand: Operand,
+    },
+    Ldr {
+        dr: Register,
+        base: Register,
+        offset: i16,
+    },
+    Str {
+        sr: Register,
+        base: Register,
+        offset: i16,
+    },
+    Rti,
+    Not {
+        dr: Register,
+        sr: Register,
+    },
+    Ldi {
+        dr: Register,
+        offset: i16,
+    },
+    Sti {
+        sr: Register,
+        offset: i16,
+    },
+    /// Also used for RET, which jumps to the address in R7
+    Jmp {
+        base: Register,
+    },
+    Lea {
+        dr: Register,
+        offset: i16,
+    },
+    Trap {
+        vect: u8,
+    },
+}
+
+impl DecodedInstr {
+    /// Returns the offset that gets added to the incremented PC, for the
+    /// instructions that use PC-relative addressing
+    pub fn pc_offset(&self) -> Option<i16> {
+        match *self {
+            DecodedInstr::Br { offset, .. }
+            | DecodedInstr::Ld { offset, .. }
+            | DecodedInstr::St { offset, .. }
+            | DecodedInstr::Jsr { offset }
+            | DecodedInstr::Ldi { offset, .. }
+            | DecodedInstr::Sti { offset, .. }
+            | DecodedInstr::Lea { offset, .. } => Some(offset),
+            _ => None,
+        }
+    }
+}
+
+/// Splits an instruction into the fields of its operation.
+///
+/// ### Returns
+///
+/// A Result containing the decoded instruction, or a VMError::Conversion
+/// if it uses the reserved opcode.
+pub fn decode(instr: u16) -> Result<DecodedInstr, VMError> {
+    let reg = |shift: u16| Register::from_u16((instr >> shift) & THREE_BIT_MASK);
+    let operand = || -> Result<Operand, VMError> {
+        if (instr >> 5) & 1 == 1 {
+            Ok(Operand::Immediate(decode_imm5(instr)))
+        } else {
+            Ok(Operand::Register(reg(0)?))
+        }
+    };
+    let offset9 = decode_signed(instr, 9);
+    let offset6 = decode_signed(instr, 6);
+
+    let decoded = match instr >> 12 {
+        0b0000 => DecodedInstr::Br {
+            nzp: (instr >> 9) & THREE_BIT_MASK,
+            offset: offset9,
+        },
+        0b0001 => DecodedInstr::Add {
+            dr: reg(9)?,
+            sr1: reg(6)?,
+            operand: operand()?,
+        },
+        0b0010 => DecodedInstr::Ld {
+            dr: reg(9)?,
+            offset: offset9,
+        },
+        0b0011 => DecodedInstr::St {
+            sr: reg(9)?,
+            offset: offset9,
+        },
+        0b0100 if (instr >> 11) & 1 == 1 => DecodedInstr::Jsr {
+            offset: decode_signed(instr, 11),
+        },
+        0b0100 => DecodedInstr::Jsrr { base: reg(6)? },
+        0b0101 => DecodedInstr::And {
+            dr: reg(9)?,
+            sr1: reg(6)?,
+            operand: operand()?,
+        },
+        0b0110 => DecodedInstr::Ldr {
+            dr: reg(9)?,
+            base: reg(6)?,
+            offset: offset6,
+        },
+        0b0111 => DecodedInstr::Str {
+            sr: reg(9)?,
+            base: reg(6)?,
+            offset: offset6,
+        },
+        0b1000 => DecodedInstr::Rti,
+        0b1001 => DecodedInstr::Not {
+            dr: reg(9)?,
+            sr: reg(6)?,
+        },
+        0b1010 => DecodedInstr::Ldi {
+            dr: reg(9)?,
+            offset: offset9,
+        },
+        0b1011 => DecodedInstr::Sti {
+            sr: reg(9)?,
+            offset: offset9,
+        },
+        0b1100 => DecodedInstr::Jmp { base: reg(6)? },
+        0b1110 => DecodedInstr::Lea {
+            dr: reg(9)?,
+            offset: offset9,
+        },
+        0b1111 => {
+            // The trapvect8 section is the low byte of the instruction
+            let [_, vect] = instr.to_be_bytes();
+            DecodedInstr::Trap { vect }
+        }
+        _ => {
+            let err_str = format!("Instruction ({:#06X}) uses the reserved opcode", instr);
+            return Err(VMError::Conversion(err_str));
+        }
+    };
+    Ok(decoded)
+}
+
+/// Takes the imm5 section of an instruction (its 5 rightmost bits)
+/// and returns it as a signed value, in the range [-16, 15].
+pub fn decode_imm5(instr: u16) -> i16 {
+    decode_signed(instr & FIVE_BIT_MASK, 5)
+}
+
+/// Takes the `bits` rightmost bits of an instruction, like the offset6 or
+/// PCoffset9 sections, and returns them as a signed value.
+pub fn decode_signed(instr: u16, bits: u32) -> i16 {
+    // Move the sign bit of the section to the MSB, so shifting it
+    // back to its place extends the sign
+    let shift = 16_u32.saturating_sub(bits);
+    let moved = instr.checked_shl(shift).unwrap_or(0);
+    moved.cast_signed().checked_shr(shift).unwrap_or(0)
+}
+
+/// Tells if any of the bits that an instruction does not use, and must be
+/// zero (or one for NOT), has a different value. This usually means that
+/// the instruction was assembled by hand with a mistake.
+pub fn has_reserved_bits(instr: u16) -> bool {
+    match instr >> 12 {
+        // ADD and AND in register mode leave bits 3 and 4 unused
+        0b0001 | 0b0101 => (instr >> 5) & 1 == 0 && instr & 0b1_1000 != 0,
+        // NOT has its 6 rightmost bits set
+        0b1001 => instr & 0b11_1111 != 0b11_1111,
+        // JMP only uses the BaseR section
+        0b1100 => instr & 0b1110_0011_1111 != 0,
+        // JSRR only uses the BaseR section
+        0b0100 => (instr >> 11) & 1 == 0 && instr & 0b0110_0011_1111 != 0,
+        // TRAP only uses the trapvect8 section
+        0b1111 => instr & 0b1111_0000_0000 != 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test the decoding of every opcode, with both modes for
+    /// ADD, AND and JSR
+    fn decode_splits_every_opcode() {
+        use DecodedInstr::*;
+        use Register::*;
+
+        let cases = [
+            (
+                0x0BFE,
+                Br {
+                    nzp: 0b101,
+                    offset: -2,
+                },
+            ),
+            (
+                0x1042,
+                Add {
+                    dr: R0,
+                    sr1: R1,
+                    operand: Operand::Register(R2),
+                },
+            ),
+            (
+                0x107F,
+                Add {
+                    dr: R0,
+                    sr1: R1,
+                    operand: Operand::Immediate(-1),
+                },
+            ),
+            (0x2205, Ld { dr: R1, offset: 5 }),
+            (0x37FF, St { sr: R3, offset: -1 }),
+            (0x4FFD, Jsr { offset: -3 }),
+            (0x40C0, Jsrr { base: R3 }),
+            (
+                0x5A03,
+                And {
+                    dr: R5,
+                    sr1: R0,
+                    operand: Operand::Register(R3),
+                },
+            ),
+            (
+                0x5260,
+                And {
+                    dr: R1,
+                    sr1: R1,
+                    operand: Operand::Immediate(0),
+                },
+            ),
+            (
+                0x63BE,
+                Ldr {
+                    dr: R1,
+                    base: R6,
+                    offset: -2,
+                },
+            ),
+            (
+                0x7383,
+                Str {
+                    sr: R1,
+                    base: R6,
+                    offset: 3,
+                },
+            ),
+            (0x8000, Rti),
+            (0x907F, Not { dr: R0, sr: R1 }),
+            (
+                0xA0FF,
+                Ldi {
+                    dr: R0,
+                    offset: 255,
+                },
+            ),
+            (
+                0xBF00,
+                Sti {
+                    sr: R7,
+                    offset: -256,
+                },
+            ),
+            (0xC1C0, Jmp { base: R7 }),
+            (0xE002, Lea { dr: R0, offset: 2 }),
+            (0xF025, Trap { vect: 0x25 }),
+        ];
+
+        for (instr, expected) in cases {
+            assert_eq!(decode(instr).unwrap(), expected, "decoding {:#06X}", instr);
+        }
+    }
+
+    #[test]
+    /// Test if every word decodes except for the ones with the reserved opcode
+    fn decode_rejects_only_reserved_opcode() {
+        for instr in 0..=u16::MAX {
+            assert_eq!(decode(instr).is_err(), instr >> 12 == 0b1101);
+        }
+    }
+
+    #[test]
+    /// Test the values in the limits of the imm5 section
+    fn decode_imm5_boundaries() {
+        // ADD R0, R0, #-16
+        assert_eq!(decode_imm5(0x1030), -16);
+        // ADD R0, R0, #15
+        assert_eq!(decode_imm5(0x102F), 15);
+        // ADD R0, R1, #-1
+        assert_eq!(decode_imm5(0x107F), -1);
+        assert_eq!(decode_imm5(0x1020), 0);
+    }
+
+    #[test]
+    /// Test if the unused bits are detected only
+    /// when they hold an unexpected value
+    fn has_reserved_bits_detects_unused_bits() {
+        // ADD R0, R1, R2 and ADD R0, R1, #-1
+        assert!(!has_reserved_bits(0x1042));
+        assert!(!has_reserved_bits(0x107F));
+        // ADD R0, R1, R2 with bits 3 and 4 set
+        assert!(has_reserved_bits(0x105A));
+        // NOT R0, R1 and NOT without its 6 rightmost bits set
+        assert!(!has_reserved_bits(0x907F));
+        assert!(has_reserved_bits(0x9040));
+        // RET and RET with bit 0 set
+        assert!(!has_reserved_bits(0xC1C0));
+        assert!(has_reserved_bits(0xC1C1));
+        // JSR with a long offset uses all of its bits
+        assert!(!has_reserved_bits(0x4FFF));
+        // TRAP x25 and TRAP with bit 8 set
+        assert!(!has_reserved_bits(0xF025));
+        assert!(has_reserved_bits(0xF125));
+    }
+
+    #[test]
+    /// Test if the sections of different sizes get their sign extended
+    fn decode_signed_extends_sign() {
+        // PCoffset9 of BRnzp #-1
+        assert_eq!(decode_signed(0x0FFF, 9), -1);
+        // PCoffset11 of JSR #1023
+        assert_eq!(decode_signed(0x4BFF, 11), 1023);
+        // offset6 of LDR R1, R6, #-32
+        assert_eq!(decode_signed(0x63A0, 6), -32);
+    }
+
+    #[test]
+    /// Test if every value that fits in 5 bits can be encoded
+    /// into the section and decoded back
+    fn decode_imm5_round_trips() {
+        for value in -16_i16..=15 {
+            let encoded = value.cast_unsigned() & FIVE_BIT_MASK;
+            assert_eq!(decode_imm5(0x1020 | encoded), value);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt::{self, Display};
+
+use crate::{
+    asm::SymbolTable,
+    decode::{DecodedInstr, Operand, decode},
+    hardware::Register,
+    trap_code::TrapCode,
+};
+
+impl Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(r) => write!(f, "{}", r),
+            Operand::Immediate(imm) => write!(f, "#{}", imm),
+        }
+    }
+}
+
+/// PC-relative operand of an instruction, shown as its label when it has
+/// one, or else as the signed offset
+struct PcOperand<'a> {
+    offset: i16,
+    label: Option<&'a str>,
+}
+
+impl Display for PcOperand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.label {
+            Some(label) => write!(f, "{}", label),
+            None => write!(f, "#{}", self.offset),
+        }
+    }
+}
+
+/// Instruction together with the label of the target of its PC-relative
+/// operand, if it has one
+struct Labeled<'a> {
+    instr: DecodedInstr,
+    label: Option<&'a str>,
+}
+
+impl Display for Labeled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_instr(&self.instr, self.label, f)
+    }
+}
+
+impl Display for DecodedInstr {
+    /// Writes the instruction in assembly, like `ADD R0, R1, #-1`. The PC
+    /// offsets are shown as signed numbers relative to the incremented PC,
+    /// and the standard trap routines by their alias, like `PUTS`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_instr(self, None, f)
+    }
+}
+
+/// Writes the instruction in assembly, with `label` in place of the offset
+/// of its PC-relative operand when it is given
+fn write_instr(
+    instr: &DecodedInstr,
+    label: Option<&str>,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    let pc = |offset| PcOperand { offset, label };
+    match *instr {
+        DecodedInstr::Br { nzp: 0, .. } => write!(f, "NOP"),
+        DecodedInstr::Br { nzp, offset } => {
+            let flags: String = [(0b100, 'n'), (0b010, 'z'), (0b001, 'p')]
+                .iter()
+                .filter(|(bit, _)| nzp & bit != 0)
+                .map(|(_, flag)| flag)
+                .collect();
+            write!(f, "BR{} {}", flags, pc(offset))
+        }
+        DecodedInstr::Add { dr, sr1, operand } => {
+            write!(f, "ADD {}, {}, {}", dr, sr1, operand)
+        }
+        DecodedInstr::And { dr, sr1, operand } => {
+            write!(f, "AND {}, {}, {}", dr, sr1, operand)
+        }
+        DecodedInstr::Ld { dr, offset } => write!(f, "LD {}, {}", dr, pc(offset)),
+        DecodedInstr::St { sr, offset } => write!(f, "ST {}, {}", sr, pc(offset)),
+        DecodedInstr::Jsr { offset } => write!(f, "JSR {}", pc(offset)),
+        DecodedInstr::Jsrr { base } => write!(f, "JSRR {}", base),
+        DecodedInstr::Ldr { dr, base, offset } => {
+            write!(f, "LDR {}, {}, #{}", dr, base, offset)
+        }
+        DecodedInstr::Str { sr, base, offset } => {
+            write!(f, "STR {}, {}, #{}", sr, base, offset)
+        }
+        DecodedInstr::Rti => write!(f, "RTI"),
+        DecodedInstr::Not { dr, sr } => write!(f, "NOT {}, {}", dr, sr),
+        DecodedInstr::Ldi { dr, offset } => write!(f, "LDI {}, {}", dr, pc(offset)),
+        DecodedInstr::Sti { sr, offset } => write!(f, "STI {}, {}", sr, pc(offset)),
+        DecodedInstr::Jmp { base: Register::R7 } => write!(f, "RET"),
+        DecodedInstr::Jmp { base } => write!(f, "JMP {}", base),
+        DecodedInstr::Lea { dr, offset } => write!(f, "LEA {}, {}", dr, pc(offset)),
+        // The standard trap routines are shown with their alias
+        DecodedInstr::Trap { vect } => match TrapCode::try_from(u16::from(vect)) {
+            Ok(trap_code) => write!(f, "{}", trap_code.name()),
+            Err(_) => write!(f, "TRAP x{:02X}", vect),
+        },
+    }
+}
+
+/// Turns an encoded instruction into its assembly representation, like
+/// `ADD R0, R1, #-1`. Words that are not valid instructions are shown
+/// as a `.FILL` of their value.
+pub fn disassemble(instr: u16) -> String {
+    match decode(instr) {
+        Ok(decoded) => decoded.to_string(),
+        Err(_) => format!(".FILL x{:04X}", instr),
+    }
+}
+
+/// Disassembles an instruction that is stored in `address`, the same way
+/// `disassemble` does. When the target of a PC-relative instruction has a
+/// label in `symbols`, the label is shown instead of the offset.
+pub fn disassemble_at(instr: u16, address: u16, symbols: &SymbolTable) -> String {
+    let Ok(decoded) = decode(instr) else {
+        return disassemble(instr);
+    };
+    let label = decoded.pc_offset().and_then(|offset| {
+        let target = address.wrapping_add(1).wrapping_add_signed(offset);
+        symbol_at(symbols, target)
+    });
+    Labeled {
+        instr: decoded,
+        label,
+    }
+    .to_string()
+}
+
+/// Returns the label of an address, if it has one. When there are several
+/// labels for the same address, the first one in alphabetical order is used.
+pub fn symbol_at(symbols: &SymbolTable, address: u16) -> Option<&str> {
+    symbols
+        .iter()
+        .filter(|(_, addr)| **addr == address)
+        .map(|(name, _)| name.as_str())
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test the representation of each kind of instruction
+    fn disassemble_shows_every_instruction() {
+        let cases = [
+            (0x1042, "ADD R0, R1, R2"),
+            (0x107F, "ADD R0, R1, #-1"),
+            (0x5260, "AND R1, R1, #0"),
+            (0x0BFE, "BRnp #-2"),
+            (0x0E01, "BRnzp #1"),
+            (0x0000, "NOP"),
+            (0x2205, "LD R1, #5"),
+            (0x3205, "ST R1, #5"),
+            (0x4FFD, "JSR #-3"),
+            (0x4040, "JSRR R1"),
+            (0x63BE, "LDR R1, R6, #-2"),
+            (0x7383, "STR R1, R6, #3"),
+            (0x907F, "NOT R0, R1"),
+            (0xA205, "LDI R1, #5"),
+            (0xB205, "STI R1, #5"),
+            (0xC1C0, "RET"),
+            (0xC080, "JMP R2"),
+            (0xE002, "LEA R0, #2"),
+            (0xF025, "HALT"),
+            (0xF022, "PUTS"),
+            (0xF026, "TRAP x26"),
+            (0x8000, "RTI"),
+            (0xD123, ".FILL xD123"),
+        ];
+
+        for (instr, expected) in cases {
+            assert_eq!(disassemble(instr), expected);
+        }
+    }
+
+    #[test]
+    /// Test if the targets with a label show it instead of the offset,
+    /// and the ones without a label keep the offset
+    fn disassemble_at_shows_labels() {
+        let symbols = SymbolTable::from([
+            (String::from("LOOP"), 0x3000),
+            (String::from("MSG"), 0x3010),
+        ]);
+
+        // BRp #-2 from x3001 and LEA R0, #14 from x3001
+        assert_eq!(disassemble_at(0x03FE, 0x3001, &symbols), "BRp LOOP");
+        assert_eq!(disassemble_at(0xE00E, 0x3001, &symbols), "LEA R0, MSG");
+        assert_eq!(disassemble_at(0xE00D, 0x3001, &symbols), "LEA R0, #13");
+        assert_eq!(disassemble_at(0x1021, 0x3001, &symbols), "ADD R0, R0, #1");
+    }
+}
@@ -10,7 +10,35 @@ pub enum VMError {
     TermiosCreation(String),
     TermiosSetup(String),
     OpenFile(String, String),
+    ImageNotFound(String, String),
     NoMoreBytes(String),
+    Assembly(String),
+    ChecksumMismatch(String, String),
+    StackUnderflow(u16),
+    PrivilegeViolation(String),
+    AccessViolation(u16),
+    TruncatedImage(usize),
+    InvalidTrapVector(u16),
+    UnterminatedString(u16),
+    MissingHalt(String),
+    UserAbort(u16),
+    ReservedBits { address: u16, instr: u16 },
+    CallDepthExceeded(usize),
+}
+
+impl VMError {
+    /// Tells if the VM can keep running after this error happened, since it
+    /// only affects the instruction that caused it. Errors on the I/O or the
+    /// terminal cannot be recovered.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            Self::Arithmetic { .. }
+                | Self::Conversion(_)
+                | Self::InvalidIndex(_)
+                | Self::ReservedBits { .. }
+        )
+    }
 }
 
 impl Debug for VMError {
@@ -38,7 +66,62 @@ impl Debug for VMError {
                 "OpenFile: cannot open file [{}] because of error [{}]",
                 path, error
             ),
+            Self::ImageNotFound(path, error) => write!(
+                f,
+                "ImageNotFound: file [{}] does not exist, error [{}]",
+                path, error
+            ),
             Self::NoMoreBytes(arg0) => f.debug_tuple("NoMoreBytes").field(arg0).finish(),
+            Self::Assembly(arg0) => write!(f, "Assembly: {}", arg0),
+            Self::ChecksumMismatch(expected, actual) => write!(
+                f,
+                "ChecksumMismatch: expected SHA-256 [{}] but the image has [{}]",
+                expected, actual
+            ),
+            Self::StackUnderflow(sp) => write!(
+                f,
+                "StackUnderflow: cannot pop from the supervisor stack with R6 at [{:#06X}]",
+                sp
+            ),
+            Self::PrivilegeViolation(arg0) => write!(f, "PrivilegeViolation: {}", arg0),
+            Self::AccessViolation(address) => write!(
+                f,
+                "AccessViolation: address [{:#06X}] cannot be accessed in user mode",
+                address
+            ),
+            Self::TruncatedImage(offset) => write!(
+                f,
+                "TruncatedImage: the image ends in the middle of a word, at byte [{}]",
+                offset
+            ),
+            Self::InvalidTrapVector(vector) => write!(
+                f,
+                "InvalidTrapVector: the trap vector table has no handler for vector [{:#04X}]",
+                vector
+            ),
+            Self::UnterminatedString(start) => write!(
+                f,
+                "UnterminatedString: the string at [{:#06X}] has no x0000 before the maximum length",
+                start
+            ),
+            Self::MissingHalt(reason) => write!(
+                f,
+                "MissingHalt: the program stopped because of [{}] instead of a HALT",
+                reason
+            ),
+            Self::UserAbort(code) => {
+                write!(f, "UserAbort: the program aborted with code [{}]", code)
+            }
+            Self::CallDepthExceeded(depth) => write!(
+                f,
+                "CallDepthExceeded: the program went [{}] calls deep without returning",
+                depth
+            ),
+            Self::ReservedBits { address, instr } => write!(
+                f,
+                "ReservedBits: the instruction [{:#06X}] at [{:#06X}] has unused bits set",
+                instr, address
+            ),
         }
     }
 }
@@ -0,0 +1,71 @@
+/// Small programs embedded in the library, so tests and demos can load
+/// them by name without knowing where their images are. Every one of them
+/// ends with HALT.
+const EXAMPLES: [(&str, &[u8]); 4] = [
+    // Writes "Hello, World!" with PUTS
+    ("hello", include_bytes!("../test_files/hello.obj")),
+    // Writes back every character it reads until a newline
+    ("echo", include_bytes!("../test_files/echo.obj")),
+    // Multiplies 6 by 7 with repeated additions and writes the result
+    ("arithmetic", include_bytes!("../test_files/arithmetic.obj")),
+    // Spins on a countdown and on the DSR before writing a '.'
+    ("spin_wait", include_bytes!("../test_files/spin_wait.obj")),
+];
+
+/// Returns the image of the embedded program with that name, ready to be
+/// loaded with `VM::load_image`, or None if there is no such program.
+pub fn get(name: &str) -> Option<&'static [u8]> {
+    EXAMPLES
+        .iter()
+        .find(|(example, _)| *example == name)
+        .map(|(_, bytes)| *bytes)
+}
+
+/// Returns the names of every embedded program
+pub fn names() -> impl Iterator<Item = &'static str> {
+    EXAMPLES.iter().map(|(name, _)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        utils::SharedBuffer,
+        vm::{StopReason, VM},
+    };
+
+    #[test]
+    /// Test if every embedded program loads and runs until HALT,
+    /// writing what it is expected to
+    fn every_example_runs_to_halt() {
+        let expected: [(&str, &[u8]); 4] = [
+            ("hello", b"Hello, World!\n"),
+            ("echo", b"hi\n"),
+            ("arithmetic", b"6 * 7 = 42\n"),
+            ("spin_wait", b"."),
+        ];
+        assert!(names().eq(expected.iter().map(|(name, _)| *name)));
+
+        for (name, output) in expected {
+            let writer = SharedBuffer::new();
+            let mut vm = VM::new()
+                .with_quiet(true)
+                .with_input(Cursor::new("hi\n"))
+                .with_output(writer.clone());
+            vm.load_image(get(name).unwrap()).unwrap();
+
+            vm.run().unwrap();
+
+            assert_eq!(
+                vm.last_stop_reason(),
+                Some(StopReason::HaltTrap),
+                "{}",
+                name
+            );
+            assert_eq!(writer.contents(), output, "{}", name);
+        }
+        assert_eq!(get("rogue"), None);
+    }
+}
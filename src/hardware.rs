@@ -1,39 +1,145 @@
 use std::{
-    io::stdin,
+    borrow::Cow,
+    fmt::{self, Display},
     ops::{Index, IndexMut},
+    str::FromStr,
 };
 
-use crate::{error::VMError, utils::getchar};
+use crate::error::VMError;
 
-const MEMORY_MAX: usize = 65536;
+/// Number of memory locations of the LC-3, one for every 16-bit address
+pub const MEMORY_MAX: usize = 65536;
 const REGS_COUNT: usize = 10;
 
-/// Abstraction of the memory.
-/// It has 65,536 memory locations.
-pub struct Memory {
-    inner: [u16; MEMORY_MAX],
+/// Address of the Keyboard Status Register
+pub const KBSR_ADDR: u16 = 0xFE00;
+/// Address of the Keyboard Data Register
+pub const KBDR_ADDR: u16 = 0xFE02;
+/// Address of the Display Status Register
+pub const DSR_ADDR: u16 = 0xFE04;
+/// Address of the Display Data Register
+pub const DDR_ADDR: u16 = 0xFE06;
+/// Address of the Machine Control Register
+pub const MCR_ADDR: u16 = 0xFFFE;
+/// First address of the region reserved for the device registers, which
+/// goes until the last memory address
+pub const DEVICE_REGION_START: u16 = KBSR_ADDR;
+
+/// Tells if an address is in the region of the device registers
+pub fn is_device_register(addr: u16) -> bool {
+    addr >= DEVICE_REGION_START
 }
 
-impl Memory {
+/// How the VM accesses memory, so the LC-3 memory of 16-bit words can be
+/// replaced by another model, like the byte-addressable memory of the LC-3b,
+/// without changing the rest of the VM. Addresses are always 16 bits wide.
+pub trait MemoryModel {
+    /// Reads the value at an address, with the side effects that reading a
+    /// device register has.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the value, or a VMError::InvalidIndex if the
+    /// address is not in the memory.
+    fn read(&mut self, addr: u16) -> Result<u16, VMError>;
+
+    /// Writes a value at an address.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether the operation failed or not. It fails with
+    /// a VMError::InvalidIndex if the address is not in the memory.
+    fn write(&mut self, addr: u16, new_val: u16) -> Result<(), VMError>;
+
+    /// Reads the value at an address without any side effects. Addresses
+    /// that are not in the memory read as 0.
+    fn peek(&self, addr: u16) -> u16;
+
+    /// Returns the number of addresses the memory has
+    fn len(&self) -> usize;
+
+    /// Tells if the memory has no addresses at all
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns every memory location, from address 0 to the last one, when
+    /// the model keeps them as consecutive words. Models that do not, like a
+    /// byte-addressable memory, return None.
+    fn as_slice(&self) -> Option<&[u16]> {
+        None
+    }
+}
+
+/// Memory of the LC-3, made of 16-bit words.
+/// It has 65,536 memory locations, unless it was created with fewer.
+pub struct WordMemory {
+    inner: Box<[u16]>,
+}
+
+impl WordMemory {
     pub fn new() -> Self {
+        Self::with_size(MEMORY_MAX)
+    }
+
+    /// Creates a memory with only the first `size` locations, which can be
+    /// at most 65,536. Accessing any address after them is an error.
+    pub fn with_size(size: usize) -> Self {
         Self {
-            inner: [0; MEMORY_MAX],
+            inner: vec![0; size.min(MEMORY_MAX)].into_boxed_slice(),
         }
     }
 
-    /// Sets a new val in the specified memory address
+    /// Returns every memory location, from address 0 to the last one
+    pub fn as_slice(&self) -> &[u16] {
+        &self.inner
+    }
+}
+
+impl MemoryModel for WordMemory {
+    /// Reads a memory address without any of the side effects that reading
+    /// a device register has. Addresses out of range read as 0.
+    fn peek(&self, addr: u16) -> u16 {
+        self.inner
+            .get(usize::from(addr))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Reads a memory address. The display is always ready, so reading the
+    /// DisplayStatus sets its ready bit. The keyboard is handled by the VM,
+    /// which owns the input.
     ///
     /// ### Arguments
     ///
-    /// - `mem_address`: A generic that can be converted into an usize and represents the memory address to write on.
-    /// - `new_val`: The new val to write on the specified memory address.
+    /// - `addr`: An u16 representing the memory address to read from.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the data in the memory address, or a VMError if
+    /// the operation failed. The operation can fail if writing in the memory fails
+    /// (writtings are done when a device register is read) or because
+    /// the address is an invalid one and is not in the range [0, size of the memory).
+    fn read(&mut self, addr: u16) -> Result<u16, VMError> {
+        if addr == MemoryRegister::DisplayStatus {
+            self.write(MemoryRegister::DisplayStatus.address(), 1 << 15)?;
+        }
+        // Get the value
+        let index: usize = addr.into();
+        if let Some(val) = self.inner.get(index) {
+            return Ok(*val);
+        }
+        Err(VMError::InvalidIndex(index))
+    }
+
+    /// Sets a new val in the specified memory address
     ///
     /// ### Returns
     ///
     /// A Result indicating whether the operation failed or not. This operation can fail if the address
-    /// to write on is an invalid one. An address is invalid if it is not in [0, 65535].
-    pub fn write<T: Into<usize>>(&mut self, mem_address: T, new_val: u16) -> Result<(), VMError> {
-        let index: usize = mem_address.into();
+    /// to write on is an invalid one. An address is invalid if it is not in [0, size of the memory).
+    fn write(&mut self, addr: u16, new_val: u16) -> Result<(), VMError> {
+        let index: usize = addr.into();
         if let Some(val) = self.inner.get_mut(index) {
             *val = new_val;
             return Ok(());
@@ -41,34 +147,119 @@ impl Memory {
         Err(VMError::InvalidIndex(index))
     }
 
-    /// Reads a memory address. If the memory address to read is the one that stores
-    /// the KeyboardStatus, then it updates the KeyboardData address in the memory
-    /// by writing the character that was read from standard input.
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn as_slice(&self) -> Option<&[u16]> {
+        Some(&self.inner)
+    }
+}
+
+impl Default for WordMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Memory the VM runs on. It goes through a memory model, a WordMemory
+/// unless another one is given, and can record the writes so they can be
+/// undone.
+pub struct Memory {
+    model: Box<dyn MemoryModel>,
+    /// When it is being recorded, holds the address and previous
+    /// value of every location that was written
+    journal: Option<Vec<(u16, u16)>>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::with_model(Box::new(WordMemory::new()))
+    }
+
+    /// Creates a memory that reads and writes through `model`
+    pub fn with_model(model: Box<dyn MemoryModel>) -> Self {
+        Self {
+            model,
+            journal: None,
+        }
+    }
+
+    /// Starts recording the previous value of every memory location
+    /// that gets written, discarding anything recorded before
+    pub(crate) fn start_journal(&mut self) {
+        self.journal = Some(Vec::new());
+    }
+
+    /// Stops recording the written memory locations and returns
+    /// the address and previous value of each of them, in the
+    /// order they were written
+    pub(crate) fn take_journal(&mut self) -> Vec<(u16, u16)> {
+        self.journal.take().unwrap_or_default()
+    }
+
+    /// Sets a new val in the specified memory address
     ///
     /// ### Arguments
     ///
-    /// - `addr`: An u16 representing the memory address to read from.
+    /// - `mem_address`: A generic that can be converted into an usize and represents the memory address to write on.
+    /// - `new_val`: The new val to write on the specified memory address.
     ///
     /// ### Returns
     ///
-    /// A Result containing the data in the memory address, or a VMError if
-    /// the operation failed. The operation can fail if writing in the memory fails
-    /// (writtings are done when a character was read from stdin) or because
-    /// the address is an invalid one and is not in the range [0, 65535].
-    pub fn read(&mut self, addr: u16) -> Result<u16, VMError> {
-        if addr == MemoryRegister::KeyboardStatus {
-            self.write(MemoryRegister::KeyboardStatus, 1 << 15)?;
-            let mut reader = stdin();
-            let buffer = getchar(&mut reader)?;
-            let char: u16 = buffer[0].into();
-            self.write(MemoryRegister::KeyboardData, char)?;
+    /// A Result indicating whether the operation failed or not. This operation can fail if the address
+    /// to write on is an invalid one. An address is invalid if it is not in [0, size of the memory).
+    pub fn write<T: Into<usize>>(&mut self, mem_address: T, new_val: u16) -> Result<(), VMError> {
+        let index: usize = mem_address.into();
+        let addr = u16::try_from(index).map_err(|_| VMError::InvalidIndex(index))?;
+        let old_val = self.model.peek(addr);
+        self.model.write(addr, new_val)?;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.push((addr, old_val));
         }
-        // Get the value
-        let index: usize = addr.into();
-        if let Some(val) = self.inner.get(index) {
-            return Ok(*val);
+        Ok(())
+    }
+
+    /// Reads a memory address, with the side effects that reading a device
+    /// register has in the memory model
+    pub fn read(&mut self, addr: u16) -> Result<u16, VMError> {
+        self.model.read(addr)
+    }
+
+    /// Reads a memory address without any side effects. Addresses out of
+    /// range read as 0.
+    pub fn peek(&self, addr: u16) -> u16 {
+        self.model.peek(addr)
+    }
+
+    /// Returns the number of addresses the memory has
+    pub fn len(&self) -> usize {
+        self.model.len()
+    }
+
+    /// Tells if the memory has no addresses at all
+    pub fn is_empty(&self) -> bool {
+        self.model.is_empty()
+    }
+
+    /// Returns every memory location, from address 0 to the last one. It is
+    /// borrowed from the memory model when it can lend it, and else copied.
+    pub fn as_slice(&self) -> Cow<'_, [u16]> {
+        match self.model.as_slice() {
+            Some(words) => Cow::Borrowed(words),
+            None => Cow::Owned(
+                (0..=u16::MAX)
+                    .take(self.len())
+                    .map(|addr| self.peek(addr))
+                    .collect(),
+            ),
         }
-        Err(VMError::InvalidIndex(index))
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -77,7 +268,7 @@ impl Memory {
 /// - 8 general purpose registers (R0-R7)
 /// - 1 program counter register (PC)
 /// - 1 condition flags register (COND)
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Register {
     R0,
     R1,
@@ -92,19 +283,20 @@ pub enum Register {
 }
 
 impl Register {
-    fn index(&self) -> usize {
-        match self {
-            Register::R0 => 0,
-            Register::R1 => 1,
-            Register::R2 => 2,
-            Register::R3 => 3,
-            Register::R4 => 4,
-            Register::R5 => 5,
-            Register::R6 => 6,
-            Register::R7 => 7,
-            Register::PC => 8,
-            Register::Cond => 9,
-        }
+    /// Returns every register, general purpose ones first
+    pub fn all() -> &'static [Register] {
+        &[
+            Register::R0,
+            Register::R1,
+            Register::R2,
+            Register::R3,
+            Register::R4,
+            Register::R5,
+            Register::R6,
+            Register::R7,
+            Register::PC,
+            Register::Cond,
+        ]
     }
 
     pub fn from_u16(n: u16) -> Result<Self, VMError> {
@@ -126,7 +318,44 @@ impl Register {
     }
 }
 
+impl Display for Register {
+    /// Writes the name of the register as it is written in assembly
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Register::R0 => "R0",
+            Register::R1 => "R1",
+            Register::R2 => "R2",
+            Register::R3 => "R3",
+            Register::R4 => "R4",
+            Register::R5 => "R5",
+            Register::R6 => "R6",
+            Register::R7 => "R7",
+            Register::PC => "PC",
+            Register::Cond => "COND",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Register {
+    type Err = VMError;
+
+    /// Parses the name of a register, from R0 to R7, PC or COND,
+    /// in any combination of uppercase and lowercase letters
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Register::all()
+            .iter()
+            .find(|reg| reg.to_string().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or(VMError::Conversion(format!(
+                "Unknown register [{}], expected R0 to R7, PC or COND",
+                s
+            )))
+    }
+}
+
 /// Abstraction of the registers storage.
+#[derive(Clone, PartialEq, Eq)]
 pub struct Registers {
     inner: [u16; REGS_COUNT],
 }
@@ -141,24 +370,53 @@ impl Registers {
     }
 }
 
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Index<Register> for Registers {
     type Output = u16;
 
     fn index(&self, reg: Register) -> &Self::Output {
-        let index = reg.index();
-        &self.inner[index]
+        let [r0, r1, r2, r3, r4, r5, r6, r7, pc, cond] = &self.inner;
+        match reg {
+            Register::R0 => r0,
+            Register::R1 => r1,
+            Register::R2 => r2,
+            Register::R3 => r3,
+            Register::R4 => r4,
+            Register::R5 => r5,
+            Register::R6 => r6,
+            Register::R7 => r7,
+            Register::PC => pc,
+            Register::Cond => cond,
+        }
     }
 }
 
 impl IndexMut<Register> for Registers {
     fn index_mut(&mut self, reg: Register) -> &mut Self::Output {
-        let index = reg.index();
-        &mut self.inner[index]
+        let [r0, r1, r2, r3, r4, r5, r6, r7, pc, cond] = &mut self.inner;
+        match reg {
+            Register::R0 => r0,
+            Register::R1 => r1,
+            Register::R2 => r2,
+            Register::R3 => r3,
+            Register::R4 => r4,
+            Register::R5 => r5,
+            Register::R6 => r6,
+            Register::R7 => r7,
+            Register::PC => pc,
+            Register::Cond => cond,
+        }
     }
 }
 
 /// Opcodes that identify an operation
 /// that the VM supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum OpCode {
     Br,
     Add,
@@ -168,6 +426,7 @@ pub enum OpCode {
     And,
     Ldr,
     Str,
+    Rti,
     Not,
     Ldi,
     Sti,
@@ -176,6 +435,29 @@ pub enum OpCode {
     Trap,
 }
 
+impl OpCode {
+    /// Returns every operation that the VM supports
+    pub fn all() -> &'static [OpCode] {
+        &[
+            OpCode::Br,
+            OpCode::Add,
+            OpCode::Ld,
+            OpCode::St,
+            OpCode::Jsr,
+            OpCode::And,
+            OpCode::Ldr,
+            OpCode::Str,
+            OpCode::Rti,
+            OpCode::Not,
+            OpCode::Ldi,
+            OpCode::Sti,
+            OpCode::Jmp,
+            OpCode::Lea,
+            OpCode::Trap,
+        ]
+    }
+}
+
 impl TryFrom<u16> for OpCode {
     type Error = VMError;
 
@@ -189,6 +471,7 @@ impl TryFrom<u16> for OpCode {
             0b0101 => Ok(OpCode::And),
             0b0110 => Ok(OpCode::Ldr),
             0b0111 => Ok(OpCode::Str),
+            0b1000 => Ok(OpCode::Rti),
             0b1001 => Ok(OpCode::Not),
             0b1010 => Ok(OpCode::Ldi),
             0b1011 => Ok(OpCode::Sti),
@@ -221,18 +504,32 @@ impl CondFlag {
     }
 }
 
+/// Tells if a branch whose nzp section is `nzp` has to be taken when
+/// the Cond register holds `cond`. This happens when any of the flags
+/// selected in the instruction is the one currently set.
+pub fn branch_taken(nzp: u16, cond: u16) -> bool {
+    nzp & cond != 0
+}
+
 /// Registers that are located on the memory
 #[derive(Clone, Copy)]
 pub enum MemoryRegister {
     KeyboardStatus,
     KeyboardData,
+    DisplayStatus,
+    DisplayData,
+    MachineControl,
 }
 
 impl MemoryRegister {
-    fn address(&self) -> u16 {
+    /// Returns the memory address where the register is mapped
+    pub fn address(&self) -> u16 {
         match self {
-            MemoryRegister::KeyboardStatus => 0xFE00,
-            MemoryRegister::KeyboardData => 0xFE02,
+            MemoryRegister::KeyboardStatus => KBSR_ADDR,
+            MemoryRegister::KeyboardData => KBDR_ADDR,
+            MemoryRegister::DisplayStatus => DSR_ADDR,
+            MemoryRegister::DisplayData => DDR_ADDR,
+            MemoryRegister::MachineControl => MCR_ADDR,
         }
     }
 }
@@ -256,3 +553,147 @@ impl PartialEq<u16> for MemoryRegister {
         self.address() == *num
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a pattern on every address of the model and checks that reading
+    /// and peeking give it back
+    fn assert_round_trips(model: &mut impl MemoryModel) {
+        let addresses = (0..u16::MAX).filter(|addr| !is_device_register(*addr));
+        for addr in addresses.clone() {
+            model.write(addr, addr.rotate_left(3) ^ 0xA5A5).unwrap();
+        }
+        for addr in addresses {
+            let expected = addr.rotate_left(3) ^ 0xA5A5;
+            assert_eq!(model.peek(addr), expected);
+            assert_eq!(model.read(addr).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    /// Test if the word memory, used through the memory model, gives
+    /// back every word written into it
+    fn word_memory_model_round_trips() {
+        let mut mem = WordMemory::default();
+        assert_eq!(MemoryModel::len(&mem), MEMORY_MAX);
+        assert!(!mem.is_empty());
+
+        assert_round_trips(&mut mem);
+
+        let mut small = WordMemory::with_size(0x100);
+        assert!(matches!(
+            MemoryModel::write(&mut small, 0x0100, 1),
+            Err(VMError::InvalidIndex(0x100))
+        ));
+        assert_eq!(small.peek(0x0100), 0);
+    }
+
+    #[test]
+    /// Test if the default memory and registers
+    /// start with every value set to 0
+    fn default_memory_and_registers_are_zeroed() {
+        let mem = WordMemory::default();
+        let regs = Registers::default();
+
+        assert!(mem.as_slice().iter().all(|val| *val == 0));
+        assert!(Register::all().iter().all(|reg| regs[*reg] == 0));
+    }
+
+    #[test]
+    /// Test the addresses of the device registers, and that all of
+    /// them are inside of the device region
+    fn device_register_addresses() {
+        assert_eq!(KBSR_ADDR, 0xFE00);
+        assert_eq!(KBDR_ADDR, 0xFE02);
+        assert_eq!(DSR_ADDR, 0xFE04);
+        assert_eq!(DDR_ADDR, 0xFE06);
+        assert_eq!(MCR_ADDR, 0xFFFE);
+        for addr in [KBSR_ADDR, KBDR_ADDR, DSR_ADDR, DDR_ADDR, MCR_ADDR, 0xFFFF] {
+            assert!(is_device_register(addr));
+        }
+        assert!(!is_device_register(0xFDFF));
+        assert!(!is_device_register(0x3000));
+    }
+
+    #[test]
+    /// Test if a smaller memory rejects the addresses after its last location
+    fn smaller_memory_rejects_out_of_range_addresses() {
+        let mut mem = Memory::with_model(Box::new(WordMemory::with_size(0x100)));
+
+        assert_eq!(mem.len(), 0x100);
+        mem.write(0x00FF_u16, 7).unwrap();
+        assert_eq!(mem.read(0x00FF).unwrap(), 7);
+        assert!(matches!(
+            mem.write(0x0100_u16, 7),
+            Err(VMError::InvalidIndex(0x100))
+        ));
+        assert!(matches!(
+            mem.read(0x0100),
+            Err(VMError::InvalidIndex(0x100))
+        ));
+        assert_eq!(
+            WordMemory::with_size(usize::MAX).as_slice().len(),
+            MEMORY_MAX
+        );
+    }
+
+    #[test]
+    /// Test if every register name is parsed back into its register,
+    /// no matter the case of its letters
+    fn register_from_str_parses_names() {
+        for reg in Register::all() {
+            assert_eq!(reg.to_string().parse::<Register>().unwrap(), *reg);
+        }
+        assert_eq!("r5".parse::<Register>().unwrap(), Register::R5);
+        assert_eq!("pc".parse::<Register>().unwrap(), Register::PC);
+        assert_eq!("Cond".parse::<Register>().unwrap(), Register::Cond);
+    }
+
+    #[test]
+    /// Test if names of registers that do not exist are rejected
+    fn register_from_str_rejects_unknown_names() {
+        for name in ["R8", "R", "", "R01", "IR", " R0"] {
+            assert!(matches!(
+                name.parse::<Register>(),
+                Err(VMError::Conversion(_))
+            ));
+        }
+    }
+
+    #[test]
+    /// Test if the list of operations has every supported opcode once
+    fn op_code_all_lists_every_operation() {
+        let all = OpCode::all();
+        assert_eq!(all.len(), 15);
+        // Every opcode that can be decoded is in the list
+        let decoded: Vec<OpCode> = (0..16).filter_map(|n| OpCode::try_from(n).ok()).collect();
+        assert_eq!(decoded, all);
+    }
+
+    #[test]
+    /// Test the branch predicate for every nzp combination
+    /// against each one of the condition flags
+    fn branch_taken_truth_table() {
+        // Each row has the nzp section and whether the branch is taken
+        // when the flag is set to NEG, ZRO and POS respectively
+        let table: [(u16, [bool; 3]); 8] = [
+            (0b000, [false, false, false]),
+            (0b001, [false, false, true]),
+            (0b010, [false, true, false]),
+            (0b011, [false, true, true]),
+            (0b100, [true, false, false]),
+            (0b101, [true, false, true]),
+            (0b110, [true, true, false]),
+            (0b111, [true, true, true]),
+        ];
+        let flags = [CondFlag::Neg, CondFlag::Zro, CondFlag::Pos];
+
+        for (nzp, expected) in table {
+            for (flag, taken) in flags.iter().zip(expected) {
+                assert_eq!(branch_taken(nzp, flag.value()), taken);
+            }
+        }
+    }
+}
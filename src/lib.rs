@@ -0,0 +1,84 @@
+pub mod asm;
+pub mod checksum;
+pub mod debugger;
+pub mod decode;
+pub mod disasm;
+pub mod error;
+pub mod examples;
+pub mod hardware;
+pub mod os;
+pub mod trap_code;
+pub mod utils;
+pub mod vm;
+
+use std::io::Cursor;
+
+use crate::{error::VMError, utils::SharedBuffer, vm::VM};
+
+/// What the embedded hello world program has to write for the self test to pass
+const SELFTEST_OUTPUT: &[u8] = b"Hello, World!\n";
+
+/// Runs an image until it halts and returns everything the program wrote.
+///
+/// The image is loaded at its origin, which is also where it starts running,
+/// and `input` is what the program reads as its stdin. The HALT banner is
+/// not part of the output.
+///
+/// ### Returns
+///
+/// A Result containing the bytes written by the program, or the VMError
+/// that stopped it.
+pub fn run_image(bytes: &[u8], input: &[u8]) -> Result<Vec<u8>, VMError> {
+    let output = SharedBuffer::new();
+    let mut vm = VM::new()
+        .with_quiet(true)
+        .with_input(Cursor::new(input.to_vec()))
+        .with_output(output.clone());
+    vm.load_image(bytes)?;
+    vm.run()?;
+    Ok(output.contents())
+}
+
+/// Runs the embedded hello world program without a terminal and checks
+/// what it wrote, so loading, decoding, executing, the trap routines and
+/// the output are known to work.
+///
+/// ### Returns
+///
+/// A Result indicating whether the test passed, or a message explaining
+/// why it failed.
+pub fn selftest() -> Result<(), String> {
+    let image = examples::get("hello").ok_or("the hello world program is not embedded")?;
+    let output = run_image(image, b"").map_err(|e| format!("the run failed with {:?}", e))?;
+    if output != SELFTEST_OUTPUT {
+        return Err(format!(
+            "expected the output {:?} but got {:?}",
+            String::from_utf8_lossy(SELFTEST_OUTPUT),
+            String::from_utf8_lossy(&output)
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::{assemble, image_bytes};
+
+    #[test]
+    /// Test if a program that echoes a character runs from start to end
+    fn run_image_returns_program_output() {
+        let src = ".ORIG x4000\nGETC\nOUT\nLEA R0, MSG\nPUTS\nHALT\nMSG .STRINGZ \"!\"";
+        let [(origin, words)] = assemble(src).unwrap().try_into().unwrap();
+        let bytes = image_bytes(origin, &words);
+
+        assert_eq!(run_image(&bytes, b"a").unwrap(), b"a!");
+        assert!(matches!(run_image(&[], b""), Err(VMError::NoMoreBytes(_))));
+    }
+
+    #[test]
+    /// Test if the self test passes with the embedded programs
+    fn selftest_passes() {
+        assert_eq!(selftest(), Ok(()));
+    }
+}
@@ -1,28 +1,59 @@
-use std::env;
+use std::{
+    env,
+    io::{IsTerminal, stdin},
+    process::exit,
+};
 
-use error::VMError;
-use utils::{setup, shutdown};
-use vm::VM;
+use lc3::{
+    error::VMError,
+    utils::TerminalGuard,
+    vm::{VM, exit_code},
+};
 
-mod error;
-mod hardware;
-mod trap_code;
-mod utils;
-mod vm;
+/// Runs the VM with the arguments of the process and exits with the code
+/// of the contract:
+///
+/// - 0 when the program ran until it stopped, usually with HALT
+/// - 1 when loading or running the program failed
+/// - 2 when the arguments are wrong or an image does not exist
+/// - 3 when the program stopped without HALT in strict HALT mode
+/// - 4 when the program aborted with the abort trap
+fn main() {
+    let result = run();
+    if let Err(e) = &result {
+        eprintln!("Error: {:?}", e);
+    }
+    exit(exit_code(&result));
+}
 
-fn main() -> Result<(), VMError> {
-    let mut args = env::args();
+fn run() -> Result<(), VMError> {
     // Virtual Machine creation
     let mut vm = VM::new();
+    // Ctrl-C stops the VM instead of killing the process, so the
+    // terminal still gets restored
+    #[cfg(all(unix, feature = "std"))]
+    {
+        use std::sync::{Arc, atomic::AtomicBool};
+
+        let interrupted = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&interrupted))
+            .map_err(|e| VMError::TermiosSetup(format!("Cannot handle SIGINT: {}", e)))?;
+        vm = vm.with_interrupt_flag(interrupted);
+    }
     // Read the file with the instructions to execute into the VM's memory
-    vm.load_arguments(&mut args)?;
-    // Setup of Terminal
-    let termios = setup()?;
+    vm.load_arguments(env::args())?;
+    // Setup of Terminal, its original settings are restored when the
+    // guard is dropped, even if the VM fails or panics. When the input
+    // is not a terminal, like a pipe or a file, there is nothing to set up.
+    let terminal = if stdin().is_terminal() {
+        Some(TerminalGuard::new()?)
+    } else {
+        None
+    };
+    // The terminal never waits for keys, so the keyboard interrupt can be
+    // polled and Ctrl-C can stop a program blocked reading one
+    vm = vm.with_polled_input(terminal.is_some());
 
     // VM main loop
-    vm.run()?;
-
-    // Reset the terminal to its original settings
-    shutdown(termios)?;
-    Ok(())
+    vm.run()
 }
@@ -0,0 +1,146 @@
+/// Address where the operating system starts running after booting
+pub const OS_ENTRY: u16 = 0x0200;
+/// Address where the operating system keeps the address of the user
+/// program it jumps to once it has booted
+pub const OS_USER_PC: u16 = 0x0202;
+
+/// Source code of a minimal operating system for the LC-3.
+///
+/// It holds the trap vector table and the trap routines, which do all
+/// of their I/O through the memory-mapped device registers. When booted,
+/// it jumps to the address stored in `OS_USER_PC`.
+pub const OS_SOURCE: &str = r#"
+            .ORIG x0020
+            ; Trap vector table
+            .FILL TRAP_GETC
+            .FILL TRAP_OUT
+            .FILL TRAP_PUTS
+            .FILL TRAP_IN
+            .FILL TRAP_PUTSP
+            .FILL TRAP_HALT
+            .BLKW #474
+
+            ; Entry point, jumps to the user program
+OS_START    LD R7, USER_PC
+            JMP R7
+USER_PC     .FILL x3000
+
+OS_KBSR     .FILL xFE00
+OS_KBDR     .FILL xFE02
+OS_DSR      .FILL xFE04
+OS_DDR      .FILL xFE06
+OS_MCR      .FILL xFFFE
+LOW_BYTE    .FILL x00FF
+HIGH_BIT    .FILL x0100
+SAVE_R1     .BLKW 1
+SAVE_R2     .BLKW 1
+SAVE_R3     .BLKW 1
+SAVE_R4     .BLKW 1
+SAVE_R5     .BLKW 1
+SAVE_R6     .BLKW 1
+SAVE_R7     .BLKW 1
+
+            ; Writes the character in R0 on the display
+OS_PUTC     ST R2, PUTC_R2
+PUTC_WAIT   LDI R2, OS_DSR
+            BRzp PUTC_WAIT
+            STI R0, OS_DDR
+            LD R2, PUTC_R2
+            RET
+PUTC_R2     .BLKW 1
+
+            ; Writes the null-terminated string that starts in R0
+OS_PUTS     ST R0, PUTS_R0
+            ST R1, PUTS_R1
+            ST R7, PUTS_R7
+            ADD R1, R0, #0
+PUTS_LOOP   LDR R0, R1, #0
+            BRz PUTS_DONE
+            JSR OS_PUTC
+            ADD R1, R1, #1
+            BR PUTS_LOOP
+PUTS_DONE   LD R0, PUTS_R0
+            LD R1, PUTS_R1
+            LD R7, PUTS_R7
+            RET
+PUTS_R0     .BLKW 1
+PUTS_R1     .BLKW 1
+PUTS_R7     .BLKW 1
+
+TRAP_GETC   LDI R0, OS_KBSR
+            BRzp TRAP_GETC
+            LDI R0, OS_KBDR
+            RET
+
+TRAP_OUT    ST R7, SAVE_R7
+            JSR OS_PUTC
+            LD R7, SAVE_R7
+            RET
+
+TRAP_PUTS   ST R7, SAVE_R7
+            JSR OS_PUTS
+            LD R7, SAVE_R7
+            RET
+
+TRAP_IN     ST R7, SAVE_R7
+            LEA R0, IN_PROMPT
+            JSR OS_PUTS
+IN_WAIT     LDI R0, OS_KBSR
+            BRzp IN_WAIT
+            LDI R0, OS_KBDR
+            JSR OS_PUTC
+            LD R7, SAVE_R7
+            ADD R0, R0, #0
+            RET
+IN_PROMPT   .STRINGZ "Enter a character: "
+
+            ; Two characters per word, the one in the low byte goes first
+TRAP_PUTSP  ST R0, PUTS_R0
+            ST R1, SAVE_R1
+            ST R2, SAVE_R2
+            ST R3, SAVE_R3
+            ST R4, SAVE_R4
+            ST R5, SAVE_R5
+            ST R6, SAVE_R6
+            ST R7, SAVE_R7
+            ADD R1, R0, #0
+PUTSP_LOOP  LDR R3, R1, #0
+            BRz PUTSP_DONE
+            LD R4, LOW_BYTE
+            AND R0, R3, R4
+            JSR OS_PUTC
+            ; Move the high byte to the low one, one bit at a time
+            AND R0, R0, #0
+            LD R5, HIGH_BIT
+            AND R6, R6, #0
+            ADD R6, R6, #1
+PUTSP_BIT   AND R4, R3, R5
+            BRz PUTSP_NEXT
+            ADD R0, R0, R6
+PUTSP_NEXT  ADD R6, R6, R6
+            ADD R5, R5, R5
+            BRnp PUTSP_BIT
+            ADD R0, R0, #0
+            BRz PUTSP_SKIP
+            JSR OS_PUTC
+PUTSP_SKIP  ADD R1, R1, #1
+            BR PUTSP_LOOP
+PUTSP_DONE  LD R0, PUTS_R0
+            LD R1, SAVE_R1
+            LD R2, SAVE_R2
+            LD R3, SAVE_R3
+            LD R4, SAVE_R4
+            LD R5, SAVE_R5
+            LD R6, SAVE_R6
+            LD R7, SAVE_R7
+            RET
+
+            ; Writes the banner and clears the Machine Control Register
+TRAP_HALT   LEA R0, HALT_MSG
+            JSR OS_PUTS
+HALT_STOP   AND R0, R0, #0
+            STI R0, OS_MCR
+            BR HALT_STOP
+HALT_MSG    .STRINGZ "HALT\n"
+            .END
+"#;
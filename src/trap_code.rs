@@ -1,5 +1,21 @@
 use crate::error::VMError;
 
+/// Vector of the extension trap that flushes the output, so everything the
+/// program wrote is shown before it goes on, like a prompt before waiting
+/// for input. It is not part of the LC-3 ISA.
+pub const FLUSH_VECTOR: u8 = 0x26;
+
+/// Vector of the extension trap that shifts R0 by the amount in R1, which
+/// goes left when positive and right when negative. Right shifts are
+/// arithmetic, keeping the sign, when R2 is not 0. It is not part of the LC-3 ISA.
+pub const SHIFT_VECTOR: u8 = 0x27;
+
+/// Vector of the extension trap that aborts the program with the code in R0,
+/// a clean way out when it finds an error it cannot handle, like a division
+/// by zero. It is not part of the LC-3 ISA.
+pub const ABORT_VECTOR: u8 = 0x28;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TrapCode {
     GetC,
     Out,
@@ -9,6 +25,44 @@ pub enum TrapCode {
     Halt,
 }
 
+impl TrapCode {
+    /// Returns every trap routine that the VM supports
+    pub fn all() -> &'static [TrapCode] {
+        &[
+            TrapCode::GetC,
+            TrapCode::Out,
+            TrapCode::Puts,
+            TrapCode::In,
+            TrapCode::PutsP,
+            TrapCode::Halt,
+        ]
+    }
+
+    /// Returns the alias of the trap routine in assembly, like `PUTS`
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrapCode::GetC => "GETC",
+            TrapCode::Out => "OUT",
+            TrapCode::Puts => "PUTS",
+            TrapCode::In => "IN",
+            TrapCode::PutsP => "PUTSP",
+            TrapCode::Halt => "HALT",
+        }
+    }
+
+    /// Returns the trap vector that identifies the trap routine
+    pub fn vector(&self) -> u16 {
+        match self {
+            TrapCode::GetC => 0x20,
+            TrapCode::Out => 0x21,
+            TrapCode::Puts => 0x22,
+            TrapCode::In => 0x23,
+            TrapCode::PutsP => 0x24,
+            TrapCode::Halt => 0x25,
+        }
+    }
+}
+
 impl TryFrom<u16> for TrapCode {
     type Error = VMError;
 
@@ -26,3 +80,19 @@ impl TryFrom<u16> for TrapCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Test if the list of trap routines has all of them
+    /// and each one converts back from its vector
+    fn trap_code_all_lists_every_routine() {
+        let all = TrapCode::all();
+        assert_eq!(all.len(), 6);
+        for trap_code in all {
+            assert_eq!(TrapCode::try_from(trap_code.vector()).unwrap(), *trap_code);
+        }
+    }
+}
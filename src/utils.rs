@@ -1,9 +1,11 @@
 use crate::error::VMError;
 use std::{
-    io::{Error, Read, Write, stdin},
-    os::fd::AsRawFd,
+    cell::RefCell,
+    io::{self, Error, Read, Write, stdin},
+    os::fd::{AsRawFd, RawFd},
+    rc::Rc,
 };
-use termios::{ECHO, ICANON, TCSANOW, Termios, tcsetattr};
+use termios::{ECHO, ICANON, TCSANOW, Termios, VMIN, VTIME, tcsetattr};
 
 /// Takes a number whose size in bits is determined by `bit_count`
 /// and extends it so that its size is 16 bits, always taking into
@@ -14,14 +16,80 @@ pub fn sign_extend(mut x: u16, bit_count: usize) -> Result<u16, VMError> {
         minuend: 1,
         subtrahend: bit_count,
     })?;
-    let msb = x >> bitcount_sub;
+    // A number cannot have more bits than the 16 of the result
+    let msb = u32::try_from(bitcount_sub)
+        .ok()
+        .and_then(|n| x.checked_shr(n))
+        .ok_or(VMError::InvalidIndex(bit_count))?
+        & 1;
     if msb != 0 {
         // If the MSB is 1 it means it is negative, else it is positive
-        x |= 0xFFFF << bit_count;
+        x |= u32::try_from(bit_count)
+            .ok()
+            .and_then(|n| 0xFFFF_u16.checked_shl(n))
+            .unwrap_or(0);
     }
     Ok(x)
 }
 
+/// Parses a number written either in decimal (`12288`) or
+/// in hexadecimal (`x3000` or `0x3000`)
+pub fn parse_u16(s: &str) -> Result<u16, VMError> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix(['x', 'X'])) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse::<u16>(),
+    };
+    parsed.map_err(|e| VMError::Conversion(format!("Invalid number [{}]: {}", s, e)))
+}
+
+/// First two bytes of every file compressed with gzip
+#[cfg(feature = "gzip")]
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+/// Compression method of the gzip header for deflate, the only one defined
+#[cfg(feature = "gzip")]
+const GZIP_DEFLATE: u8 = 8;
+/// Bits of the flags of the gzip header that are reserved and must be 0
+#[cfg(feature = "gzip")]
+const GZIP_RESERVED_FLAGS: u8 = 0b1110_0000;
+
+/// Decompresses the bytes in place when they start with a gzip header,
+/// leaving any other bytes untouched. At most `max_len` bytes are
+/// decompressed, so a small file cannot fill the whole host memory.
+/// A raw image whose origin happens to be x1F8B is kept as it is, since
+/// the bytes are only replaced if they could be decompressed.
+///
+/// ### Returns
+///
+/// Whether the decompressed data had more than `max_len` bytes, so the
+/// ones after them were left out.
+#[cfg(feature = "gzip")]
+pub fn gunzip_in_place(bytes: &mut Vec<u8>, max_len: usize) -> bool {
+    let is_gzip = match bytes.as_slice() {
+        [magic0, magic1, method, flags, ..] => {
+            [*magic0, *magic1] == GZIP_MAGIC
+                && *method == GZIP_DEFLATE
+                && flags & GZIP_RESERVED_FLAGS == 0
+        }
+        _ => false,
+    };
+    if !is_gzip {
+        return false;
+    }
+    // One byte more than the limit is decompressed to know if there are more
+    let limit = u64::try_from(max_len.saturating_add(1)).unwrap_or(u64::MAX);
+    let mut decompressed = Vec::new();
+    let decoded = flate2::read::GzDecoder::new(bytes.as_slice())
+        .take(limit)
+        .read_to_end(&mut decompressed);
+    if decoded.is_err() {
+        return false;
+    }
+    let truncated = decompressed.len() > max_len;
+    decompressed.truncate(max_len);
+    *bytes = decompressed;
+    truncated
+}
+
 /// Reads one byte from the stdin
 pub fn getchar(reader: &mut impl Read) -> Result<[u8; 1], VMError> {
     let mut buffer = [0u8; 1];
@@ -55,26 +123,219 @@ pub fn stdout_write(buffer: &[u8], writer: &mut impl Write) -> Result<(), VMErro
     Ok(())
 }
 
-/// Disables the input buffering on the terminal.
-/// This is done by getting  the initial termios
-/// and disabling its input buffering.
-pub fn setup() -> Result<Termios, VMError> {
-    let stdin_fd = stdin().lock().as_raw_fd();
-    let initial_termios = Termios::from_fd(stdin_fd)
-        .map_err(|_| VMError::TermiosCreation(String::from("Cannot create termios")))?;
-    let mut new_termios = initial_termios;
-    new_termios.c_lflag &= !ICANON & !ECHO;
-    tcsetattr(stdin_fd, TCSANOW, &new_termios).map_err(|_| {
-        VMError::TermiosSetup(String::from("Cannot set termios with new attributes"))
-    })?;
-    Ok(initial_termios)
+/// Keeps the terminal with its input buffering and echo disabled while it
+/// is alive. Reads return at once without any byte when there is no key, so
+/// the input can be polled and whoever waits for a key can check for a
+/// Ctrl-C, like the VM does with `with_polled_input`. When dropped, even
+/// during a panic or an early return, the terminal gets back its original
+/// settings.
+pub struct TerminalGuard {
+    fd: RawFd,
+    initial_termios: Termios,
 }
 
-/// Restores the termios to the one set by `initial_termios`
-pub fn shutdown(initial_termios: Termios) -> Result<(), VMError> {
-    let stdin_fd = stdin().lock().as_raw_fd();
-    tcsetattr(stdin_fd, TCSANOW, &initial_termios).map_err(|_| {
-        VMError::TermiosSetup(String::from("Cannot set termios when shutting down"))
-    })?;
-    Ok(())
+impl TerminalGuard {
+    /// Disables the input buffering and echo of the terminal on the stdin
+    pub fn new() -> Result<Self, VMError> {
+        Self::for_fd(stdin().lock().as_raw_fd())
+    }
+
+    /// Disables the input buffering and echo of the terminal behind `fd`,
+    /// saving its initial termios so it can be restored.
+    pub fn for_fd(fd: RawFd) -> Result<Self, VMError> {
+        let initial_termios = Termios::from_fd(fd)
+            .map_err(|_| VMError::TermiosCreation(String::from("Cannot create termios")))?;
+        let mut new_termios = initial_termios;
+        new_termios.c_lflag &= !ICANON & !ECHO;
+        if let Some(min_bytes) = new_termios.c_cc.get_mut(VMIN) {
+            *min_bytes = 0;
+        }
+        // A read does not wait for a key at all
+        if let Some(timeout) = new_termios.c_cc.get_mut(VTIME) {
+            *timeout = 0;
+        }
+        tcsetattr(fd, TCSANOW, &new_termios).map_err(|_| {
+            VMError::TermiosSetup(String::from("Cannot set termios with new attributes"))
+        })?;
+        Ok(Self {
+            fd,
+            initial_termios,
+        })
+    }
+}
+
+impl Drop for TerminalGuard {
+    /// Restores the termios saved when the guard was created
+    fn drop(&mut self) {
+        // There is no way to report an error while dropping, and leaving
+        // the terminal as it is would be the only alternative anyway
+        let _ = tcsetattr(self.fd, TCSANOW, &self.initial_termios);
+    }
+}
+
+/// In-memory writer that can be cloned and handed to the VM, so the
+/// bytes it writes can still be inspected once it has finished running.
+#[derive(Clone, Default)]
+pub struct SharedBuffer {
+    inner: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SharedBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of every byte written so far
+    pub fn contents(&self) -> Vec<u8> {
+        self.inner.borrow().clone()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writer that writes every byte into two writers, like the terminal and a
+/// file, so the output can be seen live and captured at the same time.
+/// An error on either of them is returned, after trying to write on both.
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Write, B: Write> Tee<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    /// Returns the two writers, in the order they were given
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let first = self.first.write_all(buf);
+        let second = self.second.write_all(buf);
+        first.and(second)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let first = self.first.flush();
+        let second = self.second.flush();
+        first.and(second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    /// Test if sign extending with bit counts that do not fit in
+    /// a word returns an error instead of overflowing the shifts
+    fn sign_extend_rejects_bit_counts_out_of_range() {
+        assert_eq!(sign_extend(0x1F, 5).unwrap(), 0xFFFF);
+        assert_eq!(sign_extend(0x8000, 16).unwrap(), 0x8000);
+        assert!(sign_extend(0x1, 0).is_err());
+        assert!(sign_extend(0x1, 17).is_err());
+    }
+
+    #[test]
+    /// Test if both writers of a tee get the same bytes, and an error
+    /// on the second one is reported even though the first one worked
+    fn tee_writes_same_bytes_on_both_writers() {
+        let mut tee = Tee::new(Vec::new(), Vec::new());
+        tee.write_all(b"Hello, ").unwrap();
+        tee.write_all(b"World!\n").unwrap();
+        tee.flush().unwrap();
+        let (first, second) = tee.into_inner();
+
+        let mut full = [0u8; 1];
+        let mut failing = Tee::new(Vec::new(), &mut full[..]);
+        let result = failing.write_all(b"ab");
+        let (first_of_failing, _) = failing.into_inner();
+
+        assert_eq!(first, b"Hello, World!\n");
+        assert_eq!(first, second);
+        assert!(result.is_err());
+        assert_eq!(first_of_failing, b"ab");
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    /// Test if bytes that only start like a gzip file, like an image
+    /// with origin x1F8B, are left as they are
+    fn gunzip_in_place_keeps_bytes_that_are_not_gzip() {
+        let raw_images: [&[u8]; 3] = [
+            // Origin x1F8B and ADD R0, R0, #1
+            &[0x1F, 0x8B, 0x10, 0x21],
+            // Deflate method, but not valid compressed data
+            &[0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00],
+            &[0x1F, 0x8B],
+        ];
+
+        for raw in raw_images {
+            let mut bytes = raw.to_vec();
+            assert!(!gunzip_in_place(&mut bytes, 16));
+            assert_eq!(bytes, raw);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    /// Test if decompressed data longer than the limit is cut there, and
+    /// the truncation is reported
+    fn gunzip_in_place_reports_truncation() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&[0xAB; 32]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut fitting = compressed.clone();
+        let mut truncated = compressed;
+
+        assert!(!gunzip_in_place(&mut fitting, 32));
+        assert_eq!(fitting, [0xAB; 32]);
+        assert!(gunzip_in_place(&mut truncated, 16));
+        assert_eq!(truncated, [0xAB; 16]);
+    }
+
+    #[test]
+    /// Test if a guard cannot be created for something that is not a terminal
+    fn terminal_guard_rejects_non_terminals() {
+        let file = File::open("Cargo.toml").unwrap();
+
+        assert!(matches!(
+            TerminalGuard::for_fd(file.as_raw_fd()),
+            Err(VMError::TermiosCreation(_))
+        ));
+    }
+
+    #[test]
+    /// Test if dropping the guard restores the settings the terminal had.
+    /// It only checks something when the tests run attached to a terminal.
+    fn terminal_guard_restores_settings_on_drop() {
+        let fd = stdin().lock().as_raw_fd();
+        let Ok(before) = Termios::from_fd(fd) else {
+            return;
+        };
+
+        let guard = TerminalGuard::for_fd(fd).unwrap();
+        let during = Termios::from_fd(fd).unwrap();
+        assert_eq!(during.c_lflag & (ICANON | ECHO), 0);
+        drop(guard);
+
+        assert_eq!(Termios::from_fd(fd).unwrap(), before);
+    }
 }
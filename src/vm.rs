@@ -1,1189 +1,5944 @@
 use std::{
-    env::Args,
-    fs,
-    io::{Error, Read, Write, stdin, stdout},
+    borrow::Cow,
+    collections::{HashMap, VecDeque},
+    fs::{self, File},
+    io::{BufWriter, Error, ErrorKind, Read, Write, empty, sink, stdin, stdout},
+    mem,
     num::TryFromIntError,
+    ops::Range,
     process::exit,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
+#[cfg(feature = "gzip")]
+use crate::utils::gunzip_in_place;
 use crate::{
+    asm::{
+        SymbolTable, assemble, assemble_with_symbols, image_bytes, parse_symbol_file,
+        parse_text_image,
+    },
+    checksum::{Sha256, verify_digest},
+    decode::{DecodedInstr, Operand, decode, has_reserved_bits},
+    disasm::{disassemble_at, symbol_at},
     error::VMError,
-    hardware::{CondFlag, Memory, OpCode, Register, Registers},
+    hardware::{
+        CondFlag, DEVICE_REGION_START, KBSR_ADDR, MEMORY_MAX, Memory, MemoryModel, MemoryRegister,
+        OpCode, Register, Registers, WordMemory, branch_taken, is_device_register,
+    },
+    os::{OS_ENTRY, OS_SOURCE, OS_USER_PC},
     trap_code::*,
-    utils::{getchar, sign_extend, stdout_flush, stdout_write},
+    utils::{Tee, getchar, parse_u16, stdout_flush, stdout_write},
 };
 
 const NULL: u16 = 0x0000;
 const PC_START: u16 = 0x3000;
-const ONE_BIT_MASK: u16 = 0b1;
 const THREE_BIT_MASK: u16 = 0b111;
-const FIVE_BIT_MASK: u16 = 0b11111;
-const SIX_BIT_MASK: u16 = 0b11_1111;
 const EIGHT_BIT_MASK: u16 = 0b1111_1111;
-const NINE_BIT_MASK: u16 = 0b1_1111_1111;
-const ELEVEN_BIT_MASK: u16 = 0b111_1111_1111;
+/// Addresses the supervisor stack may use by default. It starts empty at
+/// x3000 and grows towards lower addresses.
+const SUPERVISOR_STACK: Range<u16> = 0x2E00..0x3000;
+/// Time that a branch to itself waits in headless mode before running again
+const SPIN_WAIT: Duration = Duration::from_millis(1);
+/// Maximum amount of memory locations that PUTS and PUTSP write by default.
+/// A longer string would go over the same locations again, so it is only
+/// reached when there is no x0000 anywhere in memory.
+const MAX_STRING_LEN: usize = MEMORY_MAX;
+/// Number of recovered errors whose description is kept, the ones after
+/// them are only counted
+const MAX_RECOVERED_ERRORS: usize = 1000;
+/// Number of JSR and JSRR calls that can be nested by default before the VM
+/// stops, far more than a program that is not stuck recursing needs
+const MAX_CALL_DEPTH: usize = 4096;
+/// Text that the IN trap routine writes before reading a character by default
+const IN_PROMPT: &str = "Enter a character: ";
+/// Bit of the PSR that is set while running in user mode
+const PSR_USER: u16 = 1 << 15;
+/// Addresses that programs running in user mode can access. Below them are
+/// the system space and above them the device registers.
+const USER_SPACE: Range<u16> = PC_START..DEVICE_REGION_START;
+/// Bit of the KBSR that is set when a key is ready in the KBDR
+const KBSR_READY: u16 = 1 << 15;
+/// Bit of the KBSR that enables the keyboard interrupt
+const KBSR_INTERRUPT_ENABLE: u16 = 1 << 14;
+/// Entry of the interrupt vector table with the address of the keyboard
+/// interrupt handler
+const KEYBOARD_VECTOR: u16 = 0x0180;
+/// Priority level of the keyboard interrupt
+const KEYBOARD_PRIORITY: u16 = 4;
+/// Position of the 3 bits of the PSR that hold the priority level
+const PSR_PRIORITY_SHIFT: u16 = 8;
+/// Standard regions of the memory of the LC-3, with their first and last address
+const MEMORY_REGIONS: [(u16, u16, &str); 5] = [
+    (0x0000, 0x00FF, "trap vector table"),
+    (0x0100, 0x01FF, "interrupt vector table"),
+    (0x0200, 0x2FFF, "operating system"),
+    (PC_START, 0xFDFF, "user space"),
+    (DEVICE_REGION_START, 0xFFFF, "device registers"),
+];
+/// Maximum amount of bytes of an image file that can end up in memory: the
+/// origin and a word for every memory location
+const MAX_IMAGE_BYTES: usize = 2 * (MEMORY_MAX + 1);
+/// Exit code of the binary when the program ran until it stopped, usually with HALT
+pub const EXIT_SUCCESS: i32 = 0;
+/// Exit code for an error while loading or running the program
+pub const EXIT_RUNTIME_ERROR: i32 = 1;
+/// Exit code for a wrong usage of the binary or an image path that does not exist
+pub const EXIT_BAD_ARGUMENTS: i32 = 2;
+/// Exit code for a program that stopped without HALT in strict HALT mode,
+/// like when it reached the cycle limit or the crash guard stopped it
+pub const EXIT_MISSING_HALT: i32 = 3;
+
+/// Exit code for a program that aborted with the trap at `ABORT_VECTOR`
+pub const EXIT_USER_ABORT: i32 = 4;
+
+/// Returns the exit code of the binary for the result of a run
+pub fn exit_code(result: &Result<(), VMError>) -> i32 {
+    match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(VMError::MissingHalt(_)) => EXIT_MISSING_HALT,
+        Err(VMError::UserAbort(_)) => EXIT_USER_ABORT,
+        Err(_) => EXIT_RUNTIME_ERROR,
+    }
+}
+
+/// Walks the null-terminated string that starts at `start`, reading each
+/// memory location with `read`, until the x0000 or until `max_len` words
+/// were taken without finding it.
+///
+/// ### Returns
+///
+/// A Result containing the words before the x0000 and whether it was found,
+/// or the error `read` returned.
+fn string_words(
+    start: u16,
+    max_len: usize,
+    mut read: impl FnMut(u16) -> Result<u16, VMError>,
+) -> Result<(Vec<u16>, bool), VMError> {
+    let mut words = Vec::new();
+    let mut addr = start;
+    loop {
+        let c = read(addr)?;
+        if c == NULL {
+            return Ok((words, true));
+        }
+        if words.len() == max_len {
+            return Ok((words, false));
+        }
+        words.push(c);
+        addr = addr.wrapping_add(1);
+    }
+}
+
+/// Image given in the arguments of the VM, with the options that apply to it
+struct ImageArg {
+    path: String,
+    /// Whether it goes right after the previous image instead of its own origin
+    after_previous: bool,
+    /// SHA-256 digest the file must have
+    checksum: Option<String>,
+}
+
+/// Reasons why the VM can stop running
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    /// The HALT trap routine was executed
+    HaltTrap,
+    /// The clock enable bit of the Machine Control Register was cleared
+    McrCleared,
+    /// The maximum amount of instructions to execute was reached
+    CycleLimit,
+    /// The PC reached an address with a breakpoint
+    Breakpoint(u16),
+    /// The interrupt flag was raised, usually because of a Ctrl-C
+    Interrupted,
+    /// An instruction in user space jumped to address 0x0000, which usually
+    /// means that a RET was executed without a return address in R7
+    LikelyCrash,
+    /// A JSR went deeper than the maximum call depth, which is the depth
+    /// it got to, usually because of a recursion without a base case
+    CallDepthExceeded(usize),
+    /// The program aborted with the code it had in R0
+    UserAbort(u16),
+}
+
+/// Kind of access to a device register
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceAccess {
+    Read,
+    Write,
+}
+
+/// How a run towards a target address ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// The PC reached the target, whose instruction was not executed yet
+    Reached,
+    /// The VM stopped before reaching the target
+    Halted,
+    /// The maximum amount of instructions to execute was reached first
+    CycleLimit,
+    /// The last instruction wrote output
+    Output,
+}
+
+/// Order in which the two bytes of each word of an image are stored
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endianness {
+    /// Most significant byte first, which is the LC-3 convention
+    #[default]
+    Big,
+    /// Least significant byte first
+    Little,
+}
+
+impl Endianness {
+    /// Joins two consecutive bytes of an image into a word
+    fn word(&self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Endianness::Big => u16::from_be_bytes(bytes),
+            Endianness::Little => u16::from_le_bytes(bytes),
+        }
+    }
+}
+
+/// Ways in which the TRAP instruction runs the trap routines
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrapMode {
+    /// The routines are implemented by the VM itself
+    #[default]
+    Native,
+    /// The PC jumps to the address stored in the trap vector table, as the
+    /// real LC-3 does. The routines must be installed in memory.
+    MemoryVector,
+}
+
+/// What the VM does with an instruction that has any of its unused bits set,
+/// like bits 3 and 4 of ADD and AND in register mode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReservedBitsPolicy {
+    /// The unused bits are ignored, and the instruction runs as usual
+    #[default]
+    Lenient,
+    /// The instruction runs as usual, and is recorded in the decode warnings
+    Warn,
+    /// The instruction does not run and fails with a VMError::ReservedBits
+    Reject,
+}
+
+/// Routine run by the VM for a TRAP instruction with a custom vector
+pub type TrapHandler = fn(&mut VM) -> Result<(), VMError>;
+
+/// Privilege mode the VM runs in, bit 15 of the PSR
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Privilege {
+    /// Mode of the operating system, it can use all the memory
+    #[default]
+    Supervisor,
+    /// Mode of the user programs
+    User,
+}
+
+/// Processor status, without the condition flag that lives in COND, and
+/// the stack pointer of the mode that is not running
+#[derive(Clone, Copy)]
+struct ProcessorState {
+    psr: u16,
+    saved_usp: u16,
+    saved_ssp: u16,
+}
+
+/// Number of accesses to memory, only counted when enabled
+#[derive(Clone, Copy, Default)]
+struct MemoryStats {
+    /// Reads of data, by loads, trap routines and interrupts
+    reads: u64,
+    writes: u64,
+    /// Reads of the instructions to execute
+    fetches: u64,
+}
+
+/// Changes made by a single step, holding the values that were
+/// there before the step so it can be undone
+struct StepDelta {
+    regs: Vec<(Register, u16)>,
+    mem: Vec<(u16, u16)>,
+    running: bool,
+    stop_reason: Option<StopReason>,
+    processor: ProcessorState,
+    /// Call stack before the step, only kept when the calls are tracked
+    call_stack: Option<Vec<u16>>,
+}
 
 pub struct VM {
     mem: Memory,
     regs: Registers,
     running: bool,
+    last_stop_reason: Option<StopReason>,
+    /// Changes of the latest steps, only recorded when history is enabled
+    history: Option<VecDeque<StepDelta>>,
+    history_limit: usize,
+    /// Address of the instruction, old and new flag of the latest changes
+    /// of the condition flag, only recorded when enabled
+    cond_history: Option<VecDeque<(u16, u16, u16)>>,
+    cond_history_limit: usize,
+    trap_mode: TrapMode,
+    /// Whether recoverable errors are recorded instead of stopping the VM
+    continue_on_error: bool,
+    /// Address of the instruction and description of each recovered error
+    recovered_errors: Vec<(u16, String)>,
+    /// Number of errors recovered, including the ones that were not kept
+    recovered_error_count: u64,
+    /// Whether instructions with unused bits set are recorded
+    reserved_bits: ReservedBitsPolicy,
+    /// Address and encoding of each instruction run with unused bits set
+    decode_warnings: Vec<(u16, u16)>,
+    /// Description of each part of an image left out while loading it
+    load_warnings: Vec<String>,
+    /// Whether stores into the next instructions to run are recorded
+    self_modify_check: bool,
+    /// Address of each store into the next instructions, and where it wrote
+    self_modifying_writes: Vec<(u16, u16)>,
+    /// Whether GETC sets the condition flag from the character it read
+    getc_updates_flags: bool,
+    /// Text written by the IN trap routine before reading, None to write nothing
+    in_prompt: Option<String>,
+    /// Whether the HALT banner is left out of the output
+    quiet: bool,
+    /// Whether '\n' is written as "\r\n" by the output trap routines
+    translate_newlines: bool,
+    /// Whether non-printable bytes are written as a visible `\xNN` escape
+    sanitize_output: bool,
+    /// How many times each operation was executed, only counted when enabled
+    opcode_counts: Option<HashMap<OpCode, u64>>,
+    memory_stats: Option<MemoryStats>,
+    /// Accesses to the device registers, only recorded when enabled
+    device_log: Option<Vec<(u16, DeviceAccess, u16, u16)>>,
+    /// Flag that stops the VM when raised from outside, like a signal handler
+    interrupt_flag: Option<Arc<AtomicBool>>,
+    /// Whether reading no bytes from the input means that no key arrived yet
+    /// instead of its end, so the interrupt flag is checked while waiting
+    polled_input: bool,
+    /// Address and word of the last instruction that was fetched
+    last_instruction: Option<(u16, u16)>,
+    /// Address right after the last word of the latest image loaded, which
+    /// is 65,536 when it ends at the last memory address
+    last_loaded_end: Option<usize>,
+    /// Origin and number of words of every segment loaded
+    loaded_segments: Vec<(u16, u16)>,
+    /// Whether jumping from user space to address 0x0000 stops the VM
+    crash_guard: bool,
+    /// Return address of every JSR that has not returned yet, innermost last,
+    /// only tracked when there is a maximum call depth
+    call_stack: Vec<u16>,
+    max_call_depth: Option<usize>,
+    /// Writer that gets a line for every executed instruction, if tracing
+    trace: Option<Box<dyn Write>>,
+    /// Labels of the loaded programs mapped to their addresses
+    symbols: SymbolTable,
+    /// Addresses R6 may point to while popping from the supervisor stack
+    supervisor_stack: Range<u16>,
+    processor: ProcessorState,
+    /// Whether reading the KBSR with no input left reports that no key is
+    /// ready, instead of failing
+    headless: bool,
+    /// Number of instructions executed
+    cycles: u64,
+    /// Cycles of every operation that does not take 1 cycle
+    cycle_costs: HashMap<OpCode, u64>,
+    /// Sum of the cycles of every executed instruction
+    cycle_count: u64,
+    /// Number of characters the program wrote into the output
+    bytes_written: u64,
+    /// Whether a summary of the run is written on stderr when it ends
+    stats: bool,
+    /// Whether a run that does not end with HALT or by clearing the MCR fails
+    strict_halt: bool,
+    /// Maximum amount of instructions that a single run can execute
+    cycle_limit: Option<u64>,
+    /// Maximum amount of memory locations that PUTS and PUTSP write
+    max_string_len: usize,
+    /// Whether an image with an odd number of bytes gets its last word
+    /// completed with a 0 byte, instead of failing to load
+    pad_odd_images: bool,
+    /// Routines of the trap vectors added to the VM, by their vector
+    custom_traps: HashMap<u8, TrapHandler>,
+    /// Characters that are read before the ones of the input
+    queued_input: VecDeque<u8>,
+    /// Function that gives the keys instead of the input, None when no key
+    /// is ready yet
+    key_source: Option<Box<dyn FnMut() -> Option<u8>>>,
+    /// Whether the key in the KeyboardData was pushed from outside and
+    /// is not read yet
+    key_pushed: bool,
+    /// Every character consumed and the cycle when it happened, if logging
+    input_log: Option<Vec<(u64, u8)>>,
+    /// Output and consumed input in the order they happened, only built when enabled
+    transcript: Option<String>,
+    /// Characters of a log being replayed, with the cycle they become ready at
+    replayed_input: VecDeque<(u64, u8)>,
+    input: Box<dyn Read>,
+    output: Box<dyn Write>,
 }
 
 impl VM {
     /// Creates a new instance of the VM abstraction
     pub fn new() -> Self {
-        let mut regs = Registers::new();
-        let mem = Memory::new();
+        let mut vm = Self::default();
         // Initialize the registers Cond and PC to standard values
-        regs[Register::Cond] = CondFlag::Zro.value();
-        regs[Register::PC] = PC_START;
+        vm.regs[Register::Cond] = CondFlag::Zro.value();
+        vm.regs[Register::PC] = PC_START;
+        vm
+    }
 
-        Self {
-            regs,
-            mem,
-            running: true,
-        }
+    /// Replaces the reader used by the trap routines to get the input characters.
+    /// By default the VM reads from stdin.
+    pub fn with_input(mut self, reader: impl Read + 'static) -> Self {
+        self.input = Box::new(reader);
+        self
     }
 
-    /// Loads the file into the vm memory
-    pub fn load_arguments(&mut self, args: &mut Args) -> Result<(), VMError> {
-        if args.len() < 2 {
-            println!("lc3 [image-file1] ...");
-            exit(2);
-        }
+    /// Replaces the writer used by the trap routines to write the output characters.
+    /// By default the VM writes on stdout.
+    pub fn with_output(mut self, writer: impl Write + 'static) -> Self {
+        self.output = Box::new(writer);
+        self
+    }
+
+    /// Loads the files into the vm memory and sets the PC to the origin of the
+    /// first one, so it is the first thing to run. The PC can instead be set
+    /// to any address with the `--pc ADDRESS` flag. An image given with
+    /// `--load-after FILE` is placed right after the previous one, and
+    /// `--checksum SHA256` makes the next image fail to load unless it has
+    /// that digest. The output of the program goes into the file given with
+    /// `--output FILE`, or also into the file given with `--tee FILE` while
+    /// still being written on stdout, and `--quiet` leaves the HALT banner out
+    /// of it. `--sanitize-output` escapes the non-printable bytes written on
+    /// stdout, while the files always get the raw bytes. With
+    /// `--disasm START:LEN` the images are disassembled instead of run. Every
+    /// `--poke ADDRESS=VALUE` writes a word into memory once the images are loaded,
+    /// and every `--set-reg NAME=VALUE` sets a register, like R5, PC or COND.
+    /// With `--stats` a summary of the run is written on stderr when it ends.
+    /// `--memmap` shows where the images were loaded instead of running them.
+    /// When the first argument is `disasm`, every loaded image is disassembled
+    /// instead of run.
+    /// `--max-cycles N` stops the program after N instructions, and with
+    /// `--strict-halt` it fails unless it ends with HALT. `--strict-halt` also
+    /// enables the crash guard, so a program that jumps to x0000 stops there
+    /// and fails instead of running the trap vector table. `--selftest` runs an
+    /// embedded hello world program instead and exits telling if it worked.
+    pub fn load_arguments(
+        &mut self,
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<(), VMError> {
         // We skip the first element of the args since it is not an image
-        args.next();
-        for path in args {
-            if self.read_image(path.clone()).is_err() {
-                println!("failed to load image: {path}");
-                exit(1);
+        let mut args = args.into_iter().skip(1).peekable();
+        let disasm_all = args.next_if(|arg| arg == "disasm").is_some();
+        let mut images = Vec::new();
+        let mut pc_override = None;
+        let mut checksum = None;
+        let mut disasm = None;
+        let mut memmap = false;
+        let mut pokes = Vec::new();
+        let mut reg_values = Vec::new();
+        let mut sanitize = false;
+        let mut raw_output = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--pc" => match args.next().map(|addr| parse_u16(&addr)) {
+                    Some(Ok(addr)) => pc_override = Some(addr),
+                    _ => Self::exit_with_usage(),
+                },
+                "--output" => match args.next() {
+                    Some(path) => {
+                        self.open_output(path);
+                        raw_output = true;
+                    }
+                    None => Self::exit_with_usage(),
+                },
+                "--sanitize-output" => sanitize = true,
+                "--selftest" => Self::exit_with_selftest(),
+                "--tee" => match args.next() {
+                    Some(path) => {
+                        self.open_tee(path);
+                        raw_output = true;
+                    }
+                    None => Self::exit_with_usage(),
+                },
+                "--quiet" => self.quiet = true,
+                "--stats" => self.stats = true,
+                // A program that jumps to x0000 would never reach its HALT, so
+                // it is stopped as soon as it happens
+                "--strict-halt" => {
+                    self.strict_halt = true;
+                    self.crash_guard = true;
+                }
+                "--max-cycles" => match args.next().map(|n| n.parse::<u64>()) {
+                    Some(Ok(limit)) => self.cycle_limit = Some(limit),
+                    _ => Self::exit_with_usage(),
+                },
+                "--set-reg" => match args.next().as_deref().and_then(|p| p.split_once('=')) {
+                    Some((name, value)) => match (name.parse::<Register>(), parse_u16(value)) {
+                        (Ok(reg), Ok(value)) => reg_values.push((reg, value)),
+                        _ => Self::exit_with_usage(),
+                    },
+                    None => Self::exit_with_usage(),
+                },
+                "--poke" => match args.next().as_deref().and_then(|p| p.split_once('=')) {
+                    Some((addr, value)) => match (parse_u16(addr), parse_u16(value)) {
+                        (Ok(addr), Ok(value)) => pokes.push((addr, value)),
+                        _ => Self::exit_with_usage(),
+                    },
+                    None => Self::exit_with_usage(),
+                },
+                "--disasm" => match args.next().as_deref().and_then(|r| r.split_once(':')) {
+                    Some((start, len)) => match (parse_u16(start), parse_u16(len)) {
+                        (Ok(start), Ok(len)) => disasm = Some((start, len)),
+                        _ => Self::exit_with_usage(),
+                    },
+                    None => Self::exit_with_usage(),
+                },
+                "--memmap" => memmap = true,
+                "--checksum" => match args.next() {
+                    Some(digest) => checksum = Some(digest),
+                    None => Self::exit_with_usage(),
+                },
+                "--load-after" => match args.next() {
+                    Some(path) if !images.is_empty() => images.push(ImageArg {
+                        path,
+                        after_previous: true,
+                        checksum: checksum.take(),
+                    }),
+                    _ => Self::exit_with_usage(),
+                },
+                _ => images.push(ImageArg {
+                    path: arg,
+                    after_previous: false,
+                    checksum: checksum.take(),
+                }),
             }
         }
-        Ok(())
-    }
+        if images.is_empty() || checksum.is_some() {
+            Self::exit_with_usage();
+        }
+        // A file, or what is shown next to it, gets exactly what the program wrote
+        self.sanitize_output = sanitize && !raw_output;
 
-    /// Reads bytes from file and send them to get into memory
-    fn read_image(&mut self, path: String) -> Result<(), VMError> {
-        let mut f =
-            fs::read(path.clone()).map_err(|e: Error| VMError::OpenFile(path, e.to_string()))?;
-        self.read_image_file(&mut f)?;
+        let mut first_origin = None;
+        for image in images {
+            let loaded = self.load_image_arg(image);
+            for warning in self.load_warnings.drain(..) {
+                eprintln!("warning: {}", warning);
+            }
+            match loaded {
+                Ok(origin) => {
+                    first_origin.get_or_insert(origin);
+                }
+                Err(e) => {
+                    eprintln!("failed to load image: {:?}", e);
+                    let code = match e {
+                        VMError::ImageNotFound(..) => EXIT_BAD_ARGUMENTS,
+                        _ => EXIT_RUNTIME_ERROR,
+                    };
+                    exit(code);
+                }
+            }
+        }
+        for (addr, value) in pokes {
+            self.load_words(addr, &[value])?;
+        }
+        if let Some(pc) = pc_override.or(first_origin) {
+            self.regs[Register::PC] = pc;
+        }
+        for (reg, value) in reg_values {
+            self.regs[reg] = value;
+        }
+        if let Some((start, len)) = disasm {
+            print!("{}", self.disassemble_range(start, len));
+            exit(0);
+        }
+        if memmap {
+            print!("{}", self.memory_map());
+            exit(0);
+        }
+        if disasm_all {
+            for (origin, len) in self.loaded_segments.clone() {
+                print!("{}", self.disassemble_range(origin, len));
+            }
+            exit(0);
+        }
         Ok(())
     }
 
-    /// Writes a file encoded in bytes into memory.
-    ///
-    /// ### Arguments
-    ///
-    /// - `file_bytes`: A vector of u8 which represent each byte of the file with the file that will be written in memory.
-    fn read_image_file(&mut self, file_bytes: &mut Vec<u8>) -> Result<(), VMError> {
-        // Get the first 2 bytes and join them in reverse order to get the origin
-        let byte0 = file_bytes.remove(0);
-        let byte1 = file_bytes.remove(0);
-        let origin = u16::from_be_bytes([byte0, byte1]);
-
-        // Get chunks of 2 bytes and join them in reverse order so we get the data.
-        // This data starts to get written from memory address = origin
-        let mut mem_addr = origin;
-        for chunk in file_bytes.chunks(2) {
-            let mut chunk_iter = chunk.iter();
-            let byte0 = *chunk_iter
-                .next()
-                .ok_or(VMError::NoMoreBytes(String::from("No byte0 on chunk")))?;
-            let byte1 = *chunk_iter
-                .next()
-                .ok_or(VMError::NoMoreBytes(String::from("No byte1 in chunk")))?;
-            let data = u16::from_be_bytes([byte0, byte1]);
-
-            self.mem.write(mem_addr, data)?;
-            mem_addr = mem_addr.wrapping_add(1);
+    /// Makes the program write its output into the file in `path`, exiting
+    /// if it cannot be created.
+    fn open_output(&mut self, path: String) {
+        match File::create(&path) {
+            Ok(file) => self.output = Box::new(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!(
+                    "failed to open output file: {:?}",
+                    VMError::OpenFile(path, e.to_string())
+                );
+                exit(EXIT_BAD_ARGUMENTS);
+            }
         }
-        Ok(())
     }
 
-    pub fn run(&mut self) -> Result<(), VMError> {
-        while self.running {
-            let instr_addr = self.regs[Register::PC];
-            self.regs[Register::PC] = self.regs[Register::PC].wrapping_add(1);
-            let instr = self.mem.read(instr_addr)?;
-            let op_code = OpCode::try_from(instr >> 12)?;
-            match op_code {
-                OpCode::Br => self.branch(instr)?,
-                OpCode::Add => self.add(instr)?,
-                OpCode::Ld => self.load(instr)?,
-                OpCode::St => self.store(instr)?,
-                OpCode::Jsr => self.jump_register(instr)?,
-                OpCode::And => self.and(instr)?,
-                OpCode::Ldr => self.load_register(instr)?,
-                OpCode::Str => self.store_register(instr)?,
-                OpCode::Not => self.not(instr)?,
-                OpCode::Ldi => self.load_indirect(instr)?,
-                OpCode::Sti => self.store_indirect(instr)?,
-                OpCode::Jmp => self.jump(instr)?,
-                OpCode::Lea => self.load_effective_address(instr)?,
-                OpCode::Trap => self.trap(instr)?,
+    /// Makes the output go both to stdout and to the file at `path`, exiting
+    /// with the usage error code if it cannot be created
+    fn open_tee(&mut self, path: String) {
+        match File::create(&path) {
+            Ok(file) => self.output = Box::new(Tee::new(stdout(), BufWriter::new(file))),
+            Err(e) => {
+                eprintln!(
+                    "failed to open tee file: {:?}",
+                    VMError::OpenFile(path, e.to_string())
+                );
+                exit(EXIT_BAD_ARGUMENTS);
             }
         }
-        Ok(())
     }
 
-    /// Updates the register COND where we have the condition flag
-    pub fn update_flags(&mut self, r: Register) {
-        if self.regs[r] == 0 {
-            self.regs[Register::Cond] = CondFlag::Zro.value();
-        } else if self.regs[r] >> 15 == 1 {
-            self.regs[Register::Cond] = CondFlag::Neg.value();
-        } else {
-            self.regs[Register::Cond] = CondFlag::Pos.value();
+    /// Runs the self test, shows whether it passed and exits with the
+    /// success code only if it did
+    fn exit_with_selftest() -> ! {
+        match crate::selftest() {
+            Ok(()) => {
+                println!("selftest: pass");
+                exit(EXIT_SUCCESS);
+            }
+            Err(reason) => {
+                println!("selftest: FAIL, {}", reason);
+                exit(EXIT_RUNTIME_ERROR);
+            }
         }
     }
 
-    /// Adds two values and stores the result in a register
-    ///
-    /// This instruction can be executed in two different ways:
+    /// Shows how the binary has to be used and exits
+    fn exit_with_usage() -> ! {
+        println!(
+            "lc3 [--pc ADDRESS] [--output FILE] [--tee FILE] [--sanitize-output] [--quiet] [--stats] [--max-cycles N] [--strict-halt] [--disasm START:LEN] [--memmap] [--poke ADDRESS=VALUE] [--set-reg NAME=VALUE] [--checksum SHA256] [image-file1] [--load-after image-file2] ..."
+        );
+        println!("lc3 disasm [image-file1] [--load-after image-file2] ...");
+        println!("lc3 --selftest");
+        println!();
+        println!(
+            "--strict-halt fails unless the program ends with HALT, and stops it when it jumps to x0000"
+        );
+        exit(EXIT_BAD_ARGUMENTS);
+    }
+
+    /// Reads bytes from file and send them to get into memory.
     ///
-    /// - Register mode: Adds two values taken from the registers storage.
-    /// - Immediate mode: Adds a value taken from the registers storage with one taken from the instruction itself. The second operand of the addition is taken from a range of bits of the executed instruction.
+    /// If the file does not exist a VMError::ImageNotFound is returned, any other
+    /// failure when opening it is reported as a VMError::OpenFile. Both of them
+    /// carry the path and the underlying OS error.
     ///
-    /// ### Arguments
+    /// ### Returns
     ///
-    /// - `instr`: An u16 that has the encoding of the whole instruction to execute.
-    pub fn add(&mut self, instr: u16) -> Result<(), VMError> {
-        // Destination register
-        let dr: Register = Register::from_u16((instr >> 9) & THREE_BIT_MASK)?;
-        // First operand
-        let sr1: Register = Register::from_u16((instr >> 6) & THREE_BIT_MASK)?;
-        // Check the bit 5 to see if we are in immediate mode
-        let imm_flag = (instr >> 5) & ONE_BIT_MASK;
-
-        if imm_flag == 1 {
-            // Get the 5 bits of the imm5 section (first 5 bits) and sign extend them
-            let mut imm5 = instr & FIVE_BIT_MASK;
-            imm5 = sign_extend(imm5, 5)?;
-            self.regs[dr] = self.regs[sr1].wrapping_add(imm5);
+    /// A Result containing the origin of the image, which is the address
+    /// where it started to get written.
+    pub fn read_image(&mut self, path: String) -> Result<u16, VMError> {
+        self.load_image_arg(ImageArg {
+            path,
+            after_previous: false,
+            checksum: None,
+        })
+    }
+
+    /// Reads the file of an image given in the arguments and writes it into
+    /// memory, following the options given for it.
+    fn load_image_arg(&mut self, image: ImageArg) -> Result<u16, VMError> {
+        let mut f = self.read_file(image.path, image.checksum.as_deref())?;
+        if image.after_previous {
+            self.read_image_file_after(&mut f)
         } else {
-            // Since the immediate flag was off, we only need the SR2 section (first 3 bits).
-            // This section contains the register containing the value to add.
-            let sr2 = Register::from_u16(instr & THREE_BIT_MASK)?;
-            self.regs[dr] = self.regs[sr1].wrapping_add(self.regs[sr2]);
+            self.read_image_file(&mut f)
         }
+    }
 
-        self.update_flags(dr);
-        Ok(())
+    /// Reads the bytes of an image file that fit in memory and checks the
+    /// whole file has the expected SHA-256 digest. When no digest is given,
+    /// it is taken from the `FILE.sha256` file next to the image if there is
+    /// one. A file larger than the memory is cut with a warning.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the bytes of the file, or a VMError::ChecksumMismatch
+    /// if its digest is not the expected one.
+    fn read_file(&mut self, path: String, checksum: Option<&str>) -> Result<Vec<u8>, VMError> {
+        let open_error = |e: Error| match e.kind() {
+            ErrorKind::NotFound => VMError::ImageNotFound(path.clone(), e.to_string()),
+            _ => VMError::OpenFile(path.clone(), e.to_string()),
+        };
+        let expected = match checksum {
+            Some(expected) => Some(expected.to_string()),
+            None => fs::read_to_string(format!("{}.sha256", path)).ok(),
+        };
+        // Only the bytes that can fit in memory are kept, plus one more to
+        // know if the file has more of them
+        let limit = u64::try_from(MAX_IMAGE_BYTES.saturating_add(1)).unwrap_or(u64::MAX);
+        let mut file = File::open(&path).map_err(open_error)?;
+        let mut bytes = Vec::new();
+        (&mut file)
+            .take(limit)
+            .read_to_end(&mut bytes)
+            .map_err(open_error)?;
+        let mut hasher = Sha256::new();
+        hasher.update(&bytes);
+        if bytes.len() > MAX_IMAGE_BYTES {
+            self.load_warnings.push(format!(
+                "image [{}] is larger than the memory, only its first {} bytes are read",
+                path, MAX_IMAGE_BYTES
+            ));
+            bytes.truncate(MAX_IMAGE_BYTES);
+            // The digest is the one of the whole file, so the rest of it is
+            // hashed without being kept
+            if expected.is_some() {
+                let mut chunk = [0u8; 8192];
+                loop {
+                    match file.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(read) => hasher.update(chunk.get(..read).unwrap_or_default()),
+                        Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(open_error(e)),
+                    }
+                }
+            }
+        }
+        if let Some(expected) = expected {
+            verify_digest(&hasher.finish(), &expected)?;
+        }
+        Ok(bytes)
     }
 
-    /// Does the bitwise 'NOT' for a value stored in a register.
+    /// Writes a file encoded in bytes into memory. The words of the file
+    /// are expected to be big-endian.
     ///
     /// ### Arguments
     ///
-    /// - `instr`: An u16 that has the encoding of the whole instruction to execute.
-    pub fn not(&mut self, instr: u16) -> Result<(), VMError> {
-        let dr = Register::from_u16((instr >> 9) & THREE_BIT_MASK)?;
-        let sr = Register::from_u16((instr >> 6) & THREE_BIT_MASK)?;
-
-        self.regs[dr] = !self.regs[sr];
-        self.update_flags(dr);
-        Ok(())
+    /// - `file_bytes`: A vector of u8 which represent each byte of the file with the file that will be written in memory.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the origin of the image.
+    pub fn read_image_file(&mut self, file_bytes: &mut Vec<u8>) -> Result<u16, VMError> {
+        self.read_image_file_endian(file_bytes, Endianness::Big)
     }
 
-    /// Does the bitwise 'AND' between two values and stores the result
-    /// in a register.
+    /// Writes a file encoded in bytes into memory right after the last word of
+    /// the previous image, ignoring the origin the file has. If no image was
+    /// loaded before, its own origin is used.
     ///
-    /// This instruction can be executed in two different ways:
-    /// - register mode: Bitwise and with two elements that are in a register each.
-    /// - immediate mode: Uses the value of a register with the one that is embedded
-    ///   in the instruction itself.
+    /// Only PC-relative references keep working once the image is moved,
+    /// addresses stored with `.FILL` still point to the original place.
     ///
     /// ### Arguments
     ///
-    /// - `instr`: An u16 that has the encoding of the whole instruction to execute.
-    /// - `regs`: A Registers struct that handles each register.
-    pub fn and(&mut self, instr: u16) -> Result<(), VMError> {
-        // Destination register
-        let dr = Register::from_u16((instr >> 9) & THREE_BIT_MASK)?;
-        // SR1 section
-        let sr1 = Register::from_u16((instr >> 6) & THREE_BIT_MASK)?;
-        // Imm flag
-        let imm_flag = (instr >> 5) & ONE_BIT_MASK;
-
-        if imm_flag == 1 {
-            // Get the imm5 section, then do the bitwise and with the content on R1.
-            let mut imm5 = instr & FIVE_BIT_MASK;
-            imm5 = sign_extend(imm5, 5)?;
-            self.regs[dr] = self.regs[sr1] & imm5;
-        } else {
-            // Get the SR2 section, then do the bitwise and with the content on R1.
-            let sr2 = Register::from_u16(instr & THREE_BIT_MASK)?;
-            self.regs[dr] = self.regs[sr1] & self.regs[sr2];
+    /// - `file_bytes`: A vector of u8 which represent each byte of the file with the file that will be written in memory.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the address where the image was written, or a
+    /// VMError::InvalidIndex if the previous image ended at the last memory
+    /// address, so there is no room after it.
+    pub fn read_image_file_after(&mut self, file_bytes: &mut Vec<u8>) -> Result<u16, VMError> {
+        // The origin can only be replaced once the image is decompressed
+        #[cfg(feature = "gzip")]
+        self.gunzip_image(file_bytes);
+        if let Some(end) = self.last_loaded_end {
+            let end = u16::try_from(end).map_err(|_| VMError::InvalidIndex(end))?;
+            if let Some(header) = file_bytes.get_mut(..2) {
+                header.copy_from_slice(&end.to_be_bytes());
+            }
         }
-
-        self.update_flags(dr);
-        Ok(())
+        self.read_image_file(file_bytes)
     }
 
-    /// Changes the PC register value depending on the value of the Cond register.
-    /// If the Cond register is set to the same value taken from the bits 9 to 11 of the instruction,
-    /// this function adds the PC register to the value taken from the first 9 bits of the instruction.
+    /// Writes a file encoded in bytes into memory, joining the bytes of each
+    /// word (the origin included) in the given order. With the `gzip` feature,
+    /// a file starting with a gzip header (x1F x8B x08) is decompressed
+    /// first, unless it is not valid gzip data. The words that would go past
+    /// the last memory address are left out with a warning.
     ///
     /// ### Arguments
     ///
+    /// - `file_bytes`: A vector of u8 which represent each byte of the file with the file that will be written in memory.
+    /// - `endianness`: The order of the two bytes of every word in the file.
+    ///
+    /// ### Returns
     ///
-    /// - `instr`: An u16 that has the encoding of the whole instruction to execute.
-    /// - `regs`: A Registers struct that handles each register.
-    pub fn branch(&mut self, instr: u16) -> Result<(), VMError> {
-        // Get the PCOffset9 section
-        let mut pc_offset = instr & NINE_BIT_MASK;
-        pc_offset = sign_extend(pc_offset, 9)?;
-        // Get the Condition Flag and check if it is the same
-        // as the one selected on the instruction
-        let cond_flag = (instr >> 9) & THREE_BIT_MASK;
-        let coincides = cond_flag & self.regs[Register::Cond];
-        if coincides != 0 {
-            self.regs[Register::PC] = self.regs[Register::PC].wrapping_add(pc_offset);
+    /// A Result containing the origin of the image. When the data after the
+    /// origin has an odd number of bytes, nothing is written and the result is
+    /// a VMError::TruncatedImage with the offset of the last byte, unless the
+    /// VM pads it with a 0 byte to complete the word.
+    pub fn read_image_file_endian(
+        &mut self,
+        file_bytes: &mut Vec<u8>,
+        endianness: Endianness,
+    ) -> Result<u16, VMError> {
+        #[cfg(feature = "gzip")]
+        self.gunzip_image(file_bytes);
+        // Get the first 2 bytes and join them to get the origin
+        let (Some(&byte0), Some(&byte1)) = (file_bytes.first(), file_bytes.get(1)) else {
+            return Err(VMError::NoMoreBytes(String::from("No origin in image")));
+        };
+        file_bytes.drain(..2);
+        let origin = endianness.word([byte0, byte1]);
+        if !file_bytes.len().is_multiple_of(2) {
+            if !self.pad_odd_images {
+                // The offset in the file counts the 2 bytes of the origin
+                return Err(VMError::TruncatedImage(file_bytes.len().saturating_add(1)));
+            }
+            file_bytes.push(0);
         }
-        Ok(())
+
+        // The words after the last memory address are left out, instead of
+        // going back to address 0
+        let room = self.mem.len().saturating_sub(usize::from(origin));
+        let words = file_bytes.len() / 2;
+        if words > room {
+            self.load_warnings.push(format!(
+                "the image at [x{:04X}] has {} words after the end of the memory, they are not loaded",
+                origin,
+                words.saturating_sub(room)
+            ));
+        }
+
+        // Get chunks of 2 bytes and join them so we get the data.
+        // This data starts to get written from memory address = origin
+        let mut mem_addr = origin;
+        for chunk in file_bytes.chunks_exact(2).take(room) {
+            let &[byte0, byte1] = chunk else {
+                continue;
+            };
+            let data = endianness.word([byte0, byte1]);
+
+            self.mem.write(mem_addr, data)?;
+            mem_addr = mem_addr.wrapping_add(1);
+        }
+        self.last_loaded_end = Some(usize::from(origin).saturating_add(words.min(room)));
+        self.loaded_segments
+            .push((origin, mem_addr.wrapping_sub(origin)));
+        Ok(origin)
     }
 
-    /// Changes the PC with the value of a register specified in the instruction
-    /// itself
-    pub fn jump(&mut self, instr: u16) -> Result<(), VMError> {
-        // Get the BaseR section
-        let baser_r = Register::from_u16((instr >> 6) & THREE_BIT_MASK)?;
-        self.regs[Register::PC] = self.regs[baser_r];
-        Ok(())
+    /// Decompresses an image compressed with gzip, with a warning if it
+    /// is larger than the memory
+    #[cfg(feature = "gzip")]
+    fn gunzip_image(&mut self, file_bytes: &mut Vec<u8>) {
+        if gunzip_in_place(file_bytes, MAX_IMAGE_BYTES) {
+            self.load_warnings.push(format!(
+                "the decompressed image is larger than the memory, only its first {} bytes are read",
+                MAX_IMAGE_BYTES
+            ));
+        }
     }
 
-    /// Changes the PC register with the value on a register that is specified
-    /// on the R1 section of the encoding or in the same instruction encoding.
-    /// This depends on the long flag situated in the bit 11, which is taken by shifting right
-    /// the instruction 11 times and checking if it is a 1 or a 0. The long flag
-    /// being 1 means it can be a value of eleven bits. If the flag is a 0,
-    /// the value is taken from a register.
-    pub fn jump_register(&mut self, instr: u16) -> Result<(), VMError> {
-        let long_flag = (instr >> 11) & 1;
-        self.regs[Register::R7] = self.regs[Register::PC];
-        if long_flag == 1 {
-            let mut long_pc_offset = instr & ELEVEN_BIT_MASK;
-            long_pc_offset = sign_extend(long_pc_offset, 11)?;
-            self.regs[Register::PC] = self.regs[Register::PC].wrapping_add(long_pc_offset);
-        } else {
-            let r1 = Register::from_u16((instr >> 6) & THREE_BIT_MASK)?;
-            self.regs[Register::PC] = self.regs[r1];
+    /// Assembles the source code and writes every segment of the resulting
+    /// program into memory, starting from its `.ORIG` address. The PC is set
+    /// to the origin of the first segment so the program is the next thing to run,
+    /// and its labels are added to the symbols of the VM.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether the operation failed or not. Errors in the
+    /// source code are reported as a VMError::Assembly.
+    pub fn assemble_and_load(&mut self, src: &str) -> Result<(), VMError> {
+        let (segments, symbols) = assemble_with_symbols(src)?;
+        self.symbols.extend(symbols);
+        for (origin, words) in &segments {
+            self.load_segment(*origin, words)?;
+        }
+        if let Some((origin, _)) = segments.first() {
+            self.regs[Register::PC] = *origin;
         }
         Ok(())
     }
 
-    /// Loads a value into a register using indirect addressing.
-    /// First, it computes a memory address by adding a PC offset to the current PC,
-    /// and reads the value at that memory address. This value is treated as the final memory address.
-    /// Then, it loads the value stored at that final memory address into the destination register.
+    /// Writes an image encoded in big-endian bytes into memory and sets the PC
+    /// to its origin, so the program is the next thing to run.
     ///
-    /// ### Arguments
+    /// ### Returns
     ///
-    /// - `instr`: An u16 that has the encoding of the whole instruction to execute.
-    /// - `regs`: A Registers struct that handles each register.
-    /// - `memory`: A Memory struct that handles reads and writes on the vm memory.
-    pub fn load_indirect(&mut self, instr: u16) -> Result<(), VMError> {
-        // Destination register
-        let dr = Register::from_u16((instr >> 9) & THREE_BIT_MASK)?;
-        // PCoffset 9 section
-        let mut pc_offset = instr & NINE_BIT_MASK;
-        pc_offset = sign_extend(pc_offset, 9)?;
-        // Add the number that was on PCoffset 9 section to PC to get the
-        // memory location we need to look at for the final address
-        let address_of_final_address = self.regs[Register::PC].wrapping_add(pc_offset);
-        let final_address = self.mem.read(address_of_final_address)?;
-        self.regs[dr] = self.mem.read(final_address)?;
-        self.update_flags(dr);
-        Ok(())
+    /// A Result containing the origin of the image.
+    pub fn load_image(&mut self, bytes: &[u8]) -> Result<u16, VMError> {
+        let origin = self.read_image_file(&mut bytes.to_vec())?;
+        self.regs[Register::PC] = origin;
+        Ok(origin)
     }
 
-    /// Loads a value from a location in memory and stores the loaded value into a register
-    pub fn load(&mut self, instr: u16) -> Result<(), VMError> {
-        // Destination register
-        let dr = Register::from_u16((instr >> 9) & THREE_BIT_MASK)?;
-        // PCoffset 9 section
-        let mut pc_offset = instr & NINE_BIT_MASK;
-        pc_offset = sign_extend(pc_offset, 9)?;
-        // Calculate the memory address to read
-        let address = self.regs[Register::PC].wrapping_add(pc_offset);
-        self.regs[dr] = self.mem.read(address)?;
-        self.update_flags(dr);
+    /// Loads a text image, with one hexadecimal word per line after its
+    /// `.ORIG` line, and sets the PC to its origin.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether the operation failed or not. Malformed
+    /// lines are reported as a VMError::Assembly.
+    pub fn load_text_image(&mut self, src: &str) -> Result<(), VMError> {
+        let (origin, words) = parse_text_image(src)?;
+        self.load_segment(origin, &words)?;
+        self.regs[Register::PC] = origin;
         Ok(())
     }
 
-    /// Loads a value that is located in a memory address.  This value is formed by
-    /// adding the value on a register and the one in the offset6 section. Then,
-    /// memory is read at this value and that is set into a desired register.
-    pub fn load_register(&mut self, instr: u16) -> Result<(), VMError> {
-        // Destination Register
-        let dr = Register::from_u16((instr >> 9) & THREE_BIT_MASK)?;
-        // BaseR section
-        let r1 = Register::from_u16((instr >> 6) & THREE_BIT_MASK)?;
-        // Offset6 section
-        let mut offset6 = instr & SIX_BIT_MASK;
-        offset6 = sign_extend(offset6, 6)?;
-        // Calculate the memory address to read
-        let address = self.regs[r1].wrapping_add(offset6);
-        self.regs[dr] = self.mem.read(address)?;
-        self.update_flags(dr);
+    /// Writes consecutive words into memory, starting from `origin`.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether the operation failed or not. Nothing is
+    /// written if the words go past the last memory address.
+    pub fn load_words(&mut self, origin: u16, words: &[u16]) -> Result<(), VMError> {
+        self.check_range(origin, words.len())?;
+        for (mem_addr, word) in (origin..=u16::MAX).zip(words) {
+            self.mem.write(mem_addr, *word)?;
+        }
         Ok(())
     }
 
-    /// Loads a value into a register. This value is crated by adding the value of the PC and the
-    /// one in the PCoffset9 section, which is formed by the 9 rightmost bits in the intruction encoding.
-    pub fn load_effective_address(&mut self, instr: u16) -> Result<(), VMError> {
-        // Destination Register
-        let dr = Register::from_u16((instr >> 9) & THREE_BIT_MASK)?;
-        // PCoffset9 section
-        let mut pc_offset = instr & NINE_BIT_MASK;
-        pc_offset = sign_extend(pc_offset, 9)?;
-        // Set the new value for the destination register
-        self.regs[dr] = self.regs[Register::PC].wrapping_add(pc_offset);
-        self.update_flags(dr);
+    /// Writes the words of a loaded program into memory, like `load_words`,
+    /// and records where they were placed
+    fn load_segment(&mut self, origin: u16, words: &[u16]) -> Result<(), VMError> {
+        self.load_words(origin, words)?;
+        let len = u16::try_from(words.len())
+            .map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))?;
+        self.loaded_segments.push((origin, len));
         Ok(())
     }
 
-    /// Reads a value from a register and stores it into memory. This address
-    /// is created from the addition of the PC and the PCoffset9 section
-    pub fn store(&mut self, instr: u16) -> Result<(), VMError> {
-        // Source Register
-        let sr = Register::from_u16((instr >> 9) & THREE_BIT_MASK)?;
-        // PCoffset9 section
-        let mut pc_offset = instr & NINE_BIT_MASK;
-        pc_offset = sign_extend(pc_offset, 9)?;
-        // Calculate the address
-        let address = self.regs[Register::PC].wrapping_add(pc_offset);
-        let new_val = self.regs[sr];
-        self.mem.write(address, new_val)
+    /// Returns a summary of where things are in memory: the standard regions
+    /// of the LC-3, telling which ones have any value that is not 0, and the
+    /// segments that were loaded with the region where each one starts, like:
+    ///
+    /// ```text
+    /// Regions:
+    /// x0000-x00FF  trap vector table      empty
+    /// ...
+    /// Loaded segments:
+    /// x3000-x3003  4 words  user space
+    /// ```
+    pub fn memory_map(&self) -> String {
+        let region_name = |addr: u16| {
+            MEMORY_REGIONS
+                .iter()
+                .find(|(start, end, _)| (*start..=*end).contains(&addr))
+                .map(|(_, _, name)| *name)
+                .unwrap_or_default()
+        };
+        let mut map = String::from("Regions:\n");
+        for (start, end, name) in MEMORY_REGIONS {
+            let populated = (start..=end).any(|addr| self.mem.peek(addr) != 0);
+            map.push_str(&format!(
+                "x{:04X}-x{:04X}  {:<22} {}\n",
+                start,
+                end,
+                name,
+                if populated { "populated" } else { "empty" }
+            ));
+        }
+        map.push_str("Loaded segments:\n");
+        for (origin, len) in &self.loaded_segments {
+            let last = origin.wrapping_add(len.saturating_sub(1));
+            map.push_str(&format!(
+                "x{:04X}-x{:04X}  {} {}  {}\n",
+                origin,
+                last,
+                len,
+                if *len == 1 { "word" } else { "words" },
+                region_name(*origin)
+            ));
+        }
+        map
     }
 
-    /// Reads a value from a register and stores it into memory. This address
-    /// is taken indirectly from the instruction. By adding the PC and the PCoffset9 section
-    /// (the rightmost 9 bits of the instruction enconding) we get the first memory
-    /// address, then if we read it we get the final address. That
-    /// final address is the one that is going to get written.
-    pub fn store_indirect(&mut self, instr: u16) -> Result<(), VMError> {
-        // Source Register
-        let sr = Register::from_u16((instr >> 9) & THREE_BIT_MASK)?;
-        // PCoffset9 section
-        let mut pc_offset = instr & NINE_BIT_MASK;
-        pc_offset = sign_extend(pc_offset, 9)?;
-        // Get the first address
-        let first_address = self.regs[Register::PC].wrapping_add(pc_offset);
-        // Read the first address, get the second one and write on it
-        let final_address = self.mem.read(first_address)?;
-        let new_val = self.regs[sr];
-        self.mem.write(final_address, new_val)
+    /// Returns the origin and the number of words of every image and
+    /// assembled segment loaded, in the order they were loaded
+    pub fn loaded_segments(&self) -> &[(u16, u16)] {
+        &self.loaded_segments
     }
 
-    /// Reads a value from a register and stores it into memory. By adding
-    /// the value on the register specified in the BaseR section and the value in the
-    /// offset6 section we get the memory address. That address is the one that
-    /// is going to get written.
+    /// Encodes the `len` memory locations that start at `origin` as an image,
+    /// with the origin first and every word in big-endian, the format read
+    /// by `load_image`. The memory is read without side effects.
     ///
-    /// The BaseR and the offset6 sections can be found on the instruction enconding.
-    /// The first holds a register to use, the second one holds and embedded value.
-    pub fn store_register(&mut self, instr: u16) -> Result<(), VMError> {
-        // Source Register
-        let sr = Register::from_u16((instr >> 9) & THREE_BIT_MASK)?;
-        // BaseR section
-        let r1 = Register::from_u16((instr >> 6) & THREE_BIT_MASK)?;
-        // Offset 6 section
-        let mut offset = instr & SIX_BIT_MASK;
-        offset = sign_extend(offset, 6)?;
-        // Calculate the address
-        let address = self.regs[r1].wrapping_add(offset);
-        let new_val = self.regs[sr];
-        self.mem.write(address, new_val)
+    /// ### Returns
+    ///
+    /// A Result containing the bytes of the image, or a VMError if the range
+    /// goes past the last memory address.
+    pub fn export_image(&self, origin: u16, len: u16) -> Result<Vec<u8>, VMError> {
+        self.check_range(origin, usize::from(len))?;
+        let words: Vec<u16> = (origin..=u16::MAX)
+            .take(usize::from(len))
+            .map(|addr| self.mem.peek(addr))
+            .collect();
+        Ok(image_bytes(origin, &words))
     }
 
-    /// Executes the desired trap routine. In the instruction encoding the
-    /// trapvect8 section can be found in the 8 rightmost bits, and from there
-    /// we can get the trap code that will tell us which of the trap routines
-    /// we have to execute.
-    pub fn trap(&mut self, instr: u16) -> Result<(), VMError> {
-        self.regs[Register::R7] = self.regs[Register::PC];
-        let trap_code = TrapCode::try_from(instr & EIGHT_BIT_MASK)?;
-        let mut std_in = stdin().lock();
-        let mut std_out = stdout().lock();
-        match trap_code {
-            TrapCode::GetC => self.get_c(&mut std_in)?,
-            TrapCode::Out => self.out(&mut std_out)?,
-            TrapCode::Puts => self.puts(&mut std_out)?,
-            TrapCode::In => self.trap_in(&mut std_out, &mut std_in)?,
-            TrapCode::PutsP => self.puts_p(&mut std_out)?,
-            TrapCode::Halt => self.halt(&mut std_out)?,
+    /// Sets to 0 the `len` memory locations that start at `start`.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether the operation failed or not. Nothing is
+    /// cleared if the range goes past the last memory address.
+    pub fn clear_memory(&mut self, start: u16, len: u16) -> Result<(), VMError> {
+        self.check_range(start, usize::from(len))?;
+        for mem_addr in (start..=u16::MAX).take(usize::from(len)) {
+            self.mem.write(mem_addr, 0)?;
         }
         Ok(())
     }
 
-    /// Reads one character from the stdin.
-    pub fn get_c(&mut self, reader: &mut impl Read) -> Result<(), VMError> {
-        let buffer = getchar(reader)?;
-        let char: u16 = buffer[0].into();
-        self.regs[Register::R0] = char;
-        self.update_flags(Register::R0);
+    /// Checks that the `len` memory locations starting at `start` exist,
+    /// without wrapping around to address 0.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether they exist, or a VMError::InvalidIndex
+    /// with the last address of the range otherwise.
+    fn check_range(&self, start: u16, len: usize) -> Result<(), VMError> {
+        let memory_size = self.mem.len();
+        let end = usize::from(start).saturating_add(len);
+        if end > memory_size {
+            return Err(VMError::InvalidIndex(end.saturating_sub(1)));
+        }
         Ok(())
     }
 
-    /// Writes a single character into stdout.
-    pub fn out(&mut self, writer: &mut impl Write) -> Result<(), VMError> {
-        let c: u8 = self.regs[Register::R0]
-            .try_into()
-            .map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))?;
-        stdout_write(&[c], writer)?;
+    /// Writes the operating system into memory and makes the TRAP instruction
+    /// use its trap vector table to find the trap routines.
+    pub fn install_os(&mut self) -> Result<(), VMError> {
+        for (origin, words) in assemble(OS_SOURCE)? {
+            self.load_words(origin, &words)?;
+        }
+        self.trap_mode = TrapMode::MemoryVector;
         Ok(())
     }
 
-    /// Prompts for input character from the stdin.
-    pub fn trap_in(
-        &mut self,
-        writer: &mut impl Write,
-        reader: &mut impl Read,
-    ) -> Result<(), VMError> {
-        print!("Enter a character: ");
-        let buffer = getchar(reader)?;
-        stdout_write(&buffer, writer)?;
-        stdout_flush(writer)?;
-        self.regs[Register::R0] = buffer[0].into();
-        self.update_flags(Register::R0);
-        Ok(())
+    /// Starts the machine like the real one does: installs the operating system,
+    /// loads the user image at its origin and runs from the entry point of the
+    /// system, which then jumps to the user program.
+    ///
+    /// ### Arguments
+    ///
+    /// - `user_image`: The bytes of the image with the program to run, starting with its origin.
+    pub fn boot(&mut self, user_image: &[u8]) -> Result<(), VMError> {
+        self.install_os()?;
+        let origin = self.read_image_file(&mut user_image.to_vec())?;
+        self.mem.write(OS_USER_PC, origin)?;
+        self.validate_trap_table()?;
+        self.regs[Register::PC] = OS_ENTRY;
+        self.run()
     }
 
-    /// Writes a null-terminated string into stdout. The characters are contained in consecutive memory locations,
-    /// one character per memory location, starting with the address specified in R0. Writing
-    /// terminates with the occurrence of x0000 in a memory location.
-    pub fn puts(&mut self, writer: &mut impl Write) -> Result<(), VMError> {
-        // Get the address of the first character and read it
-        let mut c_addr = self.regs[Register::R0];
-        let mut c = self.mem.read(c_addr)?;
-        while c != NULL {
-            // Parse it into a u8, write it and pass to the next memory location
-            let char: u8 = c
-                .try_into()
-                .map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))?;
-            stdout_write(&[char], writer)?;
-            c_addr = c_addr.wrapping_add(1);
-            c = self.mem.read(c_addr)?;
+    /// Checks that every standard trap routine has the address of its handler
+    /// in the trap vector table. A vector left at 0 would make the TRAP
+    /// instruction jump to address 0x0000.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether the table is valid, or a
+    /// VMError::InvalidTrapVector with the first vector that is not.
+    pub fn validate_trap_table(&self) -> Result<(), VMError> {
+        for trap_code in TrapCode::all() {
+            let vector = trap_code.vector();
+            if self.mem.peek(vector) == NULL {
+                return Err(VMError::InvalidTrapVector(vector));
+            }
         }
-        stdout_flush(writer)?;
         Ok(())
     }
 
-    /// Writes a null-terminated string into stdout. The characters are contained in consecutive memory locations,
-    /// but this time there are two characters per memory location, starting with the address specified in R0. Writing
-    /// terminates with the occurrence of x0000 in a memory location.
-    pub fn puts_p(&mut self, writer: &mut impl Write) -> Result<(), VMError> {
-        // Get the address of the first characters and read them
-        let mut c_addr = self.regs[Register::R0];
-        let mut c = self.mem.read(c_addr)?;
-        while c != NULL {
-            // Get the first character in the memory location (the 8 leftmost bits)
-            let char1 = (c & 0xFF)
-                .try_into()
-                .map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))?;
-            stdout_write(&[char1], writer)?;
-            // Get the second character in the same memory location (the 8 rightmost bits)
-            let char2 = (c >> 8)
+    /// Writes a value into memory on behalf of the program being run.
+    /// Writing the DisplayData register outputs its character, and clearing
+    /// the clock enable bit (the MSB) of the MachineControl register stops the VM.
+    fn write_memory(&mut self, address: u16, new_val: u16) -> Result<(), VMError> {
+        self.check_access(address)?;
+        if let Some(stats) = self.memory_stats.as_mut() {
+            stats.writes = stats.writes.saturating_add(1);
+        }
+        let next_instr = self.regs[Register::PC];
+        if self.self_modify_check
+            && (address == next_instr || address == next_instr.wrapping_add(1))
+            && let Some((instr_addr, _)) = self.last_instruction
+        {
+            self.self_modifying_writes.push((instr_addr, address));
+        }
+        self.mem.write(address, new_val)?;
+        self.log_device_access(DeviceAccess::Write, address, new_val);
+        if address == MemoryRegister::DisplayData {
+            let c: u8 = (new_val & EIGHT_BIT_MASK)
                 .try_into()
                 .map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))?;
-            if char2 != 0x00 {
-                stdout_write(&[char2], writer)?;
-            }
-            c_addr = c_addr.wrapping_add(1);
-            // Get the next memory location
-            c = self.mem.read(c_addr)?;
+            let mut output = mem::replace(&mut self.output, Box::new(sink()));
+            let result = self
+                .write_output(c, &mut output)
+                .and_then(|_| stdout_flush(&mut output));
+            self.output = output;
+            result?;
+        } else if address == MemoryRegister::MachineControl && new_val >> 15 == 0 {
+            self.running = false;
+            self.last_stop_reason = Some(StopReason::McrCleared);
         }
-        stdout_flush(writer)?;
         Ok(())
     }
 
-    /// Writes on stdout th word 'HALT' to notify the user that the program is stopping
-    /// and changes the 'running' flag to false. This is the flag that is used in the
-    /// main loop to know if the program needs to continue processing instructions or not.
-    pub fn halt(&mut self, writer: &mut impl Write) -> Result<(), VMError> {
-        let s = "HALT\n".as_bytes();
-        stdout_write(s, writer)?;
-        stdout_flush(writer)?;
-        // Change the flag so the main loop stops
-        self.running = false;
-        Ok(())
+    /// Reads a memory address. Reading the KeyboardStatus waits for a character
+    /// from the input and leaves it in the KeyboardData, setting the ready bit.
+    fn read_memory(&mut self, address: u16) -> Result<u16, VMError> {
+        if let Some(stats) = self.memory_stats.as_mut() {
+            stats.reads = stats.reads.saturating_add(1);
+        }
+        self.read_memory_uncounted(address)
     }
-}
 
-impl Default for VM {
-    /// Creates a VM instance with all the registers and
-    /// memory locations set to 0.
-    ///
-    /// This is used for easier testing
-    fn default() -> Self {
-        Self {
-            mem: Memory::new(),
-            regs: Registers::new(),
+    /// Reads the instruction at a memory address, the same way as any other read
+    fn fetch(&mut self, address: u16) -> Result<u16, VMError> {
+        if let Some(stats) = self.memory_stats.as_mut() {
+            stats.fetches = stats.fetches.saturating_add(1);
+        }
+        self.read_memory_uncounted(address)
+    }
+
+    fn read_memory_uncounted(&mut self, address: u16) -> Result<u16, VMError> {
+        self.check_access(address)?;
+        if address == MemoryRegister::KeyboardStatus {
+            let status = self.mem.peek(KBSR_ADDR);
+            let non_blocking =
+                self.headless || self.key_source.is_some() || !self.replayed_input.is_empty();
+            let latched = (non_blocking || self.key_pushed) && status & KBSR_READY != 0;
+            if !latched && let Some(key) = self.next_key()? {
+                self.latch_key(key)?;
+            }
+        } else if address == MemoryRegister::KeyboardData {
+            // The key is consumed, so the ready bit is cleared
+            self.key_pushed = false;
+            let status = self.mem.peek(KBSR_ADDR);
+            self.mem
+                .write(MemoryRegister::KeyboardStatus, status & !KBSR_READY)?;
+        }
+        let value = self.mem.read(address)?;
+        self.log_device_access(DeviceAccess::Read, address, value);
+        Ok(value)
+    }
+
+    /// Records an access to a device register, if logging them
+    fn log_device_access(&mut self, access: DeviceAccess, address: u16, value: u16) {
+        if let Some(log) = self.device_log.as_mut()
+            && is_device_register(address)
+        {
+            let pc = self
+                .last_instruction
+                .map(|(addr, _)| addr)
+                .unwrap_or_default();
+            log.push((pc, access, address, value));
+        }
+    }
+
+    /// Reads the next key, from the queued characters or else from the key
+    /// source or the input. The key source returns None when there is no key
+    /// yet, and in headless mode None is returned once the input has no more
+    /// characters.
+    fn next_key(&mut self) -> Result<Option<u8>, VMError> {
+        let key = self.next_key_unlogged()?;
+        if let Some(key) = key {
+            self.log_input(key);
+        }
+        Ok(key)
+    }
+
+    fn next_key_unlogged(&mut self) -> Result<Option<u8>, VMError> {
+        if let Some(key) = self.queued_input.pop_front() {
+            return Ok(Some(key));
+        }
+        if let Some(key) = self.source_key() {
+            return Ok(key);
+        }
+        if !self.headless {
+            let mut input = mem::replace(&mut self.input, Box::new(empty()));
+            let key = self.wait_for_key(&mut input);
+            self.input = input;
+            // When interrupted there is no key, and the run stops before the next instruction
+            return key;
+        }
+        let mut buffer = [0u8; 1];
+        match self.input.read(&mut buffer) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(buffer[0])),
+            Err(e) => Err(VMError::STDINRead(e.to_string())),
+        }
+    }
+
+    /// Takes the next key only if it can be had without waiting for it, which
+    /// is always the case except for an input that blocks until a key arrives
+    /// and is not polled.
+    fn poll_key(&mut self) -> Result<Option<u8>, VMError> {
+        let non_blocking = self.headless
+            || self.key_source.is_some()
+            || !self.queued_input.is_empty()
+            || !self.replayed_input.is_empty();
+        if non_blocking {
+            return self.next_key();
+        }
+        if !self.polled_input {
+            return Ok(None);
+        }
+        let mut buffer = [0u8; 1];
+        match self.input.read(&mut buffer) {
+            Ok(0) => Ok(None),
+            Ok(_) => {
+                self.log_input(buffer[0]);
+                Ok(Some(buffer[0]))
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => Ok(None),
+            Err(e) => Err(VMError::STDINRead(e.to_string())),
+        }
+    }
+
+    /// Takes the next key from the input being replayed, or else from the key
+    /// source. A replayed key is only ready once the cycle it was consumed at
+    /// is reached.
+    ///
+    /// ### Returns
+    ///
+    /// None when neither of them is in use, or else the key if it is ready.
+    fn source_key(&mut self) -> Option<Option<u8>> {
+        if let Some(&(cycle, key)) = self.replayed_input.front() {
+            if self.cycles < cycle {
+                return Some(None);
+            }
+            self.replayed_input.pop_front();
+            return Some(Some(key));
+        }
+        self.key_source.as_mut().map(|source| source())
+    }
+
+    /// Reads a key from `reader`, waiting until there is one. With polled
+    /// input, the interrupt flag is checked every time the reader returns
+    /// without a key.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the key, or None if the interrupt flag was raised
+    /// while waiting for it.
+    fn wait_for_key(&self, reader: &mut impl Read) -> Result<Option<u8>, VMError> {
+        if !self.polled_input {
+            return getchar(reader).map(|buffer| Some(buffer[0]));
+        }
+        let mut buffer = [0u8; 1];
+        loop {
+            if self
+                .interrupt_flag
+                .as_ref()
+                .is_some_and(|flag| flag.load(Ordering::SeqCst))
+            {
+                return Ok(None);
+            }
+            match reader.read(&mut buffer) {
+                Ok(0) => thread::sleep(SPIN_WAIT),
+                Ok(_) => return Ok(Some(buffer[0])),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(VMError::STDINRead(e.to_string())),
+            }
+        }
+    }
+
+    /// Stops the VM because the interrupt flag was raised while a trap routine
+    /// was waiting for a key, leaving the PC on its TRAP instruction so
+    /// running again waits for the key once more
+    fn stop_waiting_trap(&mut self) {
+        if let Some(flag) = &self.interrupt_flag {
+            flag.store(false, Ordering::SeqCst);
+        }
+        self.regs[Register::PC] = self.regs[Register::PC].wrapping_sub(1);
+        self.running = false;
+        self.last_stop_reason = Some(StopReason::Interrupted);
+    }
+
+    /// Records that the key was consumed in the current cycle, if logging
+    fn log_input(&mut self, key: u8) {
+        if let Some(log) = self.input_log.as_mut() {
+            log.push((self.cycles, key));
+        }
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.push_str(&format!("[in:{}]", char::from(key).escape_default()));
+        }
+    }
+
+    /// Tells if there is a key in the KeyboardData that the program did not
+    /// read yet, which is what the ready bit of the KeyboardStatus shows
+    pub fn key_ready(&self) -> bool {
+        self.mem.peek(KBSR_ADDR) & KBSR_READY != 0
+    }
+
+    /// Leaves a key in the KeyboardData and sets the ready bit of the
+    /// KeyboardStatus, as if the user pressed it. Until the program reads it
+    /// from the KeyboardData, polling the KeyboardStatus does not wait for
+    /// another key. A key that was not read yet gets replaced.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether the operation failed or not.
+    pub fn push_key(&mut self, key: u8) -> Result<(), VMError> {
+        self.latch_key(key)?;
+        self.key_pushed = true;
+        Ok(())
+    }
+
+    /// Leaves a key in the KeyboardData and sets the ready bit of the
+    /// KeyboardStatus, keeping its interrupt enable bit
+    fn latch_key(&mut self, key: u8) -> Result<(), VMError> {
+        let status = self.mem.peek(KBSR_ADDR);
+        self.mem
+            .write(MemoryRegister::KeyboardStatus, status | KBSR_READY)?;
+        self.mem.write(MemoryRegister::KeyboardData, u16::from(key))
+    }
+
+    /// Returns the priority level the VM is running at, from 0 to 7. Only
+    /// the interrupts with a higher priority can preempt the running code.
+    pub fn priority(&self) -> u16 {
+        (self.processor.psr >> PSR_PRIORITY_SHIFT) & THREE_BIT_MASK
+    }
+
+    /// Changes the priority level in the PSR to the 3 rightmost bits of `level`
+    fn set_priority(&mut self, level: u16) {
+        let priority_bits = THREE_BIT_MASK << PSR_PRIORITY_SHIFT;
+        self.processor.psr = (self.processor.psr & !priority_bits)
+            | ((level & THREE_BIT_MASK) << PSR_PRIORITY_SHIFT);
+    }
+
+    /// Latches the next key when the keyboard interrupt is enabled and there is
+    /// no key ready, so the interrupt fires as soon as a key arrives without
+    /// the program polling the KBSR. It never waits for the key.
+    fn poll_keyboard(&mut self) -> Result<(), VMError> {
+        let status = self.mem.peek(KBSR_ADDR);
+        if status & KBSR_INTERRUPT_ENABLE != 0
+            && status & KBSR_READY == 0
+            && let Some(key) = self.poll_key()?
+        {
+            self.latch_key(key)?;
+        }
+        Ok(())
+    }
+
+    /// Tells if a key would be delivered as a keyboard interrupt, which needs
+    /// the interrupt enabled in the KBSR and a priority higher than the current one
+    fn keyboard_interrupt_allowed(&self) -> bool {
+        self.mem.peek(KBSR_ADDR) & KBSR_INTERRUPT_ENABLE != 0 && KEYBOARD_PRIORITY > self.priority()
+    }
+
+    /// Checks for a keyboard interrupt before the next instruction. When a key
+    /// is ready, the interrupt is enabled and its priority is higher than the
+    /// current one, the PSR and PC are pushed on the supervisor stack and the
+    /// PC jumps to the handler whose address is in the interrupt vector table.
+    fn check_interrupts(&mut self) -> Result<(), VMError> {
+        if !self.key_ready() || !self.keyboard_interrupt_allowed() {
+            return Ok(());
+        }
+        self.enter_supervisor()?;
+        self.set_priority(KEYBOARD_PRIORITY);
+        self.regs[Register::PC] = self.read_memory(KEYBOARD_VECTOR)?;
+        Ok(())
+    }
+
+    /// Checks that the running program can access an address. In user mode
+    /// only the user space can be accessed, not the system space nor the
+    /// device registers.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether the access is allowed, or a
+    /// VMError::AccessViolation with the address otherwise.
+    fn check_access(&self, address: u16) -> Result<(), VMError> {
+        if self.privilege() == Privilege::User && !USER_SPACE.contains(&address) {
+            return Err(VMError::AccessViolation(address));
+        }
+        Ok(())
+    }
+
+    /// Adds the symbols of a `.sym` file, like the ones of the LC-3 toolchain,
+    /// to the ones used by the disassembly. Malformed lines are skipped.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing a warning for each skipped line, or an error if
+    /// the file could not be read.
+    pub fn load_symbols(&mut self, path: &str) -> Result<Vec<String>, VMError> {
+        let src = fs::read_to_string(path)
+            .map_err(|e| VMError::OpenFile(path.to_string(), e.to_string()))?;
+        let (symbols, warnings) = parse_symbol_file(&src);
+        self.symbols.extend(symbols);
+        Ok(warnings
+            .into_iter()
+            .map(|warning| format!("skipping symbol in {}, {}", path, warning))
+            .collect())
+    }
+
+    /// Returns the privilege mode the VM is running in
+    pub fn privilege(&self) -> Privilege {
+        if self.processor.psr & PSR_USER == 0 {
+            Privilege::Supervisor
+        } else {
+            Privilege::User
+        }
+    }
+
+    /// Returns the Processor Status Register: the privilege mode in bit 15,
+    /// the priority in bits 10 to 8 and the condition flag in bits 2 to 0
+    pub fn psr(&self) -> u16 {
+        self.processor.psr | self.regs[Register::Cond]
+    }
+
+    /// Returns the memory address that a load or store instruction would
+    /// access with the current registers, without executing it nor having
+    /// the side effects of reading device registers. The PC is taken as the
+    /// address of the instruction, so the offsets are relative to PC + 1.
+    /// LDI and STI access the address stored in their pointer, which is
+    /// returned when `resolve_indirect` is true, and the pointer otherwise.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the address, or a VMError::Conversion if the
+    /// instruction is not a load nor a store.
+    pub fn effective_address(&self, instr: u16, resolve_indirect: bool) -> Result<u16, VMError> {
+        let next_pc = self.regs[Register::PC].wrapping_add(1);
+        let address = match decode(instr)? {
+            DecodedInstr::Ld { offset, .. } | DecodedInstr::St { offset, .. } => {
+                next_pc.wrapping_add_signed(offset)
+            }
+            DecodedInstr::Ldr { base, offset, .. } | DecodedInstr::Str { base, offset, .. } => {
+                self.regs[base].wrapping_add_signed(offset)
+            }
+            DecodedInstr::Ldi { offset, .. } | DecodedInstr::Sti { offset, .. } => {
+                let pointer = next_pc.wrapping_add_signed(offset);
+                if resolve_indirect {
+                    self.mem.peek(pointer)
+                } else {
+                    pointer
+                }
+            }
+            _ => {
+                let err_str = format!("Instruction ({:#06X}) is not a load nor a store", instr);
+                return Err(VMError::Conversion(err_str));
+            }
+        };
+        Ok(address)
+    }
+
+    /// Returns the address of a label of the loaded programs
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Returns the label of an address, if it has one. When there are several
+    /// labels for the same address, the first one in alphabetical order is used.
+    pub fn symbol_at(&self, address: u16) -> Option<&str> {
+        symbol_at(&self.symbols, address)
+    }
+
+    /// Returns the value that a register holds
+    pub fn register(&self, reg: Register) -> u16 {
+        self.regs[reg]
+    }
+
+    /// Checks that the registers hold the expected values, panicking with a
+    /// line for each one that does not, like `R1: expected x0005, found x0004`.
+    /// It is meant for tests, where it replaces an assert_eq! per register.
+    pub fn assert_registers(&self, expected: &[(Register, u16)]) {
+        let mismatches: Vec<String> = Register::all()
+            .iter()
+            .flat_map(|reg| {
+                expected
+                    .iter()
+                    .filter(move |(expected_reg, _)| expected_reg == reg)
+                    .filter(|(reg, value)| self.regs[*reg] != *value)
+                    .map(|(reg, value)| {
+                        format!(
+                            "{}: expected x{:04X}, found x{:04X}",
+                            reg, value, self.regs[*reg]
+                        )
+                    })
+            })
+            .collect();
+        assert!(
+            mismatches.is_empty(),
+            "registers do not match:\n{}",
+            mismatches.join("\n")
+        );
+    }
+
+    /// Returns the whole memory, where the index of each value is its address.
+    /// Getting it does not have the side effects of reading device registers.
+    /// With the default word memory it is borrowed, only a memory model that
+    /// cannot lend its words gets them copied.
+    pub fn memory_slice(&self) -> Cow<'_, [u16]> {
+        self.mem.as_slice()
+    }
+
+    /// Scans the memory locations in `range` and returns the addresses of the ones
+    /// holding an instruction of the given operation. The memory is read without
+    /// side effects, so scanning device registers does not alter them.
+    pub fn find_instructions(&self, opcode: OpCode, range: Range<u16>) -> Vec<u16> {
+        range
+            .filter(|addr| OpCode::try_from(self.mem.peek(*addr) >> 12).ok() == Some(opcode))
+            .collect()
+    }
+
+    /// Returns why the VM stopped the last time it was running, or None
+    /// if it has not stopped yet
+    pub fn last_stop_reason(&self) -> Option<StopReason> {
+        self.last_stop_reason
+    }
+
+    /// Stops the HALT trap routine from writing its banner, so the
+    /// output only has what the program wrote
+    pub fn with_quiet(mut self, enabled: bool) -> Self {
+        self.quiet = enabled;
+        self
+    }
+
+    /// Makes the OUT, PUTS and PUTSP trap routines write every '\n' as "\r\n",
+    /// so the output is not misaligned on a terminal in raw mode. It is disabled
+    /// by default, so the bytes are written exactly as the program produced them.
+    pub fn with_newline_translation(mut self, enabled: bool) -> Self {
+        self.translate_newlines = enabled;
+        self
+    }
+
+    /// Makes the output trap routines write every non-printable byte, like a
+    /// control character, as a visible `\xNN` escape so it cannot mangle a
+    /// terminal. Printable ASCII, '\n', '\r' and '\t' are written as they are.
+    /// It is disabled by default, so the bytes are written exactly as the
+    /// program produced them.
+    pub fn with_output_sanitizing(mut self, enabled: bool) -> Self {
+        self.sanitize_output = enabled;
+        self
+    }
+
+    /// Selects how the TRAP instruction runs the trap routines
+    pub fn with_trap_mode(mut self, trap_mode: TrapMode) -> Self {
+        self.trap_mode = trap_mode;
+        self
+    }
+
+    /// Makes the VM record every instruction it runs that has any of its unused
+    /// bits set, like bits 3 and 4 of ADD in register mode. The instructions still
+    /// run as usual. It is disabled by default. It is the same as the
+    /// ReservedBitsPolicy::Warn policy.
+    pub fn with_strict_decode(mut self, enabled: bool) -> Self {
+        self.reserved_bits = if enabled {
+            ReservedBitsPolicy::Warn
+        } else {
+            ReservedBitsPolicy::Lenient
+        };
+        self
+    }
+
+    /// Selects what the VM does with the instructions that have any of their
+    /// unused bits set, which usually means they were assembled by hand with
+    /// a mistake. By default they run as usual.
+    pub fn with_reserved_bits_policy(mut self, policy: ReservedBitsPolicy) -> Self {
+        self.reserved_bits = policy;
+        self
+    }
+
+    /// Returns the address and encoding of each instruction with unused bits
+    /// set that ran while strict decoding was enabled
+    pub fn decode_warnings(&self) -> &[(u16, u16)] {
+        &self.decode_warnings
+    }
+
+    /// Returns the description of each part of an image that was left out
+    /// while loading it, like the bytes that do not fit in the memory
+    pub fn load_warnings(&self) -> &[String] {
+        &self.load_warnings
+    }
+
+    /// Makes the VM record every store whose address is the one of the next
+    /// instruction to run, or the one after it. Writing there is usually a bug
+    /// that overwrites the program. The stores still happen as usual. It is
+    /// disabled by default.
+    pub fn with_self_modify_check(mut self, enabled: bool) -> Self {
+        self.self_modify_check = enabled;
+        self
+    }
+
+    /// Returns the address of each store into the next instructions to run,
+    /// and the address it wrote, recorded while the check was enabled
+    pub fn self_modifying_writes(&self) -> &[(u16, u16)] {
+        &self.self_modifying_writes
+    }
+
+    /// Selects whether the GETC trap routine updates the condition flag with the
+    /// character it read. Implementations of the LC-3 differ on this, so it can be
+    /// set to match the expected semantics. By default the flag is updated.
+    pub fn with_getc_updates_flags(mut self, enabled: bool) -> Self {
+        self.getc_updates_flags = enabled;
+        self
+    }
+
+    /// Sets the text that the IN trap routine writes into the output before
+    /// reading a character, or leaves it out when it is None. By default it
+    /// is "Enter a character: ".
+    pub fn with_in_prompt(mut self, prompt: Option<&str>) -> Self {
+        self.in_prompt = prompt.map(String::from);
+        self
+    }
+
+    /// Makes `run` keep going after a recoverable error, like an illegal opcode,
+    /// recording it and moving on to the next instruction. By default the VM
+    /// stops on the first error.
+    pub fn with_continue_on_error(mut self, enabled: bool) -> Self {
+        self.continue_on_error = enabled;
+        self
+    }
+
+    /// Returns the errors that were recovered while running, each one with
+    /// the address of the instruction that caused it. Only the first 1000 are
+    /// kept, so a program stuck on an error does not fill the memory.
+    pub fn recovered_errors(&self) -> &[(u16, String)] {
+        &self.recovered_errors
+    }
+
+    /// Returns how many errors were recovered while running, including the
+    /// ones after the first 1000 that `recovered_errors` does not keep
+    pub fn recovered_error_count(&self) -> u64 {
+        self.recovered_error_count
+    }
+
+    /// Enables recording the changes made by each step, so they can be undone
+    /// with `step_back`. Only the latest `limit` steps are kept.
+    pub fn with_history(mut self, limit: usize) -> Self {
+        self.history = Some(VecDeque::new());
+        self.history_limit = limit;
+        self
+    }
+
+    /// Enables recording every change of the condition flag made by an
+    /// instruction. Only the latest `limit` changes are kept.
+    pub fn with_cond_history(mut self, limit: usize) -> Self {
+        self.cond_history = Some(VecDeque::new());
+        self.cond_history_limit = limit;
+        self
+    }
+
+    /// Returns the latest changes of the condition flag, oldest first, with the
+    /// address of the instruction that made each one, the old flag and the new
+    /// one. It is empty unless recording them was enabled.
+    pub fn cond_history(&self) -> Vec<(u16, u16, u16)> {
+        self.cond_history
+            .as_ref()
+            .map(|history| history.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Makes the VM count how many times each operation gets executed
+    pub fn with_opcode_histogram(mut self, enabled: bool) -> Self {
+        self.opcode_counts = enabled.then(HashMap::new);
+        self
+    }
+
+    /// Returns how many times each operation was executed. Operations that
+    /// never ran are not included, and it is empty if counting is disabled.
+    pub fn opcode_histogram(&self) -> HashMap<OpCode, u64> {
+        self.opcode_counts.clone().unwrap_or_default()
+    }
+
+    /// Makes the VM record every read and write of a device register done by
+    /// the instructions and the trap routines
+    pub fn with_device_log(mut self, enabled: bool) -> Self {
+        self.device_log = enabled.then(Vec::new);
+        self
+    }
+
+    /// Returns the address of the instruction, the kind of access, the address
+    /// of the device register and the value read or written for every access
+    /// to a device register, empty if logging is disabled
+    pub fn device_log(&self) -> &[(u16, DeviceAccess, u16, u16)] {
+        self.device_log.as_deref().unwrap_or_default()
+    }
+
+    /// Makes the VM count the reads and writes of memory done by the
+    /// instructions, and separately the fetches of the instructions
+    pub fn with_memory_stats(mut self, enabled: bool) -> Self {
+        self.memory_stats = enabled.then(MemoryStats::default);
+        self
+    }
+
+    /// Returns how many reads, writes and instruction fetches were done,
+    /// in that order. They are all 0 if counting is disabled.
+    pub fn memory_stats(&self) -> (u64, u64, u64) {
+        let stats = self.memory_stats.unwrap_or_default();
+        (stats.reads, stats.writes, stats.fetches)
+    }
+
+    /// Makes the VM write a line into `writer` for every instruction it
+    /// executes, with its address, its disassembly and the registers it
+    /// changed, like `x3000: ADD R0, R1, R2 ; R0=x0003 COND=x0001`.
+    pub fn with_trace(mut self, writer: impl Write + 'static) -> Self {
+        self.trace = Some(Box::new(writer));
+        self
+    }
+
+    /// Makes the VM stop with StopReason::LikelyCrash when an instruction
+    /// in user space (from x3000 to xFDFF) sends the PC to address 0x0000.
+    pub fn with_crash_guard(mut self, enabled: bool) -> Self {
+        self.crash_guard = enabled;
+        self
+    }
+
+    /// Sets how many JSR and JSRR calls can be nested without returning
+    /// before the VM stops with StopReason::CallDepthExceeded, or stops
+    /// tracking the calls when it is None. A RET returns from the last call
+    /// when it lands on its return address or on the word right after it. By
+    /// default the limit is 4096 calls.
+    pub fn with_max_call_depth(mut self, depth: Option<usize>) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    /// Returns how many JSR and JSRR calls have not returned yet, always 0
+    /// when the calls are not tracked
+    pub fn call_depth(&self) -> usize {
+        self.call_stack.len()
+    }
+
+    /// Sets the addresses of the supervisor stack, which is empty when R6
+    /// points to the end of the range. By default it is `x2E00..x3000`.
+    pub fn with_supervisor_stack(mut self, stack: Range<u16>) -> Self {
+        self.processor.saved_ssp = stack.end;
+        self.supervisor_stack = stack;
+        self
+    }
+
+    /// Sets the privilege mode the VM starts in. By default it is
+    /// `Privilege::Supervisor`, where traps only save the return address in
+    /// R7. From user mode, a TRAP that jumps through the trap vector table
+    /// also switches R6 to the supervisor stack and pushes the PSR and the
+    /// PC on it, so RTI can return to user mode.
+    pub fn with_privilege(mut self, privilege: Privilege) -> Self {
+        match privilege {
+            Privilege::Supervisor => self.processor.psr &= !PSR_USER,
+            Privilege::User => self.processor.psr |= PSR_USER,
+        }
+        self
+    }
+
+    /// Makes the VM run without a terminal. Reading the KeyboardStatus then
+    /// never waits nor fails: when the input has no more characters it reads
+    /// as 0 (no key ready), and a key that was not read from the KeyboardData
+    /// yet stays there. This way programs that poll the keyboard keep waiting
+    /// until input arrives through the reader.
+    pub fn with_headless(mut self, enabled: bool) -> Self {
+        self.headless = enabled;
+        self
+    }
+
+    /// Makes the images whose data has an odd number of bytes load with a 0
+    /// byte added at the end, completing their last word. By default they
+    /// fail to load with VMError::TruncatedImage.
+    pub fn with_pad_odd_images(mut self, enabled: bool) -> Self {
+        self.pad_odd_images = enabled;
+        self
+    }
+
+    /// Adds characters to be read by GETC, IN and the keyboard registers as if
+    /// the user typed them. They are read before the ones of the input, which
+    /// is used again once they run out.
+    pub fn queue_input(&mut self, bytes: &[u8]) {
+        self.queued_input.extend(bytes);
+    }
+
+    /// Makes every run execute at most `limit` instructions, stopping with
+    /// StopReason::CycleLimit once they ran. The VM can keep running with
+    /// another run. There is no limit by default.
+    pub fn with_cycle_limit(mut self, limit: u64) -> Self {
+        self.cycle_limit = Some(limit);
+        self
+    }
+
+    /// Makes a run fail with VMError::MissingHalt when the program stops for
+    /// any reason other than the HALT trap or clearing the MCR, like reaching
+    /// the cycle limit or jumping to address 0x0000 with the crash guard on.
+    pub fn with_strict_halt(mut self, enabled: bool) -> Self {
+        self.strict_halt = enabled;
+        self
+    }
+
+    /// Sets the maximum amount of memory locations that PUTS and PUTSP write
+    /// before giving up on finding the x0000 that ends the string. By default
+    /// it is the size of the memory, so they only give up when there is no
+    /// x0000 at all.
+    pub fn with_max_string_len(mut self, max_len: usize) -> Self {
+        self.max_string_len = max_len;
+        self
+    }
+
+    /// Makes the keyboard registers, GETC and IN get their keys from `source`
+    /// instead of the input, once the queued characters run out. When it
+    /// returns None there is no key ready: the KBSR reads as 0, and GETC and
+    /// IN wait a millisecond and run again in the next step.
+    pub fn set_key_source(&mut self, source: Box<dyn FnMut() -> Option<u8>>) {
+        self.key_source = Some(source);
+    }
+
+    /// Makes the VM record every character consumed by GETC, IN and the
+    /// keyboard registers, together with the cycle when it was consumed
+    pub fn with_input_log(mut self, enabled: bool) -> Self {
+        self.input_log = enabled.then(Vec::new);
+        self
+    }
+
+    /// Returns the characters consumed so far and the cycle when each one was,
+    /// empty if logging is disabled. Giving it to `replay_input` reproduces
+    /// the same input in another run.
+    pub fn input_log(&self) -> &[(u64, u8)] {
+        self.input_log.as_deref().unwrap_or_default()
+    }
+
+    /// Makes the VM build a transcript of the session, with the characters
+    /// written by the program and the ones it consumed, in the order they
+    /// happened
+    pub fn with_transcript(mut self, enabled: bool) -> Self {
+        self.transcript = enabled.then(String::new);
+        self
+    }
+
+    /// Returns the transcript of the session so far, empty if it is disabled.
+    /// The written characters are as the program produced them, and every
+    /// consumed one is marked like `[in:k]`, with `[in:\n]` for a newline.
+    pub fn transcript(&self) -> String {
+        self.transcript.clone().unwrap_or_default()
+    }
+
+    /// Feeds the characters of an input log to the program instead of the
+    /// input, each one becoming ready at the cycle it was consumed at in the
+    /// recorded run. Until then, GETC and IN wait and the KBSR reports that
+    /// no key is ready. The input is used again once the log runs out.
+    pub fn replay_input(&mut self, log: &[(u64, u8)]) {
+        self.replayed_input.extend(log);
+    }
+
+    /// Makes the TRAP instruction with the given vector run `handler`, which
+    /// takes precedence over the standard routines and the trap vector
+    /// table. By default the flush trap is at `FLUSH_VECTOR`, the shift
+    /// trap at `SHIFT_VECTOR` and the abort trap at `ABORT_VECTOR`.
+    pub fn with_custom_trap(mut self, vector: u8, handler: TrapHandler) -> Self {
+        self.custom_traps.insert(vector, handler);
+        self
+    }
+
+    /// Makes the memory of the VM have only `size` locations, starting from
+    /// address 0, instead of the 65,536 of the LC-3. Accessing an address
+    /// after them fails with VMError::InvalidIndex. This clears the memory.
+    pub fn with_memory_size(self, size: usize) -> Self {
+        self.with_memory(WordMemory::with_size(size))
+    }
+
+    /// Makes the VM read and write its memory through `model` instead of the
+    /// 65,536 words of the LC-3, like a memory that logs every access or the
+    /// byte-addressable memory of the LC-3b. Everything the VM loaded into
+    /// its previous memory is lost.
+    pub fn with_memory(mut self, model: impl MemoryModel + 'static) -> Self {
+        self.mem = Memory::with_model(Box::new(model));
+        self
+    }
+
+    /// Sets the priority level the VM starts running at, which is 0 by default.
+    /// Only the 3 rightmost bits of `level` are used.
+    pub fn with_priority(mut self, level: u16) -> Self {
+        self.set_priority(level);
+        self
+    }
+
+    /// Makes the VM stop before the next instruction once `flag` is raised.
+    /// The flag is lowered again when the VM stops because of it.
+    pub fn with_interrupt_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.interrupt_flag = Some(flag);
+        self
+    }
+
+    /// Tells the VM that reads of the input return at once without any byte
+    /// when there is no key, like a terminal set up by the TerminalGuard does.
+    /// The keyboard interrupt can then be polled for a key before every
+    /// instruction, and while waiting for a key the VM checks if the interrupt
+    /// flag was raised. When it was, the VM stops with StopReason::Interrupted
+    /// and the PC on the instruction that was waiting, instead of blocking
+    /// until a key arrives. An input that reads no bytes then never ends, so
+    /// it is only meant for terminals.
+    pub fn with_polled_input(mut self, enabled: bool) -> Self {
+        self.polled_input = enabled;
+        self
+    }
+
+    /// Runs instructions until the VM stops, or the cycle limit is reached.
+    /// Everything the program wrote is flushed before returning, even when it
+    /// stopped because of an error.
+    pub fn run(&mut self) -> Result<(), VMError> {
+        self.last_stop_reason = None;
+        let start = Instant::now();
+        let cycles_before = self.cycles;
+        let result = self.run_loop(None, false);
+        let flushed = stdout_flush(&mut self.output);
+        if self.stats {
+            let executed = self.cycles.saturating_sub(cycles_before);
+            let elapsed = start.elapsed();
+            let per_second = u128::from(executed)
+                .saturating_mul(1_000_000_000)
+                .checked_div(elapsed.as_nanos())
+                .unwrap_or_default();
+            eprintln!("instructions: {}", executed);
+            eprintln!("elapsed: {:.6} s", elapsed.as_secs_f64());
+            eprintln!("instructions per second: ~{}", per_second);
+        }
+        result.and(flushed)?;
+        match self.last_stop_reason {
+            Some(StopReason::HaltTrap | StopReason::McrCleared) => Ok(()),
+            Some(StopReason::UserAbort(code)) => Err(VMError::UserAbort(code)),
+            Some(StopReason::CallDepthExceeded(depth)) => Err(VMError::CallDepthExceeded(depth)),
+            Some(reason) if self.strict_halt => Err(VMError::MissingHalt(format!("{:?}", reason))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs instructions until the PC gets to `target_pc`, stopping before
+    /// executing the instruction there, or until the VM stops. When a cycle
+    /// limit is set, it also returns once that many instructions ran.
+    /// Everything the program wrote is flushed before returning.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the reason why the run ended.
+    pub fn run_until(&mut self, target_pc: u16) -> Result<RunOutcome, VMError> {
+        self.last_stop_reason = None;
+        let result = self.run_loop(Some(target_pc), false);
+        let flushed = stdout_flush(&mut self.output);
+        result.and_then(|outcome| flushed.map(|_| outcome))
+    }
+
+    /// Returns the number of instructions executed since the VM was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Sets how many cycles each operation costs, to model how long a program
+    /// takes instead of how many instructions it runs. The operations that
+    /// are not in the table cost 1 cycle, which is the default for all of them.
+    pub fn with_cycle_costs(mut self, costs: HashMap<OpCode, u64>) -> Self {
+        self.cycle_costs = costs;
+        self
+    }
+
+    /// Returns the cycles spent since the VM was created, adding the cost of
+    /// every executed instruction. With the default costs it is the same as
+    /// the number of instructions.
+    pub fn cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    /// Runs instructions until the VM stops, or until the program writes its
+    /// first output character, stopping right after the instruction that
+    /// wrote it. Everything the program wrote is flushed before returning.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing RunOutcome::Output when the program wrote something,
+    /// or else the reason why the run ended.
+    pub fn run_until_output(&mut self) -> Result<RunOutcome, VMError> {
+        self.last_stop_reason = None;
+        let result = self.run_loop(None, true);
+        let flushed = stdout_flush(&mut self.output);
+        result.and_then(|outcome| flushed.map(|_| outcome))
+    }
+
+    fn run_loop(
+        &mut self,
+        target_pc: Option<u16>,
+        until_output: bool,
+    ) -> Result<RunOutcome, VMError> {
+        let first_cycle = self.cycles;
+        while self.running {
+            if Some(self.regs[Register::PC]) == target_pc {
+                return Ok(RunOutcome::Reached);
+            }
+            if self
+                .cycle_limit
+                .is_some_and(|limit| self.cycles.saturating_sub(first_cycle) >= limit)
+            {
+                self.last_stop_reason = Some(StopReason::CycleLimit);
+                return Ok(RunOutcome::CycleLimit);
+            }
+            if let Some(flag) = &self.interrupt_flag
+                && flag.swap(false, Ordering::SeqCst)
+            {
+                self.running = false;
+                self.last_stop_reason = Some(StopReason::Interrupted);
+                break;
+            }
+            let instr_addr = self.regs[Register::PC];
+            let written_before = self.bytes_written;
+            if let Err(e) = self.step() {
+                if !(self.continue_on_error && e.is_recoverable()) {
+                    return Err(e);
+                }
+                // The PC already points to the next instruction, so the
+                // VM continues from there
+                self.recovered_error_count = self.recovered_error_count.saturating_add(1);
+                if self.recovered_errors.len() < MAX_RECOVERED_ERRORS {
+                    self.recovered_errors.push((instr_addr, format!("{:?}", e)));
+                }
+            }
+            if until_output && self.bytes_written > written_before {
+                return Ok(RunOutcome::Output);
+            }
+        }
+        Ok(RunOutcome::Halted)
+    }
+
+    /// Fetches the instruction the PC points to, increments the PC and executes it.
+    /// If history is enabled, the changes made are recorded so they can be undone,
+    /// and if tracing is enabled a line describing the instruction gets written.
+    pub fn step(&mut self) -> Result<(), VMError> {
+        let Some(mut trace) = self.trace.take() else {
+            return self.step_recorded();
+        };
+        let regs_before = self.regs.clone();
+        let instr_addr = self.regs[Register::PC];
+        let instr = self.mem.peek(instr_addr);
+        let result = self.step_recorded();
+        // An interrupt may have run the first instruction of its handler instead
+        let (instr_addr, instr) = match self.last_instruction {
+            Some(last) if result.is_ok() => last,
+            _ => (instr_addr, instr),
+        };
+
+        let changes: Vec<String> = Register::all()
+            .iter()
+            .filter(|r| **r != Register::PC && regs_before[**r] != self.regs[**r])
+            .map(|r| format!("{}=x{:04X}", r, self.regs[*r]))
+            .collect();
+        let mut line = format!(
+            "x{:04X}: {}",
+            instr_addr,
+            disassemble_at(instr, instr_addr, &self.symbols)
+        );
+        if !changes.is_empty() {
+            line = format!("{} ; {}", line, changes.join(" "));
+        }
+        let written = writeln!(trace, "{}", line)
+            .map_err(|_| VMError::STDOUTWrite(String::from("Cannot write the trace")));
+        self.trace = Some(trace);
+        result.and(written)
+    }
+
+    /// Runs a single step, recording its changes when history is enabled
+    fn step_recorded(&mut self) -> Result<(), VMError> {
+        if self.history.is_none() {
+            return self.fetch_and_execute();
+        }
+        let regs_before = self.regs.clone();
+        let running_before = self.running;
+        let stop_reason_before = self.last_stop_reason;
+        let processor_before = self.processor;
+        let call_stack_before = self.max_call_depth.map(|_| self.call_stack.clone());
+        self.mem.start_journal();
+        let result = self.fetch_and_execute();
+
+        let delta = StepDelta {
+            regs: Register::all()
+                .iter()
+                .filter(|r| regs_before[**r] != self.regs[**r])
+                .map(|r| (*r, regs_before[*r]))
+                .collect(),
+            mem: self.mem.take_journal(),
+            running: running_before,
+            stop_reason: stop_reason_before,
+            processor: processor_before,
+            call_stack: call_stack_before,
+        };
+        if let Some(history) = self.history.as_mut() {
+            history.push_back(delta);
+            if history.len() > self.history_limit {
+                history.pop_front();
+            }
+        }
+        result
+    }
+
+    /// Returns a listing of the `len` words that start at `start`, one per line
+    /// with its address, its value and its disassembly, where the targets with
+    /// a known label show it. Reading them does not have the side effects of
+    /// reading device registers.
+    pub fn disassemble_range(&self, start: u16, len: u16) -> String {
+        (0..len)
+            .map(|i| {
+                let addr = start.wrapping_add(i);
+                let word = self.mem.peek(addr);
+                format!(
+                    "x{:04X}  x{:04X}  {}\n",
+                    addr,
+                    word,
+                    disassemble_at(word, addr, &self.symbols)
+                )
+            })
+            .collect()
+    }
+
+    /// Runs up to `n` steps, calling `f` with the VM after each one. It stops
+    /// early once the VM stops, like after a HALT.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether the steps failed or not. When a step fails,
+    /// `f` is not called for it and no more steps run.
+    pub fn step_each(&mut self, n: u64, mut f: impl FnMut(&VM)) -> Result<(), VMError> {
+        for _ in 0..n {
+            if !self.running {
+                break;
+            }
+            self.step()?;
+            f(self);
+        }
+        Ok(())
+    }
+
+    /// Runs a single step and returns the disassembly of the instruction that
+    /// was executed, preceded by the address it was fetched from.
+    pub fn step_disasm(&mut self) -> Result<String, VMError> {
+        let instr_addr = self.regs[Register::PC];
+        let instr = self.mem.peek(instr_addr);
+        self.step()?;
+        Ok(format!(
+            "x{:04X}: {}",
+            instr_addr,
+            disassemble_at(instr, instr_addr, &self.symbols)
+        ))
+    }
+
+    /// Returns the state of the VM as a compact JSON object, meant for
+    /// frontends that show it after every step. It has the general purpose
+    /// registers, the PC, the condition flag as `n`, `z` or `p`, the number of
+    /// executed instructions, the disassembly of the last one (null if none ran
+    /// yet) and the memory from `mem_window` words before the PC to
+    /// `mem_window` words after it, like:
+    ///
+    /// `{"registers":{"R0":1,...,"R7":0},"pc":12289,"cond":"p","cycles":1,
+    /// "last_instruction":"ADD R0, R0, #1","memory":{"start":12287,"words":[0,4129,0]}}`
+    pub fn state_json(&self, mem_window: u16) -> String {
+        let registers: Vec<String> = Register::all()
+            .iter()
+            .filter(|reg| !matches!(reg, Register::PC | Register::Cond))
+            .map(|reg| format!("\"{}\":{}", reg, self.regs[*reg]))
+            .collect();
+        let cond: String = [
+            (CondFlag::Neg, 'n'),
+            (CondFlag::Zro, 'z'),
+            (CondFlag::Pos, 'p'),
+        ]
+        .iter()
+        .filter(|(flag, _)| self.regs[Register::Cond] & flag.value() != 0)
+        .map(|(_, name)| *name)
+        .collect();
+        let last_instruction = match self.last_instruction {
+            Some((addr, instr)) => {
+                let text = disassemble_at(instr, addr, &self.symbols);
+                format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            None => String::from("null"),
+        };
+        let pc = self.regs[Register::PC];
+        let start = pc.saturating_sub(mem_window);
+        let words: Vec<String> = (start..=pc.saturating_add(mem_window))
+            .map(|addr| self.mem.peek(addr).to_string())
+            .collect();
+        format!(
+            "{{\"registers\":{{{}}},\"pc\":{},\"cond\":\"{}\",\"cycles\":{},\"last_instruction\":{},\"memory\":{{\"start\":{},\"words\":[{}]}}}}",
+            registers.join(","),
+            pc,
+            cond,
+            self.cycles,
+            last_instruction,
+            start,
+            words.join(",")
+        )
+    }
+
+    /// Returns the address and the word of the last instruction that was
+    /// fetched, or None if the VM has not run any yet.
+    pub fn last_instruction(&self) -> Option<(u16, u16)> {
+        self.last_instruction
+    }
+
+    /// Undoes the latest recorded step, restoring the registers and memory
+    /// locations it changed, and why the VM stopped if the step stopped it.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing whether there was a step to undo or not.
+    pub fn step_back(&mut self) -> Result<bool, VMError> {
+        let Some(delta) = self.history.as_mut().and_then(|h| h.pop_back()) else {
+            return Ok(false);
+        };
+        for (reg, val) in delta.regs {
+            self.regs[reg] = val;
+        }
+        // Restore in reverse order, so a location written twice gets its oldest value
+        for (addr, val) in delta.mem.into_iter().rev() {
+            self.mem.write(addr, val)?;
+        }
+        self.running = delta.running;
+        self.last_stop_reason = delta.stop_reason;
+        self.processor = delta.processor;
+        if let Some(call_stack) = delta.call_stack {
+            self.call_stack = call_stack;
+        }
+        Ok(true)
+    }
+
+    fn fetch_and_execute(&mut self) -> Result<(), VMError> {
+        self.poll_keyboard()?;
+        self.check_interrupts()?;
+        let instr_addr = self.regs[Register::PC];
+        self.regs[Register::PC] = self.regs[Register::PC].wrapping_add(1);
+        let instr = self.fetch(instr_addr)?;
+        self.last_instruction = Some((instr_addr, instr));
+        self.cycles = self.cycles.saturating_add(1);
+        let cost = OpCode::try_from(instr >> 12)
+            .ok()
+            .and_then(|op_code| self.cycle_costs.get(&op_code))
+            .copied()
+            .unwrap_or(1);
+        self.cycle_count = self.cycle_count.saturating_add(cost);
+        if self.reserved_bits != ReservedBitsPolicy::Lenient && has_reserved_bits(instr) {
+            if self.reserved_bits == ReservedBitsPolicy::Reject {
+                return Err(VMError::ReservedBits {
+                    address: instr_addr,
+                    instr,
+                });
+            }
+            self.decode_warnings.push((instr_addr, instr));
+        }
+        if let (Some(counts), Ok(op_code)) =
+            (self.opcode_counts.as_mut(), OpCode::try_from(instr >> 12))
+        {
+            let count = counts.entry(op_code).or_insert(0);
+            *count = count.saturating_add(1);
+        }
+        self.execute(instr)?;
+        if self.crash_guard && USER_SPACE.contains(&instr_addr) && self.regs[Register::PC] == NULL {
+            self.running = false;
+            self.last_stop_reason = Some(StopReason::LikelyCrash);
+        }
+        Ok(())
+    }
+
+    /// Decodes the instruction and runs the routine of its operation
+    pub fn execute(&mut self, instr: u16) -> Result<(), VMError> {
+        match decode(instr)? {
+            DecodedInstr::Br { nzp, offset: -1 }
+                if self.headless && branch_taken(nzp, self.regs[Register::Cond]) =>
+            {
+                self.spin_wait(nzp)
+            }
+            DecodedInstr::Br { nzp, offset } => self.branch(nzp, offset),
+            DecodedInstr::Add { dr, sr1, operand } => self.add(dr, sr1, operand),
+            DecodedInstr::Ld { dr, offset } => self.load(dr, offset),
+            DecodedInstr::St { sr, offset } => self.store(sr, offset),
+            DecodedInstr::Jsr { offset } => {
+                self.jump_register(self.regs[Register::PC].wrapping_add_signed(offset))
+            }
+            DecodedInstr::Jsrr { base } => self.jump_register(self.regs[base]),
+            DecodedInstr::And { dr, sr1, operand } => self.and(dr, sr1, operand),
+            DecodedInstr::Ldr { dr, base, offset } => self.load_register(dr, base, offset),
+            DecodedInstr::Str { sr, base, offset } => self.store_register(sr, base, offset),
+            DecodedInstr::Not { dr, sr } => self.not(dr, sr),
+            DecodedInstr::Ldi { dr, offset } => self.load_indirect(dr, offset),
+            DecodedInstr::Sti { sr, offset } => self.store_indirect(sr, offset),
+            DecodedInstr::Jmp { base } => self.jump(base),
+            DecodedInstr::Lea { dr, offset } => self.load_effective_address(dr, offset),
+            DecodedInstr::Trap { vect } => self.trap(vect),
+            DecodedInstr::Rti => self.return_from_interrupt(),
+        }
+    }
+
+    /// Updates the register COND where we have the condition flag
+    pub fn update_flags(&mut self, r: Register) {
+        let old_flag = self.regs[Register::Cond];
+        if self.regs[r] == 0 {
+            self.regs[Register::Cond] = CondFlag::Zro.value();
+        } else if self.regs[r] >> 15 == 1 {
+            self.regs[Register::Cond] = CondFlag::Neg.value();
+        } else {
+            self.regs[Register::Cond] = CondFlag::Pos.value();
+        }
+
+        let new_flag = self.regs[Register::Cond];
+        if let Some(history) = self.cond_history.as_mut()
+            && old_flag != new_flag
+        {
+            let instr_addr = match self.last_instruction {
+                Some((addr, _)) => addr,
+                None => self.regs[Register::PC],
+            };
+            history.push_back((instr_addr, old_flag, new_flag));
+            if history.len() > self.cond_history_limit {
+                history.pop_front();
+            }
+        }
+    }
+
+    /// Adds two values and stores the result in the destination register.
+    ///
+    /// This instruction can be executed in two different ways:
+    ///
+    /// - Register mode: Adds two values taken from the registers storage.
+    /// - Immediate mode: Adds a value taken from the registers storage with one taken from the instruction itself.
+    ///
+    /// ### Arguments
+    ///
+    /// - `dr`: The destination register.
+    /// - `sr1`: The register with the first operand.
+    /// - `operand`: The register or the sign extended imm5 section with the second operand.
+    pub fn add(&mut self, dr: Register, sr1: Register, operand: Operand) -> Result<(), VMError> {
+        self.regs[dr] = match operand {
+            Operand::Immediate(imm5) => self.regs[sr1].wrapping_add_signed(imm5),
+            Operand::Register(sr2) => self.regs[sr1].wrapping_add(self.regs[sr2]),
+        };
+        self.update_flags(dr);
+        Ok(())
+    }
+
+    /// Does the bitwise 'NOT' for a value stored in a register.
+    ///
+    /// ### Arguments
+    ///
+    /// - `dr`: The destination register.
+    /// - `sr`: The register with the value to negate.
+    pub fn not(&mut self, dr: Register, sr: Register) -> Result<(), VMError> {
+        self.regs[dr] = !self.regs[sr];
+        self.update_flags(dr);
+        Ok(())
+    }
+
+    /// Does the bitwise 'AND' between two values and stores the result
+    /// in a register.
+    ///
+    /// This instruction can be executed in two different ways:
+    /// - register mode: Bitwise and with two elements that are in a register each.
+    /// - immediate mode: Uses the value of a register with the one that is embedded
+    ///   in the instruction itself.
+    ///
+    /// ### Arguments
+    ///
+    /// - `dr`: The destination register.
+    /// - `sr1`: The register with the first operand.
+    /// - `operand`: The register or the sign extended imm5 section with the second operand.
+    pub fn and(&mut self, dr: Register, sr1: Register, operand: Operand) -> Result<(), VMError> {
+        self.regs[dr] = match operand {
+            Operand::Immediate(imm5) => self.regs[sr1] & imm5.cast_unsigned(),
+            Operand::Register(sr2) => self.regs[sr1] & self.regs[sr2],
+        };
+        self.update_flags(dr);
+        Ok(())
+    }
+
+    /// Changes the PC register value depending on the value of the Cond register.
+    /// If any of the condition flags selected in `nzp` is set in the Cond register,
+    /// the offset gets added to the PC register.
+    ///
+    /// ### Arguments
+    ///
+    /// - `nzp`: The condition flags that make the branch be taken.
+    /// - `offset`: The sign extended PCoffset9 section.
+    pub fn branch(&mut self, nzp: u16, offset: i16) -> Result<(), VMError> {
+        if branch_taken(nzp, self.regs[Register::Cond]) {
+            self.regs[Register::PC] = self.regs[Register::PC].wrapping_add_signed(offset);
+        }
+        Ok(())
+    }
+
+    /// Runs a branch that jumps to itself, which only an interrupt can get out
+    /// of. Instead of spinning at full speed, it waits a bit when the keyboard
+    /// interrupt could get it out but there is no key for it yet. The keyboard
+    /// is polled before each instruction.
+    fn spin_wait(&mut self, nzp: u16) -> Result<(), VMError> {
+        self.branch(nzp, -1)?;
+        if self.keyboard_interrupt_allowed() && !self.key_ready() {
+            thread::sleep(SPIN_WAIT);
+        }
+        Ok(())
+    }
+
+    /// Switches to supervisor mode, moving R6 to the supervisor stack and
+    /// pushing the PSR and the PC on it, in that order
+    fn enter_supervisor(&mut self) -> Result<(), VMError> {
+        let psr = self.psr();
+        if self.privilege() == Privilege::User {
+            self.processor.saved_usp = self.regs[Register::R6];
+            self.regs[Register::R6] = self.processor.saved_ssp;
+        }
+        self.processor.psr &= !PSR_USER;
+        for value in [psr, self.regs[Register::PC]] {
+            self.regs[Register::R6] = self.regs[Register::R6].wrapping_sub(1);
+            self.write_memory(self.regs[Register::R6], value)?;
+        }
+        Ok(())
+    }
+
+    /// Returns from a trap or interrupt routine, popping the PC and then the
+    /// PSR from the supervisor stack that R6 points to. The condition flag is
+    /// restored from the 3 rightmost bits of the PSR, and when the PSR is
+    /// the one of user mode R6 goes back to the user stack.
+    ///
+    /// ### Returns
+    ///
+    /// A Result indicating whether the operation failed, which is a
+    /// VMError::PrivilegeViolation when running in user mode, or a
+    /// VMError::StackUnderflow when the stack does not hold both values.
+    pub fn return_from_interrupt(&mut self) -> Result<(), VMError> {
+        if self.privilege() == Privilege::User {
+            return Err(VMError::PrivilegeViolation(String::from(
+                "RTI can only run in supervisor mode",
+            )));
+        }
+        let sp = self.regs[Register::R6];
+        let in_stack = sp >= self.supervisor_stack.start
+            && sp
+                .checked_add(2)
+                .is_some_and(|end| end <= self.supervisor_stack.end);
+        if !in_stack {
+            return Err(VMError::StackUnderflow(sp));
+        }
+        self.regs[Register::PC] = self.read_memory(sp)?;
+        let psr = self.read_memory(sp.wrapping_add(1))?;
+        self.regs[Register::Cond] = psr & THREE_BIT_MASK;
+        self.regs[Register::R6] = sp.wrapping_add(2);
+        self.processor.psr = psr & !THREE_BIT_MASK;
+        if self.privilege() == Privilege::User {
+            self.processor.saved_ssp = self.regs[Register::R6];
+            self.regs[Register::R6] = self.processor.saved_usp;
+        }
+        Ok(())
+    }
+
+    /// Changes the PC with the value of the base register of the instruction
+    pub fn jump(&mut self, base: Register) -> Result<(), VMError> {
+        self.regs[Register::PC] = self.regs[base];
+        // A RET going back to where the last call came from, or skipping the
+        // word after it, returns from that call
+        let target = self.regs[Register::PC];
+        if base == Register::R7
+            && self
+                .call_stack
+                .last()
+                .is_some_and(|ret| *ret == target || ret.wrapping_add(1) == target)
+        {
+            self.call_stack.pop();
+        }
+        Ok(())
+    }
+
+    /// Saves the incremented PC in R7 as the return address and jumps to the
+    /// subroutine at `target`. For JSR the target is the PC plus the PCoffset11
+    /// section, and for JSRR it is the value of the base register, read before
+    /// R7 changes.
+    pub fn jump_register(&mut self, target: u16) -> Result<(), VMError> {
+        self.regs[Register::R7] = self.regs[Register::PC];
+        self.regs[Register::PC] = target;
+        if let Some(max_depth) = self.max_call_depth {
+            self.call_stack.push(self.regs[Register::R7]);
+            if self.call_stack.len() > max_depth {
+                self.running = false;
+                self.last_stop_reason = Some(StopReason::CallDepthExceeded(self.call_stack.len()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads a value into a register using indirect addressing.
+    /// First, it computes a memory address by adding the offset to the current PC,
+    /// and reads the value at that memory address. This value is treated as the final memory address.
+    /// Then, it loads the value stored at that final memory address into the destination register.
+    ///
+    /// ### Arguments
+    ///
+    /// - `dr`: The destination register.
+    /// - `offset`: The sign extended PCoffset9 section.
+    pub fn load_indirect(&mut self, dr: Register, offset: i16) -> Result<(), VMError> {
+        // Add the offset to PC to get the memory location we need to
+        // look at for the final address
+        let address_of_final_address = self.regs[Register::PC].wrapping_add_signed(offset);
+        let final_address = self.read_memory(address_of_final_address)?;
+        self.regs[dr] = self.read_memory(final_address)?;
+        self.update_flags(dr);
+        Ok(())
+    }
+
+    /// Loads a value from a location in memory and stores the loaded value into a register
+    pub fn load(&mut self, dr: Register, offset: i16) -> Result<(), VMError> {
+        // Calculate the memory address to read
+        let address = self.regs[Register::PC].wrapping_add_signed(offset);
+        self.regs[dr] = self.read_memory(address)?;
+        self.update_flags(dr);
+        Ok(())
+    }
+
+    /// Loads a value that is located in a memory address.  This value is formed by
+    /// adding the value on the base register and the one in the offset6 section. Then,
+    /// memory is read at this value and that is set into a desired register.
+    pub fn load_register(
+        &mut self,
+        dr: Register,
+        base: Register,
+        offset: i16,
+    ) -> Result<(), VMError> {
+        // Calculate the memory address to read
+        let address = self.regs[base].wrapping_add_signed(offset);
+        self.regs[dr] = self.read_memory(address)?;
+        self.update_flags(dr);
+        Ok(())
+    }
+
+    /// Loads a value into a register. This value is created by adding the value of the PC and the
+    /// one in the PCoffset9 section, which is formed by the 9 rightmost bits in the intruction encoding.
+    pub fn load_effective_address(&mut self, dr: Register, offset: i16) -> Result<(), VMError> {
+        self.regs[dr] = self.regs[Register::PC].wrapping_add_signed(offset);
+        self.update_flags(dr);
+        Ok(())
+    }
+
+    /// Reads a value from a register and stores it into memory. This address
+    /// is created from the addition of the PC and the PCoffset9 section
+    pub fn store(&mut self, sr: Register, offset: i16) -> Result<(), VMError> {
+        let address = self.regs[Register::PC].wrapping_add_signed(offset);
+        let new_val = self.regs[sr];
+        self.write_memory(address, new_val)
+    }
+
+    /// Reads a value from a register and stores it into memory. This address
+    /// is taken indirectly from the instruction. By adding the PC and the PCoffset9 section
+    /// (the rightmost 9 bits of the instruction enconding) we get the first memory
+    /// address, then if we read it we get the final address. That
+    /// final address is the one that is going to get written.
+    pub fn store_indirect(&mut self, sr: Register, offset: i16) -> Result<(), VMError> {
+        // Get the first address
+        let first_address = self.regs[Register::PC].wrapping_add_signed(offset);
+        // Read the first address, get the second one and write on it
+        let final_address = self.read_memory(first_address)?;
+        let new_val = self.regs[sr];
+        self.write_memory(final_address, new_val)
+    }
+
+    /// Reads a value from a register and stores it into memory. By adding
+    /// the value on the base register and the value in the
+    /// offset6 section we get the memory address. That address is the one that
+    /// is going to get written.
+    pub fn store_register(
+        &mut self,
+        sr: Register,
+        base: Register,
+        offset: i16,
+    ) -> Result<(), VMError> {
+        let address = self.regs[base].wrapping_add_signed(offset);
+        let new_val = self.regs[sr];
+        self.write_memory(address, new_val)
+    }
+
+    /// Executes the desired trap routine. In the instruction encoding the
+    /// trapvect8 section can be found in the 8 rightmost bits, and from there
+    /// we can get the trap code that will tell us which of the trap routines
+    /// we have to execute.
+    pub fn trap(&mut self, vector: u8) -> Result<(), VMError> {
+        self.regs[Register::R7] = self.regs[Register::PC];
+        if let Some(handler) = self.custom_traps.get(&vector).copied() {
+            return handler(self);
+        }
+        if self.trap_mode == TrapMode::MemoryVector {
+            if self.privilege() == Privilege::User {
+                self.enter_supervisor()?;
+            }
+            // Jump to the routine whose address is in the trap vector table
+            self.regs[Register::PC] = self.read_memory(u16::from(vector))?;
+            return Ok(());
+        }
+        let trap_code = TrapCode::try_from(u16::from(vector))?;
+        let reads_input = matches!(trap_code, TrapCode::GetC | TrapCode::In);
+        if reads_input
+            && self.queued_input.is_empty()
+            && let Some(key) = self.source_key()
+        {
+            match key {
+                Some(key) => self.queued_input.push_back(key),
+                None => {
+                    // There is no key yet, so the TRAP runs again in the next step.
+                    // A replayed key comes after some more steps, but the key
+                    // source is waited for a bit instead of spinning at full speed.
+                    self.regs[Register::PC] = self.regs[Register::PC].wrapping_sub(1);
+                    if self.replayed_input.is_empty() {
+                        thread::sleep(SPIN_WAIT);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        // Take the I/O out of the VM while the routine runs, so it can
+        // borrow the VM mutably at the same time. The queued characters
+        // are read before the ones of the input.
+        let mut input = mem::replace(&mut self.input, Box::new(empty()));
+        let mut queued = mem::take(&mut self.queued_input);
+        let mut output = mem::replace(&mut self.output, Box::new(sink()));
+        let mut reader = (&mut queued).chain(&mut input);
+        let result = match trap_code {
+            TrapCode::GetC => self.get_c(&mut reader),
+            TrapCode::Out => self.out(&mut output).map(|()| None),
+            TrapCode::Puts => self.puts(&mut output).map(|()| None),
+            TrapCode::In => self.trap_in(&mut output, &mut reader),
+            TrapCode::PutsP => self.puts_p(&mut output).map(|()| None),
+            TrapCode::Halt => self.halt(&mut output).map(|()| None),
+        };
+        self.input = input;
+        self.queued_input = queued;
+        self.output = output;
+        // Only a key the routine consumed is logged, not one it was interrupted waiting for
+        if let Some(key) = result? {
+            self.log_input(key);
+        }
+        Ok(())
+    }
+
+    /// Flushes everything written into the output so far, without writing
+    /// anything else. It is the routine of the trap at `FLUSH_VECTOR`.
+    pub fn flush_output(&mut self) -> Result<(), VMError> {
+        stdout_flush(&mut self.output)
+    }
+
+    /// Stops the program with StopReason::UserAbort and the code in R0, after
+    /// flushing whatever it wrote. `run` returns it as a VMError::UserAbort.
+    /// It is the routine of the trap at `ABORT_VECTOR`, and it can be put at
+    /// any other vector with `with_custom_trap`.
+    pub fn abort(&mut self) -> Result<(), VMError> {
+        stdout_flush(&mut self.output)?;
+        self.running = false;
+        self.last_stop_reason = Some(StopReason::UserAbort(self.regs[Register::R0]));
+        Ok(())
+    }
+
+    /// Reads the null-terminated string that starts at `addr`, with one
+    /// character per memory location like the ones PUTS writes. The memory is
+    /// read without the side effects of reading device registers, and the
+    /// bytes are decoded as UTF-8, replacing invalid sequences.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the string, a VMError::UnterminatedString if there is
+    /// no x0000 in the first `max_len` locations, or a VMError::Conversion if a
+    /// location holds a value that does not fit in a byte.
+    pub fn read_string(&self, addr: u16, max_len: usize) -> Result<String, VMError> {
+        let (words, terminated) = string_words(addr, max_len, |c_addr| Ok(self.mem.peek(c_addr)))?;
+        let bytes = words
+            .into_iter()
+            .map(|c| {
+                u8::try_from(c).map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))
+            })
+            .collect::<Result<Vec<u8>, VMError>>()?;
+        if !terminated {
+            return Err(VMError::UnterminatedString(addr));
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Writes a string into memory starting at `addr`, with each byte of its
+    /// UTF-8 encoding in its own memory location followed by an x0000, so PUTS
+    /// writes the same bytes and `read_string` reads it back.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the address right after the x0000. Nothing is
+    /// written if the string goes past the last memory address.
+    pub fn write_string(&mut self, addr: u16, s: &str) -> Result<u16, VMError> {
+        let words: Vec<u16> = s.bytes().map(u16::from).chain([NULL]).collect();
+        self.load_words(addr, &words)?;
+        let len = u16::try_from(words.len())
+            .map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))?;
+        Ok(addr.wrapping_add(len))
+    }
+
+    /// Shifts R0 by the amount in R1, read as a signed number: positive amounts
+    /// shift left and negative ones shift right. When R2 is 0 the right shifts
+    /// are logical, filling with zeros, and otherwise they are arithmetic,
+    /// filling with the sign bit. Shifting 16 or more places leaves only the
+    /// fill. The result stays in R0 and sets the condition flag. It is the
+    /// routine of the trap at `SHIFT_VECTOR`.
+    pub fn shift(&mut self) -> Result<(), VMError> {
+        let value = self.regs[Register::R0];
+        let amount = self.regs[Register::R1].cast_signed();
+        let distance = u32::from(amount.unsigned_abs());
+        let result = if amount >= 0 {
+            value.checked_shl(distance).unwrap_or(0)
+        } else if self.regs[Register::R2] != 0 {
+            // Shifting the sign bit 15 places already fills every bit with it
+            (value.cast_signed() >> distance.min(15)).cast_unsigned()
+        } else {
+            value.checked_shr(distance).unwrap_or(0)
+        };
+        self.regs[Register::R0] = result;
+        self.update_flags(Register::R0);
+        Ok(())
+    }
+
+    /// Reads one character from the stdin.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the key that was read, or None if the interrupt
+    /// flag was raised while waiting for it.
+    pub fn get_c(&mut self, reader: &mut impl Read) -> Result<Option<u8>, VMError> {
+        let Some(key) = self.wait_for_key(reader)? else {
+            self.stop_waiting_trap();
+            return Ok(None);
+        };
+        let char: u16 = key.into();
+        self.regs[Register::R0] = char;
+        if self.getc_updates_flags {
+            self.update_flags(Register::R0);
+        }
+        Ok(Some(key))
+    }
+
+    /// Writes a single character into stdout.
+    pub fn out(&mut self, writer: &mut impl Write) -> Result<(), VMError> {
+        let c: u8 = self.regs[Register::R0]
+            .try_into()
+            .map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))?;
+        self.write_output(c, writer)?;
+        Ok(())
+    }
+
+    /// Writes the prompt into the writer and reads an input character from the stdin.
+    ///
+    /// ### Returns
+    ///
+    /// A Result containing the key that was read, or None if the interrupt
+    /// flag was raised while waiting for it.
+    pub fn trap_in(
+        &mut self,
+        writer: &mut impl Write,
+        reader: &mut impl Read,
+    ) -> Result<Option<u8>, VMError> {
+        if let Some(prompt) = self.in_prompt.clone() {
+            for &c in prompt.as_bytes() {
+                self.write_output(c, writer)?;
+            }
+            // The prompt has to be seen before waiting for the character
+            stdout_flush(writer)?;
+        }
+        let Some(key) = self.wait_for_key(reader)? else {
+            self.stop_waiting_trap();
+            return Ok(None);
+        };
+        self.write_output(key, writer)?;
+        stdout_flush(writer)?;
+        self.regs[Register::R0] = key.into();
+        self.update_flags(Register::R0);
+        Ok(Some(key))
+    }
+
+    /// Writes a null-terminated string into stdout. The characters are contained in consecutive memory locations,
+    /// one character per memory location, starting with the address specified in R0. Writing
+    /// terminates with the occurrence of x0000 in a memory location. If there is none in the
+    /// maximum length of a string, it fails with VMError::UnterminatedString after writing them.
+    pub fn puts(&mut self, writer: &mut impl Write) -> Result<(), VMError> {
+        let start = self.regs[Register::R0];
+        let (words, terminated) = string_words(start, self.max_string_len, |c_addr| {
+            self.read_memory(c_addr)
+        })?;
+        for c in words {
+            // Parse it into a u8 and write it
+            let char: u8 = c
+                .try_into()
+                .map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))?;
+            self.write_output(char, writer)?;
+        }
+        stdout_flush(writer)?;
+        if !terminated {
+            return Err(VMError::UnterminatedString(start));
+        }
+        Ok(())
+    }
+
+    /// Writes a null-terminated string into stdout. The characters are contained in consecutive memory locations,
+    /// but this time there are two characters per memory location, starting with the address specified in R0. The
+    /// character in the low byte of each location is written first. If the high byte of a location is x00 that
+    /// character is skipped, which happens with strings of odd length. Writing terminates with the occurrence of
+    /// x0000 in a memory location, and fails like PUTS when there is none in the maximum length of a string.
+    pub fn puts_p(&mut self, writer: &mut impl Write) -> Result<(), VMError> {
+        let start = self.regs[Register::R0];
+        let (words, terminated) = string_words(start, self.max_string_len, |c_addr| {
+            self.read_memory(c_addr)
+        })?;
+        for c in words {
+            // Get the first character in the memory location (the 8 rightmost bits)
+            let char1 = (c & 0xFF)
+                .try_into()
+                .map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))?;
+            self.write_output(char1, writer)?;
+            // Get the second character in the same memory location (the 8 leftmost bits)
+            let char2 = (c >> 8)
+                .try_into()
+                .map_err(|e: TryFromIntError| VMError::Conversion(e.to_string()))?;
+            if char2 != 0x00 {
+                self.write_output(char2, writer)?;
+            }
+        }
+        stdout_flush(writer)?;
+        if !terminated {
+            return Err(VMError::UnterminatedString(start));
+        }
+        Ok(())
+    }
+
+    /// Writes a character produced by the program, translating
+    /// the newlines if it was enabled
+    fn write_output(&mut self, c: u8, writer: &mut impl Write) -> Result<(), VMError> {
+        self.bytes_written = self.bytes_written.saturating_add(1);
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.push(char::from(c));
+        }
+        if self.translate_newlines && c == b'\n' {
+            return stdout_write(b"\r\n", writer);
+        }
+        if self.sanitize_output
+            && !(c.is_ascii_graphic() || matches!(c, b' ' | b'\n' | b'\r' | b'\t'))
+        {
+            return stdout_write(format!("\\x{:02X}", c).as_bytes(), writer);
+        }
+        stdout_write(&[c], writer)
+    }
+
+    /// Writes on stdout th word 'HALT' to notify the user that the program is stopping
+    /// and changes the 'running' flag to false. This is the flag that is used in the
+    /// main loop to know if the program needs to continue processing instructions or not.
+    ///
+    /// Whatever the program wrote before is flushed first, so it always comes before
+    /// the banner. In quiet mode the banner is not written.
+    pub fn halt(&mut self, writer: &mut impl Write) -> Result<(), VMError> {
+        stdout_flush(writer)?;
+        if !self.quiet {
+            let s = "HALT\n".as_bytes();
+            stdout_write(s, writer)?;
+            stdout_flush(writer)?;
+        }
+        // Change the flag so the main loop stops
+        self.running = false;
+        self.last_stop_reason = Some(StopReason::HaltTrap);
+        Ok(())
+    }
+}
+
+impl Default for VM {
+    /// Creates a VM instance with all the registers and
+    /// memory locations set to 0.
+    ///
+    /// This is used for easier testing
+    fn default() -> Self {
+        let flush: TrapHandler = Self::flush_output;
+        let shift: TrapHandler = Self::shift;
+        let abort: TrapHandler = Self::abort;
+        Self {
+            mem: Memory::default(),
+            regs: Registers::default(),
             running: true,
+            last_stop_reason: None,
+            history: None,
+            history_limit: 0,
+            cond_history: None,
+            cond_history_limit: 0,
+            trap_mode: TrapMode::Native,
+            continue_on_error: false,
+            recovered_errors: Vec::new(),
+            recovered_error_count: 0,
+            reserved_bits: ReservedBitsPolicy::Lenient,
+            decode_warnings: Vec::new(),
+            load_warnings: Vec::new(),
+            self_modify_check: false,
+            self_modifying_writes: Vec::new(),
+            getc_updates_flags: true,
+            in_prompt: Some(String::from(IN_PROMPT)),
+            quiet: false,
+            translate_newlines: false,
+            sanitize_output: false,
+            opcode_counts: None,
+            memory_stats: None,
+            device_log: None,
+            interrupt_flag: None,
+            polled_input: false,
+            last_instruction: None,
+            last_loaded_end: None,
+            loaded_segments: Vec::new(),
+            crash_guard: false,
+            call_stack: Vec::new(),
+            max_call_depth: Some(MAX_CALL_DEPTH),
+            trace: None,
+            symbols: SymbolTable::new(),
+            supervisor_stack: SUPERVISOR_STACK,
+            processor: ProcessorState {
+                psr: 0,
+                saved_usp: 0,
+                saved_ssp: SUPERVISOR_STACK.end,
+            },
+            headless: false,
+            cycles: 0,
+            cycle_costs: HashMap::new(),
+            cycle_count: 0,
+            bytes_written: 0,
+            stats: false,
+            strict_halt: false,
+            cycle_limit: None,
+            max_string_len: MAX_STRING_LEN,
+            pad_odd_images: false,
+            custom_traps: HashMap::from([
+                (FLUSH_VECTOR, flush),
+                (SHIFT_VECTOR, shift),
+                (ABORT_VECTOR, abort),
+            ]),
+            queued_input: VecDeque::new(),
+            key_source: None,
+            key_pushed: false,
+            input_log: None,
+            transcript: None,
+            replayed_input: VecDeque::new(),
+            input: Box::new(stdin()),
+            output: Box::new(stdout()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        io::{BufWriter, Cursor},
+        rc::Rc,
+    };
+
+    use super::*;
+    use crate::{
+        checksum::sha256_hex,
+        hardware::{DDR_ADDR, KBDR_ADDR},
+        utils::SharedBuffer,
+    };
+
+    #[test]
+    /// Test if doing the bitwise 'AND' with register mode
+    /// gets the correct result
+    fn and_with_register_mode() {
+        let sr1 = 0xFFFF;
+        let sr2 = 0x0000;
+        let result = 0x0000;
+        // Create the registers and set the values on R1 and R2
+        let mut vm = VM::new();
+        vm.regs[Register::R1] = sr1;
+        vm.regs[Register::R2] = sr2;
+        // The instruction will have the following encoding:
+        // 0 1 0 1 0 0 0 0 0 1 0 0 0 0 1 0
+        let instr = 0x5042;
+        let _ = vm.execute(instr);
+
+        // Check if in R0 we have the desired result
+        assert_eq!(vm.regs[Register::R0], result);
+    }
+
+    #[test]
+    /// Test if doing the bitwise 'AND' with immediate mode
+    /// gets the correct result
+    fn and_with_immediate_mode() {
+        let sr1 = 0xFFFF;
+        let result = 0x0000;
+        // Create the registers and set the value on R1
+        let mut vm = VM::new();
+        vm.regs[Register::R1] = sr1;
+        // The instruction will have the following encoding:
+        // 0 1 0 1 0 0 0 0 0 1 0 0 0 0 0 0
+        let instr = 0x5040;
+        let _ = vm.execute(instr);
+
+        // Check if in R0 we have the desired result
+        assert_eq!(vm.regs[Register::R0], result);
+    }
+
+    #[test]
+    /// Test if bitwise 'NOT' actually negates all the bits
+    /// in a register
+    fn bitwise_not_negates_all_bits() {
+        let sr = 0xFFFF;
+        let result = 0x0000;
+        // Create the registers and set the value on R1
+        let mut vm = VM::new();
+        vm.regs[Register::R1] = sr;
+        // The instruction will have the following encoding:
+        // 1 0 0 1 0 0 0 0 0 1 1 1 1 1 1 1
+        let instr = 0x907F;
+        let _ = vm.execute(instr);
+
+        // Check if in R0 we have the desired result
+        assert_eq!(vm.regs[Register::R0], result);
+    }
+
+    #[test]
+    /// Test if branch changes the PC for condition
+    /// flag set to positive
+    fn branch_changes_pc_with_pos_cond_flag() {
+        // Create the registers and set the value on register Cond
+        let mut vm = VM::default();
+        vm.regs[Register::Cond] = CondFlag::Pos.value();
+        // The instruction will have the following encoding:
+        // 0 0 0 0 0 0 1 0 0 0 0 0 0 0 0 1
+        let instr = 0x0201;
+        let _ = vm.execute(instr);
+
+        // Check if the PC register was set to 1
+        assert_eq!(vm.regs[Register::PC], 0x0001);
+    }
+
+    #[test]
+    /// Test if branch changes the PC for condition
+    /// flag set to zero
+    fn branch_changes_pc_with_zro_cond_flag() {
+        // Create the registers and set the value on register Cond
+        let mut vm = VM::default();
+        vm.regs[Register::Cond] = CondFlag::Zro.value();
+        // The instruction will have the following encoding:
+        // 0 0 0 0 0 1 0 0 0 0 0 0 0 0 0 1
+        let instr = 0x0401;
+        let _ = vm.execute(instr);
+
+        // Check if the PC register was set to 1
+        assert_eq!(vm.regs[Register::PC], 0x0001);
+    }
+
+    #[test]
+    /// Test if branch changes the PC for condition
+    /// flag set to negative
+    fn branch_changes_pc_with_neg_cond_flag() {
+        // Create the registers and set the value on register Cond
+        let mut vm = VM::default();
+        vm.regs[Register::Cond] = CondFlag::Neg.value();
+        // The instruction will have the following encoding:
+        // 0 0 0 0 1 0 0 0 0 0 0 0 0 0 0 1
+        let instr = 0x0801;
+        let _ = vm.execute(instr);
+
+        // Check if the PC register was set to 1
+        assert_eq!(vm.regs[Register::PC], 0x0001);
+    }
+
+    #[test]
+    /// Test if jump changes the value of the PC is set
+    /// to the value of the register specified in the
+    /// encoding
+    fn jump_changes_pc() {
+        // Create the registers and set the value of R1 register
+        let mut vm = VM::new();
+        let result = 0xFFFF;
+        vm.regs[Register::R1] = result;
+        // The instruction will have the following encoding:
+        // 1 1 0 0  0 0 0 0  0 1 0 0  0 0 0 0
+        let instr = 0xC040;
+        let _ = vm.execute(instr);
+
+        // Check if the PC was set with the value that R1 had
+        assert_eq!(vm.regs[Register::PC], result);
+    }
+
+    #[test]
+    /// Test if the PC register adds the desired value to the PC
+    /// when using the jump register instruction with the long
+    /// flag set. With this flag being set, means we have 11 bits
+    /// to choose a value for our PC register.
+    fn jump_register_adds_pc_with_long_flag() {
+        let mut vm = VM::default();
+        let result = 0x03FF;
+        // The instruction will have the following encoding:
+        // 0 1 0 0  1 0 1 1  1 1 1 1  1 1 1 1
+        let instr = 0x4BFF;
+        let _ = vm.execute(instr);
+
+        // Check if the PC register was set to the value
+        // embedded on the encoding
+        assert_eq!(vm.regs[Register::PC], result);
+    }
+
+    #[test]
+    /// Test if the PC register substracts the desired value to the PC
+    /// when using the jump register instruction with the long
+    /// flag set and a negative value in the PCoffset11 section.
+    /// With this flag being set, means we have 11 bits
+    /// to choose a value for our PC register.
+    ///
+    /// In this case we will set the PC to 10 and we will substract 3
+    /// so it sets to 7.
+    fn jump_register_substracts_pc_with_long_flag() {
+        // Create the registers and set the pc to 10
+        let mut vm = VM::new();
+        vm.regs[Register::PC] = 0x000A;
+        let result = 0x0007;
+        // The instruction will have the following encoding:
+        // 0 1 0 0  1 1 1 1  1 1 1 1  1 1 0 1
+        let instr = 0x4FFD;
+        let _ = vm.execute(instr);
+
+        // Check if the PC register was set to the value
+        // embedded on the encoding
+        assert_eq!(vm.regs[Register::PC], result);
+    }
+
+    #[test]
+    /// Test if the PC register changes to the desired value
+    /// when using the jum register instruction without the long
+    /// flag set. This means that the value for the PC register
+    /// will be take from one of the registers.
+    fn jump_register_changes_pc_without_long_flag() {
+        // Create the registers and set the for the PC on R1
+        let mut vm = VM::new();
+        let result = 0x07FF;
+        vm.regs[Register::R1] = result;
+        // The instruction will have the following encoding:
+        // 0 1 0 0  0 0 0 0  0 1 0 0  0 0 0 0
+        let instr = 0x4040;
+        let _ = vm.execute(instr);
+
+        // Check if the PC register was set to the value
+        // embedded on the encoding
+        assert_eq!(vm.regs[Register::PC], result);
+    }
+
+    #[test]
+    /// Test if the jump register instruction stores the PC
+    /// value into the R7 register before it is changed to a
+    /// new value.
+    fn jump_register_stores_pc_on_r7() {
+        // Create the registers and set the value of the PC
+        let result = 0x000A;
+        let mut vm = VM::new();
+        vm.regs[Register::PC] = result;
+
+        // Check if R7 has a different value before calling the instruction
+        assert_ne!(vm.regs[Register::R7], result);
+        // Run the instruction
+        let _ = vm.execute(0x4040);
+        // Check if R7 changed its value to the one the PC had
+        assert_eq!(vm.regs[Register::R7], result);
+    }
+
+    #[test]
+    /// Test if load indirect instruction changes the value of a register
+    /// with one that was in a place in memory.
+    ///
+    /// We seted in the PCoffset9 section the value 5 and the PC to 10.
+    /// So when adding this we get the memory address 15 where we
+    /// seted to be the address where the result will be found. This
+    /// address will be 20, so the instruction reads that memory address
+    /// and loads the value of it to the register indicated on the instruction
+    /// (register 1 in this case)
+    fn load_indirect_changes_register_value() {
+        // Create the memory and set the values for the addresses
+        let mut vm = VM::new();
+        let first_address: u16 = 0x000F;
+        let result_address = 0x0014;
+        let result = 0x0001;
+        let _ = vm.mem.write(first_address, result_address);
+        let _ = vm.mem.write(result_address, result);
+        // Set the value of pc to 10.
+        vm.regs[Register::PC] = 0x000A;
+        // The instruction will have the following encoding:
+        // 1 0 1 0  0 0 1 0  0 0 0 0  0 1 0 1
+        let instr = 0xA205;
+        let _ = vm.execute(instr);
+
+        // Check if R1 has the value that was on memory in 'result_address'
+        assert_eq!(vm.regs[Register::R1], result);
+    }
+
+    #[test]
+    /// Test if load (this time without indirection) instruction changes the
+    /// value of the desired register to the one on a memory address.
+    ///
+    /// This time there is no indirection, so we set the pc to 10, add the value
+    /// 5 into the PCoffset9 section and with that we get the address where
+    /// we will find the value for our register.
+    fn load_changes_register_value() {
+        // Create the memory and set the values for the address
+        let mut vm = VM::new();
+        let result = 0x0001;
+        let address: u16 = 0x000F;
+        let _ = vm.mem.write(address, result);
+        // Set the value of PC to 10.
+        vm.regs[Register::PC] = 0x000A;
+        // The instruction will have the following encoding:
+        // 0 0 1 0  0 0 1 0  0 0 0 0  0 1 0 1
+        let instr = 0x2205;
+        let _ = vm.execute(instr);
+
+        // Check if R1 has the value that was on memory in 'result_address'
+        assert_eq!(vm.regs[Register::R1], result);
+    }
+
+    #[test]
+    /// Test if load register instruction changes the value of the desired
+    /// register to the one on a memory address that is formed from the value
+    /// in a register plus the one on the offset6 section.
+    ///
+    /// Register 0 will have the value 10 while in the offset 6 section we will
+    /// have the value 5. So then in memory address 15 we will have the value
+    /// 1 that will be set to the register 1.
+    fn load_register_changes_register_value() {
+        // Create the memory and set the values for the address
+        let mut vm = VM::new();
+        let result = 0x0001;
+        let address: u16 = 0x000F;
+        let _ = vm.mem.write(address, result);
+        // Set the value of pc to 10.
+        vm.regs[Register::R0] = 0x000A;
+        // The instruction will have the following encoding:
+        // 0 1 1 0  0 0 1 0  0 0 0 0  0 1 0 1
+        let instr = 0x6205;
+        let _ = vm.execute(instr);
+
+        // Check if R1 has the value that was on memory in 'result_address'
+        assert_eq!(vm.regs[Register::R1], result);
+    }
+
+    #[test]
+    /// Test if load effective address instruction changes the
+    /// value of the desired register to the one that comes from
+    /// the addition of the PC and the PCoffset9 section.
+    ///
+    /// PC will have the value 10 and PCoffset9 will have the value 5
+    /// so after the call of the instruction, register 1 should
+    /// have the value 15
+    fn load_effective_changes_register_value() {
+        // Create the registers and set the value of pc to 10.
+        let mut vm = VM::new();
+        vm.regs[Register::PC] = 0x000A;
+        let result: u16 = 0x000A + 0x0005;
+        // The instruction will have the following encoding:
+        // 1 1 1 0  0 0 1 0  0 0 0 0  0 1 0 1
+        let instr = 0xE205;
+        let _ = vm.execute(instr);
+
+        // Check if R1 has the value of PC + PCoffset9
+        assert_eq!(vm.regs[Register::R1], result);
+    }
+
+    #[test]
+    /// Test if store instruction changes the value in memory.
+    ///
+    /// Register 1 will have the value 1, while PC and PCoffset9 section
+    /// will both have the value 5. So memory address = PC + PCoffset9 = 10
+    /// will have the value of register 1
+    fn store_changes_value_in_memory() {
+        let pc_val = 0x0005;
+        let affected_address: u16 = pc_val + 0x0005; // PC + PCoffset9
+        // Create the registers and set the values for R1 and PC
+        let mut vm = VM::new();
+        vm.regs[Register::PC] = 0x0005;
+        vm.regs[Register::R1] = 0x0001;
+        // The instruction will have the following encoding:
+        // 0 0 1 1  0 0 1 0  0 0 0 0  0 1 0 1
+        let instr = 0x3205;
+        let _ = vm.execute(instr);
+
+        // Check if memory[PC + PCoffset9] = vm.regs[R1]
+        assert_eq!(
+            vm.mem.read(affected_address).unwrap(),
+            vm.regs[Register::R1]
+        );
+    }
+
+    #[test]
+    /// Test if store indirect instruction changes the value in memory.
+    ///
+    /// Register 1 will have the value 1, address 0x000A will have the value
+    /// 0x000F, while PC and PCoffset9 section will both have the value 5.
+    /// So the first memory address = PC + PCoffset9 = 10 = 0x000A. When we
+    /// read that address we will get the final address = 0x000F and thats
+    /// the one that will be written with the value of register 1.
+    fn store_indirect_changes_value_in_memory() {
+        // Set the value of the address 0x000A to 0x000F
+        let mut vm = VM::new();
+        let first_address: u16 = 0x000A;
+        let final_address: u16 = 0x000F;
+        let _ = vm.mem.write(first_address, final_address);
+        // Set the values of PC and R1
+        vm.regs[Register::R1] = 0x0001;
+        vm.regs[Register::PC] = 0x0005;
+        // The instruction will have the following encoding:
+        // 1 0 1 1  0 0 1 0  0 0 0 0  0 1 0 1
+        let instr = 0xB205;
+        let _ = vm.execute(instr);
+
+        // Check if 0x000F has the value of register R1
+        assert_eq!(vm.mem.read(final_address).unwrap(), vm.regs[Register::R1]);
+    }
+
+    #[test]
+    /// Test if store register instruction changes the value in memory.
+    ///
+    /// Register 1 will have value 1, register 0 and offset6 section will both
+    /// have value 5. The address = offset6 + register 0 = 0x000A will be written
+    /// with the value of register 1.
+    fn store_register_changes_value_in_memory() {
+        let mut vm = VM::new();
+        // Set the values of R1 and R0
+        vm.regs[Register::R0] = 0x0005;
+        vm.regs[Register::R1] = 0x0001;
+        // The instruction will have the following encoding:
+        // 0 1 1 1  0 0 1  0 0 0  0 0 0 1 0 1
+        let instr = 0x7205;
+        let _ = vm.execute(instr);
+
+        // Check if address 0x000A = R0 + offset6 was written with R1's value
+        let affected_address: u16 = 0x000A;
+        assert_eq!(
+            vm.mem.read(affected_address).unwrap(),
+            vm.regs[Register::R1]
+        );
+    }
+
+    #[test]
+    /// Test if by using the trap instruction, the register R7 gets the value
+    /// of the PC
+    fn trap_sets_register_7_with_pc_value() {
+        let mut vm = VM::new();
+        let pc_val = 0x0A0A;
+        vm.regs[Register::PC] = pc_val;
+        // The instruction will have the following encoding:
+        // 1 1 1 1  0 0 0 0  0 0 1 0  0 1 0 1
+        let instr = 0xF025;
+
+        let _ = vm.execute(instr);
+
+        assert_eq!(vm.regs[Register::R7], pc_val);
+    }
+
+    #[test]
+    /// Test trap instruction calls the halt trap routine
+    fn trap_calls_correct_subroutine() {
+        let mut vm = VM::new();
+        let pc_val = 0x0A0A;
+        vm.regs[Register::PC] = pc_val;
+        // The instruction will have the following encoding:
+        // 1 1 1 1  0 0 0 0  0 0 1 0  0 1 0 1
+        let instr = 0xF025;
+
+        let _ = vm.execute(instr);
+
+        // The running flag should change to false
+        assert!(!vm.running);
+    }
+
+    #[test]
+    /// Test if the data is written in the memory, starting from
+    /// the indicated address and with the data in the correct
+    /// endianess
+    fn read_image_file_writes_memory_correctly() {
+        let mut vm = VM::new();
+        let mut data: Vec<u8> = vec![0xFA, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+        vm.read_image_file(&mut data).unwrap();
+
+        let origin = 0xFA00;
+        assert_eq!(vm.mem.read(origin).unwrap(), 0x0102);
+        assert_eq!(vm.mem.read(origin + 1).unwrap(), 0x0304);
+        assert_eq!(vm.mem.read(origin + 2).unwrap(), 0x0506);
+    }
+
+    #[test]
+    /// Test if loading an image returns the origin
+    /// written in its header
+    fn read_image_returns_origin() {
+        let mut vm = VM::new();
+
+        let origin = vm.read_image("test_files/bytes.bin".to_string()).unwrap();
+
+        assert_eq!(origin, 0xFA00);
+    }
+
+    #[test]
+    /// Test if loading the arguments sets the PC to the origin of the first
+    /// image, so the first fetched instruction is the one at that address
+    fn load_arguments_sets_pc_to_first_origin() {
+        let mut vm = VM::new();
+        let args = ["lc3", "test_files/origin_x4000.obj", "test_files/bytes.bin"];
+
+        vm.load_arguments(args.map(String::from)).unwrap();
+
+        assert_eq!(vm.regs[Register::PC], 0x4000);
+        // The instruction at 0x4000 adds 1 to R0
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::R0], 1);
+    }
+
+    #[test]
+    /// Test if the PC set by the flag takes precedence
+    /// over the origin of the first image
+    fn load_arguments_pc_flag_overrides_origin() {
+        let mut vm = VM::new();
+        let args = ["lc3", "--pc", "x3000", "test_files/origin_x4000.obj"];
+
+        vm.load_arguments(args.map(String::from)).unwrap();
+
+        assert_eq!(vm.regs[Register::PC], 0x3000);
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    /// Test if an image compressed with gzip ends up in memory the
+    /// same way as the uncompressed one
+    fn read_image_decompresses_gzip_images() {
+        let mut plain_vm = VM::new();
+        let plain_origin = plain_vm
+            .read_image("test_files/2048.obj".to_string())
+            .unwrap();
+        let mut gzip_vm = VM::new();
+        let gzip_origin = gzip_vm
+            .read_image("test_files/2048.obj.gz".to_string())
+            .unwrap();
+
+        assert_eq!(gzip_origin, plain_origin);
+        assert!(gzip_vm.memory_slice() == plain_vm.memory_slice());
+        assert_eq!(gzip_vm.loaded_segments(), plain_vm.loaded_segments());
+    }
+
+    #[test]
+    /// Test if the same program stored with big-endian and little-endian
+    /// words ends up in the same memory locations with the same values
+    fn read_image_file_endian_loads_both_byte_orders() {
+        let mut big_vm = VM::new();
+        let mut big_data: Vec<u8> = vec![0x30, 0x00, 0x12, 0x34, 0xF0, 0x25];
+        big_vm
+            .read_image_file_endian(&mut big_data, Endianness::Big)
+            .unwrap();
+
+        let mut little_vm = VM::new();
+        let mut little_data: Vec<u8> = vec![0x00, 0x30, 0x34, 0x12, 0x25, 0xF0];
+        little_vm
+            .read_image_file_endian(&mut little_data, Endianness::Little)
+            .unwrap();
+
+        let origin: u16 = 0x3000;
+        for addr in origin..origin + 2 {
+            assert_eq!(
+                big_vm.mem.read(addr).unwrap(),
+                little_vm.mem.read(addr).unwrap()
+            );
+        }
+        assert_eq!(little_vm.mem.read(origin).unwrap(), 0x1234);
+        assert_eq!(little_vm.mem.read(origin + 1).unwrap(), 0xF025);
+    }
+
+    #[test]
+    /// Test if the origin and length of every loaded image and
+    /// assembled segment are recorded
+    fn loaded_segments_records_every_image() {
+        let mut vm = VM::new();
+        vm.load_image(&[0x30, 0x00, 0x12, 0x34, 0xF0, 0x25])
+            .unwrap();
+        vm.load_image(&[0x40, 0x00, 0x12, 0x34]).unwrap();
+        vm.assemble_and_load(".ORIG x5000\nHALT\n.END\n.ORIG x6000\n.BLKW 3\n.END")
+            .unwrap();
+
+        assert_eq!(
+            vm.loaded_segments(),
+            [(0x3000, 2), (0x4000, 1), (0x5000, 1), (0x6000, 3)]
+        );
+    }
+
+    #[test]
+    /// Test if an image with a byte missing in its last word fails
+    /// to load, writing nothing, unless padding is enabled
+    fn read_image_file_with_odd_length_is_truncated() {
+        let mut vm = VM::new();
+        let mut data: Vec<u8> = vec![0x30, 0x00, 0x12, 0x34, 0xF0];
+
+        assert!(matches!(
+            vm.read_image_file(&mut data.clone()),
+            Err(VMError::TruncatedImage(4))
+        ));
+        assert_eq!(vm.mem.peek(0x3000), 0);
+
+        let mut vm = VM::new().with_pad_odd_images(true);
+        vm.read_image_file(&mut data).unwrap();
+        assert_eq!(vm.mem.peek(0x3000), 0x1234);
+        assert_eq!(vm.mem.peek(0x3001), 0xF000);
+    }
+
+    #[test]
+    /// Test if the data is written in the memory, starting from
+    /// the indicated address and with the data in the correct
+    /// endianess when opening a file
+    fn read_image_reads_file_correctly_into_memory() {
+        let mut vm = VM::new();
+
+        let _ = vm.read_image("test_files/bytes.bin".to_string());
+
+        let origin = 0xFA00;
+        assert_eq!(vm.mem.read(origin).unwrap(), 0x0102);
+        assert_eq!(vm.mem.read(origin + 1).unwrap(), 0x0304);
+        assert_eq!(vm.mem.read(origin + 2).unwrap(), 0x0506);
+    }
+
+    #[test]
+    /// Test if reading an image that does not exist fails with an error
+    /// that carries both the path and the cause
+    fn read_image_with_nonexistent_path_reports_not_found() {
+        let mut vm = VM::new();
+        let path = "test_files/nonexistent.obj";
+
+        let result = vm.read_image(path.to_string());
+
+        match result {
+            Err(VMError::ImageNotFound(err_path, cause)) => {
+                assert_eq!(err_path, path);
+                assert!(!cause.is_empty());
+            }
+            _ => panic!("expected an ImageNotFound error"),
+        }
+    }
+
+    #[test]
+    /// Test result when adding two values in register mode.
+    /// One value will be in R1 and the other in R2, while
+    /// the destination register will be R0.
+    fn add_with_register_mode() {
+        let sr1 = 0x0001;
+        let sr2 = 0x0002;
+        let result = 0x0003;
+        // Create the registers and set the values on R1 and R2
+        let mut vm = VM::new();
+        vm.regs[Register::R1] = sr1;
+        vm.regs[Register::R2] = sr2;
+        // The instruction will have the following encoding:
+        // 0 0 0 1 0 0 0 0 0 1 0 0 0 0 1 0
+        let instr = 0x1042;
+        let _ = vm.execute(instr);
+
+        // Check if in R0 we have the desired result
+        assert_eq!(vm.regs[Register::R0], result);
+    }
+
+    #[test]
+    /// Test result when adding the two values in immediate mode.
+    /// One value will be in R1 and the other will be embedded in
+    /// the instruction encoding, while the destination
+    /// register will be R0.
+    fn add_with_immediate_mode() {
+        let sr1 = 0x0001;
+        let result = 0x003;
+        // Create the registers and set the value on R1
+        let mut vm = VM::new();
+        vm.regs[Register::R1] = sr1;
+        // The instruction will have the following encoding:
+        // 0 0 0 1 0 0 0 0 0 1 1 0 0 0 1 0
+        let instr = 0x1062;
+        let _ = vm.execute(instr);
+
+        // Check if in R0 we have the desired result
+        assert_eq!(vm.regs[Register::R0], result);
+    }
+
+    #[test]
+    /// Test result when adding one positve value with a
+    /// negative one. To do this adding we need to use immediate mode.
+    fn add_with_negative_value() {
+        let sr1 = 0x0001;
+        let result = 0x000;
+        // Create the registers and set the value on R1
+        let mut vm = VM::new();
+        vm.regs[Register::R1] = sr1;
+        // The instruction will have the following encoding:
+        // 0 0 0 1 0 0 0 0 0 1 1 1 1 1 1 1
+        let instr = 0x107F;
+        let _ = vm.execute(instr);
+
+        // Check if in R0 we have the desired result
+        assert_eq!(vm.regs[Register::R0], result);
+    }
+
+    #[test]
+    /// Test if the condition flag was set to POS when the
+    /// result of an addition is a positive number.
+    fn add_updates_cond_flag_to_pos() {
+        let sr1 = 0x0001;
+        let sr2 = 0x0002;
+        // Create the registers and set the values on R1 and R2
+        let mut vm = VM::new();
+        vm.regs[Register::R1] = sr1;
+        vm.regs[Register::R2] = sr2;
+        // The instruction will have the following encoding:
+        // 0 0 0 1 0 0 0 0 0 1 0 0 0 0 1 0
+        let instr = 0x1042;
+        let _ = vm.execute(instr);
+
+        assert_eq!(vm.regs[Register::Cond], CondFlag::Pos.value());
+    }
+
+    #[test]
+    /// Test if the condition flag was set to ZRO when the
+    /// result of an addition is a 0.
+    fn add_updates_cond_flag_to_zro() {
+        let sr1 = 0x0001;
+        // Create the registers and set the value on R1
+        let mut vm = VM::new();
+        vm.regs[Register::R1] = sr1;
+        // The instruction will have the following encoding:
+        // 0 0 0 1 0 0 0 0 0 1 1 1 1 1 1 1
+        let instr = 0x107F;
+        let _ = vm.execute(instr);
+
+        assert_eq!(vm.regs[Register::Cond], CondFlag::Zro.value());
+    }
+
+    #[test]
+    fn get_c_modifies_register_0_with_input_char() {
+        let char = "c";
+        let char_bytes: u16 = char.as_bytes()[0].into();
+        let mut reader = Cursor::new(char);
+        let mut vm = VM::new();
+        let _ = vm.get_c(&mut reader);
+
+        assert_eq!(vm.regs[Register::R0], char_bytes);
+    }
+
+    #[test]
+    /// Test if GETC sets the condition flag from the read
+    /// character by default
+    fn get_c_updates_flags_by_default() {
+        let mut reader = Cursor::new("c");
+        let mut vm = VM::new();
+        vm.get_c(&mut reader).unwrap();
+
+        assert_eq!(vm.regs[Register::Cond], CondFlag::Pos.value());
+    }
+
+    #[test]
+    /// Test if GETC leaves the condition flag untouched
+    /// when it was configured not to update it
+    fn get_c_without_flag_update_keeps_cond() {
+        let mut reader = Cursor::new("c");
+        let mut vm = VM::new().with_getc_updates_flags(false);
+        vm.get_c(&mut reader).unwrap();
+
+        assert_eq!(vm.regs[Register::R0], u16::from(b'c'));
+        assert_eq!(vm.regs[Register::Cond], CondFlag::Zro.value());
+    }
+
+    #[test]
+    fn trap_out_writes_register_0_value() {
+        let char = "c";
+        let char_bytes: u16 = char.as_bytes()[0].into();
+        let mut writer: Vec<u8> = Vec::new();
+        let mut vm = VM::new();
+        vm.regs[Register::R0] = char_bytes;
+        let _ = vm.out(&mut writer);
+
+        let written_val: u16 = writer[0].into();
+        assert_eq!(written_val, char_bytes);
+    }
+
+    #[test]
+    fn trap_in_writes_register_0_with_reader_value() {
+        let char = "c";
+        let char_bytes: u16 = char.as_bytes()[0].into();
+        let mut reader = Cursor::new(char);
+        let mut writer: Vec<u8> = Vec::new();
+        let mut vm = VM::new();
+        let _ = vm.trap_in(&mut writer, &mut reader);
+
+        assert_eq!(vm.regs[Register::R0], char_bytes);
+    }
+
+    #[test]
+    fn trap_in_writes_writer_with_value() {
+        let char = "c";
+        let char_bytes: u16 = char.as_bytes()[0].into();
+        let mut reader = Cursor::new(char);
+        let mut writer: Vec<u8> = Vec::new();
+        let mut vm = VM::new().with_in_prompt(None);
+        let _ = vm.trap_in(&mut writer, &mut reader);
+
+        let written_val: u16 = writer[0].into();
+        assert_eq!(written_val, char_bytes);
+    }
+
+    #[test]
+    /// Test if the prompt of IN is written into the writer before the
+    /// echoed character, and if it can be changed
+    fn trap_in_writes_prompt_before_echo() {
+        let mut writer: Vec<u8> = Vec::new();
+        let mut vm = VM::new();
+        vm.trap_in(&mut writer, &mut Cursor::new("c")).unwrap();
+        assert_eq!(writer, b"Enter a character: c");
+
+        let mut writer: Vec<u8> = Vec::new();
+        let mut vm = VM::new().with_in_prompt(Some("> "));
+        vm.trap_in(&mut writer, &mut Cursor::new("d")).unwrap();
+        assert_eq!(writer, b"> d");
+    }
+
+    #[test]
+    /// Test if the prompt of IN reaches the writer given to the VM, even a
+    /// buffered one, before the character is read
+    fn in_trap_flushes_prompt_into_vm_writer() {
+        /// Reader that saves what the output had when it was read
+        struct SnoopingReader {
+            output: SharedBuffer,
+            seen: Rc<RefCell<Vec<u8>>>,
+        }
+        impl Read for SnoopingReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                *self.seen.borrow_mut() = self.output.contents();
+                Cursor::new("z").read(buf)
+            }
+        }
+        let output = SharedBuffer::new();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let reader = SnoopingReader {
+            output: output.clone(),
+            seen: Rc::clone(&seen),
+        };
+        let mut vm = VM::new()
+            .with_input(reader)
+            .with_output(BufWriter::new(output.clone()))
+            .with_quiet(true);
+        // IN and HALT
+        vm.load_words(0x3000, &[0xF023, 0xF025]).unwrap();
+
+        vm.run().unwrap();
+
+        assert_eq!(*seen.borrow(), b"Enter a character: ");
+        assert_eq!(output.contents(), b"Enter a character: z");
+    }
+
+    #[test]
+    /// Test if the JSON state has every field with the values left
+    /// by a step, and a null last instruction before it
+    fn state_json_describes_state_after_step() {
+        let mut vm = VM::new();
+        // ADD R0, R0, #1
+        vm.load_words(0x3000, &[0x1021]).unwrap();
+        assert!(vm.state_json(0).contains("\"last_instruction\":null"));
+
+        vm.step().unwrap();
+
+        assert_eq!(
+            vm.state_json(2),
+            concat!(
+                "{\"registers\":{\"R0\":1,\"R1\":0,\"R2\":0,\"R3\":0,\"R4\":0,\"R5\":0,\"R6\":0,\"R7\":0},",
+                "\"pc\":12289,\"cond\":\"p\",\"cycles\":1,\"last_instruction\":\"ADD R0, R0, #1\",",
+                "\"memory\":{\"start\":12287,\"words\":[0,4129,0,0,0]}}"
+            )
+        );
+    }
+
+    #[test]
+    /// Test if the shift trap shifts R0 in both directions, logically and
+    /// arithmetically, setting the condition flag from the result
+    fn shift_trap_shifts_r0() {
+        // Value, amount, whether it is arithmetic, result and flag
+        let cases = [
+            (0x0003, 2, false, 0x000C, CondFlag::Pos),
+            (0x8001, 1, false, 0x0002, CondFlag::Pos),
+            (0x0001, 16, false, 0x0000, CondFlag::Zro),
+            (0xF000, -4, false, 0x0F00, CondFlag::Pos),
+            (0xF000, -4, true, 0xFF00, CondFlag::Neg),
+            (0x7000, -4, true, 0x0700, CondFlag::Pos),
+            (0x8000, -20, true, 0xFFFF, CondFlag::Neg),
+            (0x8000, -20, false, 0x0000, CondFlag::Zro),
+        ];
+
+        for (value, amount, arithmetic, result, flag) in cases {
+            let mut vm = VM::new();
+            // TRAP x27
+            vm.load_words(0x3000, &[0xF027]).unwrap();
+            vm.regs[Register::R0] = value;
+            vm.regs[Register::R1] = i16::cast_unsigned(amount);
+            vm.regs[Register::R2] = arithmetic.into();
+
+            vm.step().unwrap();
+
+            assert_eq!(
+                vm.regs[Register::R0],
+                result,
+                "shifting {:#06X} by {}",
+                value,
+                amount
+            );
+            assert_eq!(vm.regs[Register::Cond], flag.value());
+        }
+    }
+
+    #[test]
+    /// Test if the reads, writes and fetches of a program with
+    /// known loads and stores are counted separately
+    fn memory_stats_counts_loads_stores_and_fetches() {
+        let src = "
+            .ORIG x3000
+            LD R0, DATA
+            LDI R1, PTR
+            ST R0, DATA
+            STI R1, PTR
+            LEA R2, DATA
+            LDR R3, R2, #0
+            STR R3, R2, #0
+            HALT
+            DATA .FILL #7
+            PTR .FILL DATA
+            .END
+        ";
+        let mut vm = VM::new().with_output(Vec::new());
+        vm.assemble_and_load(src).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.memory_stats(), (0, 0, 0));
+
+        let mut vm = VM::new().with_output(Vec::new()).with_memory_stats(true);
+        vm.assemble_and_load(src).unwrap();
+        vm.run().unwrap();
+        // LD, LDI twice, STI once and LDR read; ST, STI and STR write
+        assert_eq!(vm.memory_stats(), (5, 3, 8));
+    }
+
+    #[test]
+    /// Test if the input recorded in a run, read by GETC and by polling
+    /// the keyboard, makes another run without input write the same output
+    fn replay_input_reproduces_recorded_run() {
+        let src = "
+            .ORIG x3000
+            GETC
+            OUT
+            POLL LDI R1, KBSR
+            BRzp POLL
+            LDI R0, KBDR
+            OUT
+            GETC
+            OUT
+            HALT
+            KBSR .FILL xFE00
+            KBDR .FILL xFE02
+            .END
+        ";
+        let recorded_output = SharedBuffer::new();
+        let mut recorded = VM::new()
+            .with_input(Cursor::new("abc"))
+            .with_output(recorded_output.clone())
+            .with_input_log(true);
+        recorded.assemble_and_load(src).unwrap();
+        recorded.run().unwrap();
+        let log = recorded.input_log().to_vec();
+        assert_eq!(log, [(1, b'a'), (3, b'b'), (7, b'c')]);
+
+        let replayed_output = SharedBuffer::new();
+        let mut replayed = VM::new()
+            .with_input(empty())
+            .with_output(replayed_output.clone())
+            .with_input_log(true);
+        replayed.assemble_and_load(src).unwrap();
+        replayed.replay_input(&log);
+        replayed.run().unwrap();
+
+        assert_eq!(replayed_output.contents(), recorded_output.contents());
+        assert_eq!(replayed.input_log(), log);
+    }
+
+    #[test]
+    /// Test if running until output stops right after the instruction
+    /// that printed, with the computation before it already done
+    fn run_until_output_stops_after_first_print() {
+        let src = "
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #15
+            ADD R0, R0, R0
+            ADD R0, R0, R0
+            ADD R0, R0, #5
+            OUT
+            ADD R1, R1, #1
+            HALT
+            .END
+        ";
+        let output = SharedBuffer::new();
+        let mut vm = VM::new().with_output(output.clone());
+        vm.assemble_and_load(src).unwrap();
+
+        assert_eq!(vm.run_until_output().unwrap(), RunOutcome::Output);
+
+        assert_eq!(output.contents(), b"A");
+        assert_eq!(vm.regs[Register::PC], 0x3006);
+        assert_eq!(vm.regs[Register::R1], 0);
+        assert!(vm.running);
+        // The HALT banner is not written by the program
+        assert_eq!(vm.run_until_output().unwrap(), RunOutcome::Halted);
+        assert_eq!(output.contents(), b"AHALT\n");
+    }
+
+    #[test]
+    /// Test if a pushed key is ready, is seen by a program polling the
+    /// KBSR without waiting for the input, and is cleared when read
+    fn push_key_is_ready_until_kbdr_read() {
+        let src = "
+            .ORIG x3000
+            POLL LDI R1, KBSR
+            BRzp POLL
+            LDI R0, KBDR
+            HALT
+            KBSR .FILL xFE00
+            KBDR .FILL xFE02
+            .END
+        ";
+        // Reading the input would fail, since it has no characters
+        let mut vm = VM::new().with_input(empty()).with_output(Vec::new());
+        vm.assemble_and_load(src).unwrap();
+        assert!(!vm.key_ready());
+
+        vm.push_key(b'g').unwrap();
+        assert!(vm.key_ready());
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert!(vm.key_ready());
+        vm.step().unwrap();
+
+        assert_eq!(vm.regs[Register::R0], u16::from(b'g'));
+        assert!(!vm.key_ready());
+    }
+
+    #[test]
+    /// Test if the cycles add up the cost of each executed operation,
+    /// and match the instructions with the default costs
+    fn cycle_count_uses_cost_table() {
+        let src = "
+            .ORIG x3000
+            AND R0, R0, #0
+            LD R1, DATA
+            ADD R0, R0, R1
+            LDI R2, PTR
+            STR R2, R0, #0
+            HALT
+            DATA .FILL x4000
+            PTR .FILL DATA
+            .END
+        ";
+        let mut plain = VM::new().with_output(Vec::new());
+        plain.assemble_and_load(src).unwrap();
+        plain.run().unwrap();
+        assert_eq!(plain.cycle_count(), 6);
+
+        let costs = HashMap::from([
+            (OpCode::Ld, 3),
+            (OpCode::Ldi, 5),
+            (OpCode::Str, 3),
+            (OpCode::Trap, 10),
+        ]);
+        let mut modeled = VM::new().with_output(Vec::new()).with_cycle_costs(costs);
+        modeled.assemble_and_load(src).unwrap();
+        modeled.run().unwrap();
+
+        // AND and ADD cost 1 each
+        assert_eq!(modeled.cycle_count(), 1 + 3 + 1 + 5 + 3 + 10);
+        assert_eq!(modeled.cycles(), 6);
+    }
+
+    #[test]
+    /// Test if a string is read up to its x0000, and if it fails when
+    /// it is longer than the maximum length
+    fn read_string_reads_until_null() {
+        let mut vm = VM::new();
+        let words: Vec<u16> = "Hi there\0".bytes().map(u16::from).collect();
+        vm.load_words(0x4000, &words).unwrap();
+
+        assert_eq!(vm.read_string(0x4000, 100).unwrap(), "Hi there");
+        assert_eq!(vm.read_string(0x4003, 5).unwrap(), "there");
+        assert_eq!(vm.read_string(0x4008, 0).unwrap(), "");
+        assert!(matches!(
+            vm.read_string(0x4000, 4),
+            Err(VMError::UnterminatedString(0x4000))
+        ));
+        vm.load_words(0x4001, &[0x0100]).unwrap();
+        assert!(matches!(
+            vm.read_string(0x4000, 100),
+            Err(VMError::Conversion(_))
+        ));
+    }
+
+    #[test]
+    /// Test if written strings are terminated, one after the other, and
+    /// read back the same, non-ASCII characters included
+    fn write_string_round_trips_with_read_string() {
+        let mut vm = VM::new();
+
+        let next = vm.write_string(0x4000, "Hello").unwrap();
+        assert_eq!(next, 0x4006);
+        assert_eq!(vm.mem.peek(0x4005), 0);
+        let after = vm.write_string(next, "año").unwrap();
+        assert_eq!(after, 0x400B);
+
+        assert_eq!(vm.read_string(0x4000, 100).unwrap(), "Hello");
+        assert_eq!(vm.read_string(next, 100).unwrap(), "año");
+        assert!(matches!(
+            vm.write_string(0xFFFE, "ab"),
+            Err(VMError::InvalidIndex(_))
+        ));
+        assert_eq!(vm.mem.peek(0xFFFE), 0);
+    }
+
+    #[test]
+    /// Test if the callback sees the PC after each step, and if the
+    /// steps stop once the program halts
+    fn step_each_calls_back_after_every_step() {
+        let mut vm = VM::new().with_output(Vec::new());
+        // ADD R0, R0, #1, BRnzp #1, a word that is skipped, then HALT
+        vm.load_words(0x3000, &[0x1021, 0x0E01, 0x0000, 0xF025])
+            .unwrap();
+        let mut pcs = Vec::new();
+
+        vm.step_each(2, |vm| pcs.push(vm.register(Register::PC)))
+            .unwrap();
+        vm.step_each(1, |vm| pcs.push(vm.register(Register::PC)))
+            .unwrap();
+        assert_eq!(pcs, [0x3001, 0x3003, 0x3004]);
+
+        vm.step_each(10, |vm| pcs.push(vm.register(Register::PC)))
+            .unwrap();
+        assert_eq!(pcs.len(), 3);
+    }
+
+    #[test]
+    /// Test if polling the KBSR, reading the KBDR and writing the DDR
+    /// are logged with the instruction that did them
+    fn device_log_records_register_accesses() {
+        let src = "
+            .ORIG x3000
+            POLL LDI R1, KBSR
+            BRzp POLL
+            LDI R0, KBDR
+            STI R0, DDR
+            ST R0, SAVED
+            HALT
+            KBSR .FILL xFE00
+            KBDR .FILL xFE02
+            DDR .FILL xFE06
+            SAVED .BLKW 1
+            .END
+        ";
+        let mut vm = VM::new()
+            .with_input(Cursor::new("k"))
+            .with_output(Vec::new())
+            .with_device_log(true);
+        vm.assemble_and_load(src).unwrap();
+
+        vm.run().unwrap();
+
+        let key = u16::from(b'k');
+        assert_eq!(
+            vm.device_log(),
+            [
+                (0x3000, DeviceAccess::Read, KBSR_ADDR, 0x8000),
+                (0x3002, DeviceAccess::Read, KBDR_ADDR, key),
+                (0x3003, DeviceAccess::Write, DDR_ADDR, key),
+            ]
+        );
+    }
+
+    #[test]
+    fn puts_writes_whole_string_on_writer() {
+        let mut writer: Vec<u8> = Vec::new();
+        let (char1, char2, char3) = ("1", "2", "3");
+        let char1_bytes: u16 = char1.as_bytes()[0].into();
+        let char2_bytes: u16 = char2.as_bytes()[0].into();
+        let char3_bytes: u16 = char3.as_bytes()[0].into();
+        let starting_address: u16 = 0x0005;
+
+        let mut vm = VM::new();
+        vm.regs[Register::R0] = starting_address;
+        let _ = vm.mem.write(starting_address, char1_bytes);
+        let _ = vm.mem.write(starting_address + 1, char2_bytes);
+        let _ = vm.mem.write(starting_address + 2, char3_bytes);
+
+        let _ = vm.puts(&mut writer);
+
+        let written_val_1: u16 = writer[0].into();
+        let written_val_2: u16 = writer[1].into();
+        let written_val_3: u16 = writer[2].into();
+        assert_eq!(written_val_1, char1_bytes);
+        assert_eq!(written_val_2, char2_bytes);
+        assert_eq!(written_val_3, char3_bytes);
+    }
+
+    #[test]
+    fn halt_changes_bool() {
+        let mut vm = VM::new();
+        let mut writer: Vec<u8> = Vec::new();
+
+        let _ = vm.halt(&mut writer);
+
+        assert!(!vm.running);
+    }
+
+    #[test]
+    fn halt_writes_on_writer() {
+        let mut vm = VM::new();
+        let mut writer: Vec<u8> = Vec::new();
+        let expected_bytes = "HALT\n".as_bytes();
+        let _ = vm.halt(&mut writer);
+
+        assert_eq!(writer, expected_bytes);
+    }
+
+    #[test]
+    /// Test if running a program until the HALT trap
+    /// records it as the reason why the VM stopped
+    fn run_until_halt_sets_stop_reason() {
+        let mut vm = VM::new().with_output(Vec::new());
+        // The first instruction is a TRAP with the HALT trap code
+        let _ = vm.mem.write(0x3000_u16, 0xF025);
+
+        assert_eq!(vm.last_stop_reason(), None);
+        vm.run().unwrap();
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::HaltTrap));
+    }
+
+    #[test]
+    /// Test if an assembled program gets loaded at its origin
+    /// and can be run until it halts
+    fn assemble_and_load_runs_program() {
+        let src = "
+            .ORIG x4000
+            AND R0, R0, #0
+            ADD R0, R0, #7
+            HALT
+            .END
+        ";
+        let mut vm = VM::new().with_output(Vec::new());
+        vm.assemble_and_load(src).unwrap();
+
+        assert_eq!(vm.regs[Register::PC], 0x4000);
+        vm.run().unwrap();
+        assert_eq!(vm.regs[Register::R0], 7);
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::HaltTrap));
+    }
+
+    /// Returns the bytes of the image of an assembled program
+    fn assembled_image(src: &str) -> Vec<u8> {
+        let [(origin, words)] = assemble(src).unwrap().try_into().unwrap();
+        image_bytes(origin, &words)
+    }
+
+    #[test]
+    /// Test if booting runs the user program through the trap
+    /// routines of the operating system until it halts
+    fn boot_runs_user_program_through_os() {
+        let src = "
+            .ORIG x3000
+            LEA R0, MSG
+            PUTS
+            LD R0, BANG
+            OUT
+            HALT
+    MSG     .STRINGZ \"Hi\"
+    BANG    .FILL x21
+        ";
+        let output = SharedBuffer::new();
+        let mut vm = VM::new().with_output(output.clone());
+
+        vm.boot(&assembled_image(src)).unwrap();
+
+        assert_eq!(output.contents(), b"Hi!HALT\n");
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::McrCleared));
+        assert_eq!(vm.mem.read(OS_USER_PC).unwrap(), 0x3000);
+    }
+
+    #[test]
+    /// Test if a PUTSP run by the operating system writes the
+    /// low byte of each word before the high one
+    fn boot_puts_p_writes_packed_string() {
+        let src = "
+            .ORIG x3000
+            LEA R0, MSG
+            PUTSP
+            HALT
+    MSG     .FILL x6261
+            .FILL x0063
+            .FILL x0000
+        ";
+        let output = SharedBuffer::new();
+        let mut vm = VM::new().with_output(output.clone());
+
+        vm.boot(&assembled_image(src)).unwrap();
+
+        assert_eq!(output.contents(), b"abcHALT\n");
+    }
+
+    #[test]
+    /// Test if writing a value without the clock enable bit into
+    /// the Machine Control Register stops the VM
+    fn clearing_mcr_stops_vm() {
+        let src = "
+            .ORIG x3000
+            AND R0, R0, #0
+            STI R0, MCR
+            ADD R0, R0, #1
+    MCR     .FILL xFFFE
+        ";
+        let mut vm = VM::new();
+        vm.assemble_and_load(src).unwrap();
+
+        vm.run().unwrap();
+
+        assert_eq!(vm.regs[Register::R0], 0);
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::McrCleared));
+    }
+
+    #[test]
+    /// Test if strict decoding records an ADD in register mode
+    /// with bits 3 and 4 set, and still runs it
+    fn strict_decode_records_unused_bits() {
+        let mut vm = VM::new().with_strict_decode(true);
+        vm.regs[Register::R1] = 1;
+        vm.regs[Register::R2] = 2;
+        // ADD R0, R1, R2 with bits 3 and 4 set
+        vm.mem.write(0x3000_u16, 0x105A).unwrap();
+        // ADD R0, R1, R2
+        vm.mem.write(0x3001_u16, 0x1042).unwrap();
+
+        vm.step().unwrap();
+        vm.step().unwrap();
+
+        assert_eq!(vm.regs[Register::R0], 3);
+        assert_eq!(vm.decode_warnings(), &[(0x3000, 0x105A)]);
+    }
+
+    #[test]
+    /// Test if an ADD in register mode with bit 3 set runs when lenient,
+    /// and fails without changing its destination when rejected
+    fn reserved_bits_policy_decides_if_add_runs() {
+        // ADD R0, R1, R2 with bit 3 set
+        let instr = 0x104A;
+        let mut lenient_vm = VM::new();
+        let mut rejecting_vm = VM::new().with_reserved_bits_policy(ReservedBitsPolicy::Reject);
+        for vm in [&mut lenient_vm, &mut rejecting_vm] {
+            vm.regs[Register::R1] = 1;
+            vm.regs[Register::R2] = 2;
+            vm.mem.write(0x3000_u16, instr).unwrap();
         }
+
+        lenient_vm.step().unwrap();
+        let rejected = rejecting_vm.step();
+
+        assert_eq!(lenient_vm.regs[Register::R0], 3);
+        assert!(lenient_vm.decode_warnings().is_empty());
+        assert!(matches!(
+            rejected,
+            Err(VMError::ReservedBits {
+                address: 0x3000,
+                instr: 0x104A
+            })
+        ));
+        assert_eq!(rejecting_vm.regs[Register::R0], 0);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::io::Cursor;
+    #[test]
+    /// Test if a subroutine that calls itself forever is stopped once it
+    /// goes past the maximum depth, while calls that return do not count
+    fn unbounded_recursion_exceeds_call_depth() {
+        let src = "
+            .ORIG x3000
+            JSR DONE
+            JSR RECURSE
+            HALT
+            RECURSE ADD R1, R1, #1
+            JSR RECURSE
+            RET
+            DONE RET
+            .END
+        ";
+        let mut vm = VM::new()
+            .with_output(Vec::new())
+            .with_max_call_depth(Some(100));
+        vm.assemble_and_load(src).unwrap();
 
-    use super::*;
+        let result = vm.run();
+
+        assert!(matches!(result, Err(VMError::CallDepthExceeded(101))));
+        assert_eq!(
+            vm.last_stop_reason(),
+            Some(StopReason::CallDepthExceeded(101))
+        );
+        assert_eq!(vm.call_depth(), 101);
+        assert_eq!(vm.regs[Register::R1], 100);
+        assert_eq!(vm.regs[Register::PC], 0x3003);
+    }
 
     #[test]
-    /// Test if doing the bitwise 'AND' with register mode
-    /// gets the correct result
-    fn and_with_register_mode() {
-        let sr1 = 0xFFFF;
-        let sr2 = 0x0000;
-        let result = 0x0000;
-        // Create the registers and set the values on R1 and R2
+    /// Test if a skip-return takes the call stack back, and stepping back
+    /// restores it, while a RET that goes back to an older call leaves the
+    /// calls made after it on the stack
+    fn call_stack_follows_unusual_returns() {
+        let src = "
+            .ORIG x3000
+            JSR SKIP
+            HALT
+            JSR OUTER
+            HALT
+            SKIP ADD R7, R7, #1
+            RET
+            OUTER ST R7, SAVED
+            JSR INNER
+            INNER LD R7, SAVED
+            RET
+            SAVED .BLKW 1
+            .END
+        ";
+        let mut vm = VM::new()
+            .with_output(Vec::new())
+            .with_history(16)
+            .with_max_call_depth(Some(2));
+        vm.assemble_and_load(src).unwrap();
+
+        vm.step().unwrap();
+        assert_eq!(vm.call_depth(), 1);
+        vm.step_back().unwrap();
+        assert_eq!(vm.call_depth(), 0);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.call_depth(), 0);
+
+        assert_eq!(vm.run_until(0x3003).unwrap(), RunOutcome::Reached);
+        assert_eq!(vm.call_depth(), 2);
+        assert_eq!(VM::new().call_depth(), 0);
+    }
+
+    #[test]
+    /// Test if the calls are tracked by default, and opting out lets a
+    /// program go past the default depth
+    fn call_depth_is_limited_by_default() {
+        let src = "
+            .ORIG x3000
+            RECURSE ADD R1, R1, #1
+            BRz DONE
+            JSR RECURSE
+            DONE HALT
+            .END
+        ";
+        let mut vm = VM::new().with_output(Vec::new());
+        vm.assemble_and_load(src).unwrap();
+
+        assert!(matches!(
+            vm.run(),
+            Err(VMError::CallDepthExceeded(depth)) if depth == MAX_CALL_DEPTH.wrapping_add(1)
+        ));
+
+        let mut vm = VM::new().with_output(Vec::new()).with_max_call_depth(None);
+        vm.assemble_and_load(src).unwrap();
+
+        vm.run().unwrap();
+
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::HaltTrap));
+        assert_eq!(vm.call_depth(), 0);
+    }
+
+    #[test]
+    /// Test if the transcript has the prompt, then the consumed key,
+    /// then what the program wrote after reading it
+    fn transcript_interleaves_input_and_output() {
+        let src = "
+            .ORIG x3000
+            LEA R0, PROMPT
+            PUTS
+            GETC
+            OUT
+            LEA R0, BYE
+            PUTS
+            HALT
+            PROMPT .STRINGZ \"Key? \"
+            BYE .STRINGZ \"!\\n\"
+            .END
+        ";
+        let mut vm = VM::new()
+            .with_input(Cursor::new("k"))
+            .with_output(Vec::new())
+            .with_transcript(true);
+        vm.assemble_and_load(src).unwrap();
+
+        vm.run().unwrap();
+
+        assert_eq!(vm.transcript(), "Key? [in:k]k!\n");
+        assert_eq!(VM::new().transcript(), "");
+    }
+
+    #[test]
+    /// Test if a program with an illegal opcode fails by default,
+    /// but reaches the HALT when continuing after errors
+    fn continue_on_error_skips_illegal_opcode() {
+        let src = "
+            .ORIG x3000
+            ADD R0, R0, #1
+            .FILL xD000
+            ADD R0, R0, #1
+            HALT
+        ";
+        let mut strict_vm = VM::new().with_output(Vec::new());
+        strict_vm.assemble_and_load(src).unwrap();
+        assert!(strict_vm.run().is_err());
+
+        let mut lenient_vm = VM::new()
+            .with_output(Vec::new())
+            .with_continue_on_error(true);
+        lenient_vm.assemble_and_load(src).unwrap();
+        lenient_vm.run().unwrap();
+
+        assert_eq!(lenient_vm.regs[Register::R0], 2);
+        assert_eq!(lenient_vm.last_stop_reason(), Some(StopReason::HaltTrap));
+        let errors = lenient_vm.recovered_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 0x3001);
+        assert_eq!(lenient_vm.recovered_error_count(), 1);
+    }
+
+    #[test]
+    /// Test if a program that keeps failing only has its first errors kept,
+    /// while all of them are counted
+    fn recovered_errors_are_capped() {
+        let mut vm = VM::new()
+            .with_output(Vec::new())
+            .with_continue_on_error(true)
+            .with_cycle_limit(3000);
+        // Illegal opcode followed by a branch back to it
+        vm.assemble_and_load(".ORIG x3000\n.FILL xD000\nBR #-2")
+            .unwrap();
+
+        vm.run().unwrap();
+
+        assert_eq!(vm.recovered_errors().len(), MAX_RECOVERED_ERRORS);
+        assert_eq!(vm.recovered_error_count(), 1500);
+    }
+
+    #[test]
+    /// Test if stepping forward and then back the same amount
+    /// of times leaves the registers and memory as they were
+    fn step_back_reverts_steps() {
+        let src = "
+            .ORIG x3000
+            ADD R0, R0, #3
+            ST R0, DATA
+            ADD R1, R0, #-3
+    DATA    .FILL #9
+        ";
+        let mut vm = VM::new().with_history(8);
+        vm.assemble_and_load(src).unwrap();
+        let initial_regs = vm.regs.clone();
+
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.mem.read(0x3003).unwrap(), 3);
+        assert_eq!(vm.regs[Register::Cond], CondFlag::Zro.value());
+
+        for _ in 0..3 {
+            assert!(vm.step_back().unwrap());
+        }
+        assert!(vm.regs == initial_regs);
+        assert_eq!(vm.mem.read(0x3003).unwrap(), 9);
+        // There is nothing left to undo
+        assert!(!vm.step_back().unwrap());
+    }
+
+    #[test]
+    /// Test if stepping back over the HALT that stopped the VM forgets why
+    /// it stopped, so the VM can run it again
+    fn step_back_restores_stop_reason() {
+        let mut vm = VM::new().with_history(8).with_output(Vec::new());
+        vm.assemble_and_load(".ORIG x3000\nADD R0, R0, #1\nHALT")
+            .unwrap();
+
+        vm.run().unwrap();
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::HaltTrap));
+
+        assert!(vm.step_back().unwrap());
+        assert_eq!(vm.last_stop_reason(), None);
+        assert_eq!(vm.regs[Register::PC], 0x3001);
+    }
+
+    #[test]
+    /// Test if a change in memory can be seen through the memory slice
+    fn memory_slice_reflects_writes() {
         let mut vm = VM::new();
-        vm.regs[Register::R1] = sr1;
-        vm.regs[Register::R2] = sr2;
-        // The instruction will have the following encoding:
-        // 0 1 0 1 0 0 0 0 0 1 0 0 0 0 1 0
-        let instr = 0x5042;
-        let _ = vm.and(instr);
+        let address: u16 = 0x3005;
 
-        // Check if in R0 we have the desired result
-        assert_eq!(vm.regs[Register::R0], result);
+        assert_eq!(vm.memory_slice()[usize::from(address)], 0);
+        vm.mem.write(address, 0xABCD).unwrap();
+
+        assert_eq!(vm.memory_slice().len(), 65536);
+        assert_eq!(vm.memory_slice()[usize::from(address)], 0xABCD);
+        // The word memory lends its words instead of copying them
+        assert!(matches!(vm.memory_slice(), Cow::Borrowed(_)));
     }
 
     #[test]
-    /// Test if doing the bitwise 'AND' with immediate mode
-    /// gets the correct result
-    fn and_with_immediate_mode() {
-        let sr1 = 0xFFFF;
-        let result = 0x0000;
-        // Create the registers and set the value on R1
+    /// Test if scanning a loaded program finds the address
+    /// of its only TRAP instruction
+    fn find_instructions_finds_trap() {
+        let src = "
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #1
+            HALT
+            ADD R0, R0, #2
+        ";
         let mut vm = VM::new();
-        vm.regs[Register::R1] = sr1;
-        // The instruction will have the following encoding:
-        // 0 1 0 1 0 0 0 0 0 1 0 0 0 0 0 0
-        let instr = 0x5040;
-        let _ = vm.and(instr);
+        vm.assemble_and_load(src).unwrap();
 
-        // Check if in R0 we have the desired result
-        assert_eq!(vm.regs[Register::R0], result);
+        assert_eq!(
+            vm.find_instructions(OpCode::Trap, 0x3000..0x3004),
+            vec![0x3002]
+        );
+        assert_eq!(
+            vm.find_instructions(OpCode::Add, 0x3000..0x3004),
+            vec![0x3001, 0x3003]
+        );
     }
 
     #[test]
-    /// Test if bitwise 'NOT' actually negates all the bits
-    /// in a register
-    fn bitwise_not_negates_all_bits() {
-        let sr = 0xFFFF;
-        let result = 0x0000;
-        // Create the registers and set the value on R1
+    /// Test if the newlines written by OUT are only translated
+    /// when the translation was enabled
+    fn out_translates_newline_only_when_enabled() {
+        let mut raw_writer: Vec<u8> = Vec::new();
+        let mut raw_vm = VM::new();
+        raw_vm.regs[Register::R0] = u16::from(b'\n');
+        raw_vm.out(&mut raw_writer).unwrap();
+
+        let mut translated_writer: Vec<u8> = Vec::new();
+        let mut translated_vm = VM::new().with_newline_translation(true);
+        translated_vm.regs[Register::R0] = u16::from(b'\n');
+        translated_vm.out(&mut translated_writer).unwrap();
+
+        assert_eq!(raw_writer, b"\n");
+        assert_eq!(translated_writer, b"\r\n");
+    }
+
+    #[test]
+    /// Test if a bell written by OUT is only escaped when
+    /// the output is sanitized
+    fn out_escapes_non_printable_byte_only_when_sanitizing() {
+        let src = "
+            .ORIG x3000
+            LD R0, BELL
+            OUT
+            HALT
+            BELL .FILL x0007
+            .END
+        ";
+        let raw_output = SharedBuffer::new();
+        let mut raw_vm = VM::new().with_quiet(true).with_output(raw_output.clone());
+        raw_vm.assemble_and_load(src).unwrap();
+        raw_vm.run().unwrap();
+
+        let sanitized_output = SharedBuffer::new();
+        let mut sanitized_vm = VM::new()
+            .with_quiet(true)
+            .with_output_sanitizing(true)
+            .with_output(sanitized_output.clone());
+        sanitized_vm.assemble_and_load(src).unwrap();
+        sanitized_vm.run().unwrap();
+
+        assert_eq!(raw_output.contents(), [0x07]);
+        assert_eq!(sanitized_output.contents(), b"\\x07");
+    }
+
+    #[test]
+    fn puts_p_run_writes_on_writer() {
+        let mut writer: Vec<u8> = Vec::new();
+        let (char1, char2, char3, char4) = ("1", "2", "3", "4");
+        let char1_bytes: u16 = char1.as_bytes()[0].into();
+        let char2_bytes: u16 = char2.as_bytes()[0].into();
+        let char3_bytes: u16 = char3.as_bytes()[0].into();
+        let char4_bytes: u16 = char4.as_bytes()[0].into();
+
+        let memory_location1: u16 = (char2_bytes << 8) | char1_bytes;
+        let memory_location2: u16 = (char4_bytes << 8) | char3_bytes;
+
+        let starting_address: u16 = 0x0005;
         let mut vm = VM::new();
-        vm.regs[Register::R1] = sr;
-        // The instruction will have the following encoding:
-        // 1 0 0 1 0 0 0 0 0 1 1 1 1 1 1 1
-        let instr = 0x907F;
-        let _ = vm.not(instr);
+        vm.regs[Register::R0] = starting_address;
+        let _ = vm.mem.write(starting_address, memory_location1);
+        let _ = vm.mem.write(starting_address + 1, memory_location2);
 
-        // Check if in R0 we have the desired result
-        assert_eq!(vm.regs[Register::R0], result);
+        let _ = vm.puts_p(&mut writer);
+
+        let written_val_1: u16 = writer[0].into();
+        let written_val_2: u16 = writer[1].into();
+        let written_val_3: u16 = writer[2].into();
+        let written_val_4: u16 = writer[3].into();
+        assert_eq!(written_val_1, char1_bytes);
+        assert_eq!(written_val_2, char2_bytes);
+        assert_eq!(written_val_3, char3_bytes);
+        assert_eq!(written_val_4, char4_bytes);
+    }
+
+    #[test]
+    /// Test if PUTSP writes the low byte of a memory
+    /// location before its high byte
+    fn puts_p_writes_low_byte_first() {
+        let mut writer: Vec<u8> = Vec::new();
+        let starting_address: u16 = 0x0005;
+        let mut vm = VM::new();
+        vm.regs[Register::R0] = starting_address;
+        // 'b' in the high byte and 'a' in the low one
+        vm.mem.write(starting_address, 0x6261).unwrap();
+
+        vm.puts_p(&mut writer).unwrap();
+
+        assert_eq!(writer, b"ab");
+    }
+
+    #[test]
+    /// Test if a memory location whose high byte is x00 only writes
+    /// its low byte, as it happens at the end of odd length strings
+    fn puts_p_skips_null_high_byte() {
+        let mut writer: Vec<u8> = Vec::new();
+        let starting_address: u16 = 0x0005;
+        let mut vm = VM::new();
+        vm.regs[Register::R0] = starting_address;
+        vm.mem.write(starting_address, 0x6261).unwrap();
+        vm.mem.write(starting_address + 1, 0x0063).unwrap();
+
+        vm.puts_p(&mut writer).unwrap();
+
+        assert_eq!(writer, b"abc");
+    }
+
+    #[test]
+    /// Test if with buffered output the characters written
+    /// by the program come before the HALT banner
+    fn halt_flushes_output_before_banner() {
+        let src = "
+            .ORIG x3000
+            LD R0, CHAR
+            OUT
+            OUT
+            HALT
+    CHAR    .FILL x41
+        ";
+        let output = SharedBuffer::new();
+        let mut vm = VM::new().with_output(BufWriter::new(output.clone()));
+        vm.assemble_and_load(src).unwrap();
+
+        vm.run().unwrap();
+
+        assert_eq!(output.contents(), b"AAHALT\n");
+    }
+
+    #[test]
+    /// Test if the quiet mode leaves the banner out of the output
+    fn halt_in_quiet_mode_writes_no_banner() {
+        let mut vm = VM::new().with_quiet(true);
+        let mut writer: Vec<u8> = Vec::new();
+
+        vm.halt(&mut writer).unwrap();
+
+        assert!(writer.is_empty());
+        assert!(!vm.running);
+    }
+
+    #[test]
+    /// Test if the output is flushed when the program
+    /// stops because of an error
+    fn run_flushes_output_on_error() {
+        let src = "
+            .ORIG x3000
+            LD R0, CHAR
+            OUT
+            .FILL xD000
+    CHAR    .FILL x41
+        ";
+        let output = SharedBuffer::new();
+        let mut vm = VM::new().with_output(BufWriter::new(output.clone()));
+        vm.assemble_and_load(src).unwrap();
+
+        assert!(vm.run().is_err());
+        assert_eq!(output.contents(), b"A");
+    }
+
+    #[test]
+    /// Test if stepping with disassembly returns each executed
+    /// instruction, following the branch that was taken
+    fn step_disasm_returns_executed_instructions() {
+        let src = "
+            .ORIG x3000
+            ADD R0, R0, #1
+            BRp SKIP
+            ADD R0, R0, #1
+    SKIP    HALT
+        ";
+        let mut vm = VM::new().with_output(Vec::new());
+        vm.assemble_and_load(src).unwrap();
+
+        let lines: Vec<String> = (0..3).map(|_| vm.step_disasm().unwrap()).collect();
+
+        assert_eq!(
+            lines,
+            vec!["x3000: ADD R0, R0, #1", "x3001: BRp SKIP", "x3003: HALT"]
+        );
+    }
+
+    #[test]
+    /// Test if a commented text image gets loaded and runs
+    fn load_text_image_runs_program() {
+        let src = "
+            .ORIG x3000
+    START:  5020    ; AND R0, R0, #0
+            1025    ; ADD R0, R0, #5
+            F025    ; HALT
+        ";
+        let mut vm = VM::new().with_quiet(true).with_output(Vec::new());
+        vm.load_text_image(src).unwrap();
+        vm.run().unwrap();
+
+        assert_eq!(vm.regs[Register::R0], 5);
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::HaltTrap));
+    }
+
+    #[test]
+    /// Test if the histogram counts executions per operation, so the
+    /// body of a loop dominates it
+    fn opcode_histogram_counts_executions() {
+        let src = "
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R1, R0, #10
+    LOOP    ADD R0, R0, #2
+            ADD R1, R1, #-1
+            BRp LOOP
+            HALT
+        ";
+        let mut vm = VM::new()
+            .with_quiet(true)
+            .with_output(Vec::new())
+            .with_opcode_histogram(true);
+        vm.assemble_and_load(src).unwrap();
+        vm.run().unwrap();
+
+        let histogram = vm.opcode_histogram();
+        assert_eq!(histogram.get(&OpCode::Add), Some(&21));
+        assert_eq!(histogram.get(&OpCode::Br), Some(&10));
+        assert_eq!(histogram.get(&OpCode::And), Some(&1));
+        assert_eq!(histogram.get(&OpCode::Trap), Some(&1));
+        assert_eq!(histogram.get(&OpCode::Ld), None);
+    }
+
+    #[test]
+    /// Test if nothing gets counted unless the histogram is enabled
+    fn opcode_histogram_is_empty_when_disabled() {
+        let mut vm = VM::new().with_quiet(true).with_output(Vec::new());
+        vm.assemble_and_load(".ORIG x3000\nADD R0, R0, #1\nHALT")
+            .unwrap();
+        vm.run().unwrap();
+
+        assert!(vm.opcode_histogram().is_empty());
+    }
+
+    #[test]
+    /// Test if raising the interrupt flag stops a program that loops forever
+    fn interrupt_flag_stops_run() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut vm = VM::new()
+            .with_output(Vec::new())
+            .with_interrupt_flag(Arc::clone(&flag));
+        vm.assemble_and_load(".ORIG x3000\nLOOP BRnzp LOOP")
+            .unwrap();
+        flag.store(true, Ordering::SeqCst);
+
+        vm.run().unwrap();
+
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::Interrupted));
+        assert!(!flag.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    /// Test if raising the interrupt flag while GETC waits for a key on
+    /// polled input stops the VM on the GETC, without a key
+    fn interrupt_flag_stops_getc_waiting_for_key() {
+        /// Input where a key never arrives, and Ctrl-C is pressed while waiting
+        struct NoKey(Arc<AtomicBool>);
+        impl Read for NoKey {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                self.0.store(true, Ordering::SeqCst);
+                Ok(0)
+            }
+        }
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut vm = VM::new()
+            .with_input(NoKey(Arc::clone(&flag)))
+            .with_output(Vec::new())
+            .with_polled_input(true)
+            .with_interrupt_flag(Arc::clone(&flag));
+        vm.assemble_and_load(".ORIG x3000\nGETC\nHALT").unwrap();
+
+        vm.run().unwrap();
+
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::Interrupted));
+        assert_eq!(vm.regs[Register::PC], 0x3000);
+        assert!(!flag.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    /// Test if a GETC interrupted while waiting for a key leaves the input
+    /// log empty, instead of logging whatever R0 held
+    fn interrupted_getc_logs_no_input() {
+        /// Input where a key never arrives, and Ctrl-C is pressed while waiting
+        struct NoKey(Arc<AtomicBool>);
+        impl Read for NoKey {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                self.0.store(true, Ordering::SeqCst);
+                Ok(0)
+            }
+        }
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut vm = VM::new()
+            .with_input(NoKey(Arc::clone(&flag)))
+            .with_output(Vec::new())
+            .with_polled_input(true)
+            .with_input_log(true)
+            .with_interrupt_flag(Arc::clone(&flag));
+        vm.assemble_and_load(".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #7\nGETC\nHALT")
+            .unwrap();
+
+        vm.run().unwrap();
+
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::Interrupted));
+        assert_eq!(vm.regs[Register::R0], 7);
+        assert!(vm.input_log().is_empty());
+    }
+
+    #[test]
+    /// Test if the last instruction is unknown before stepping and
+    /// follows the PC that was executed after it
+    fn last_instruction_follows_steps() {
+        let mut vm = VM::new().with_output(Vec::new());
+        vm.assemble_and_load(".ORIG x3000\nADD R0, R0, #1\nHALT")
+            .unwrap();
+        assert_eq!(vm.last_instruction(), None);
+
+        vm.step().unwrap();
+
+        assert_eq!(vm.last_instruction(), Some((0x3000, 0x1021)));
+    }
+
+    #[test]
+    /// Test if an image loaded after another one starts right past its
+    /// last word, no matter the origin it was assembled with
+    fn read_image_file_after_layers_images() {
+        let mut vm = VM::new();
+        let mut library = assembled_image(".ORIG x3000\n.FILL x1111\n.FILL x2222");
+        let mut program = assembled_image(".ORIG x5000\n.FILL x3333");
+
+        vm.read_image_file(&mut library).unwrap();
+        let origin = vm.read_image_file_after(&mut program).unwrap();
+
+        assert_eq!(origin, 0x3002);
+        assert_eq!(
+            &vm.memory_slice()[0x3000..0x3003],
+            &[0x1111, 0x2222, 0x3333]
+        );
+        assert_eq!(vm.mem.peek(0x5000), 0);
+    }
+
+    #[test]
+    /// Test if there is no room to load an image after one that ends at
+    /// the last memory address, instead of going back to address 0
+    fn read_image_file_after_rejects_image_at_end_of_memory() {
+        let mut vm = VM::new();
+        let mut last = assembled_image(".ORIG xFFFE\n.FILL x1111\n.FILL x2222");
+        let mut program = assembled_image(".ORIG x3000\n.FILL x3333");
+
+        vm.read_image_file(&mut last).unwrap();
+
+        assert!(matches!(
+            vm.read_image_file_after(&mut program),
+            Err(VMError::InvalidIndex(0x10000))
+        ));
+        assert_eq!(vm.mem.peek(0x0000), 0);
+    }
+
+    #[test]
+    /// Test if an image with a matching sidecar digest gets loaded and one
+    /// with a different digest is rejected
+    fn read_file_verifies_sidecar_checksum() {
+        let dir = std::env::temp_dir().join(format!("lc3-checksum-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = assembled_image(".ORIG x3000\nHALT");
+        let path = dir.join("prog.obj");
+        fs::write(&path, &image).unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        let sidecar = format!("{}.sha256", path);
+        fs::write(&sidecar, format!("{}  prog.obj\n", sha256_hex(&image))).unwrap();
+        let matching = VM::new().read_file(path.clone(), None);
+        fs::write(&sidecar, sha256_hex(b"something else")).unwrap();
+        let mismatching = VM::new().read_file(path.clone(), None);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(matching.unwrap(), image);
+        assert!(matches!(mismatching, Err(VMError::ChecksumMismatch(..))));
+    }
+
+    #[test]
+    /// Test if a file much larger than the memory is loaded up to the last
+    /// address, without reading all of it nor going back to address 0
+    fn read_image_caps_oversized_files() {
+        let path = std::env::temp_dir().join(format!("lc3-oversized-{}.obj", std::process::id()));
+        // Origin x3000 and 4 MB of words with the value x1234
+        let mut bytes = vec![0x30, 0x00];
+        bytes.extend([0x12, 0x34].repeat(2 * 1024 * 1024));
+        fs::write(&path, &bytes).unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        let mut reader = VM::new();
+        let read = reader.read_file(path.clone(), None);
+        let checked = VM::new().read_file(path.clone(), Some(&sha256_hex(&bytes)));
+        let mut vm = VM::new();
+        let origin = vm.read_image(path.clone());
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.unwrap().len(), MAX_IMAGE_BYTES);
+        assert_eq!(reader.load_warnings().len(), 1);
+        // The digest is checked on the whole file, not only what was read
+        assert_eq!(checked.unwrap().len(), MAX_IMAGE_BYTES);
+        assert_eq!(origin.unwrap(), 0x3000);
+        assert_eq!(vm.mem.peek(0x3000), 0x1234);
+        assert_eq!(vm.mem.peek(0xFFFF), 0x1234);
+        assert_eq!(vm.mem.peek(0x0000), 0);
+        assert_eq!(vm.mem.peek(0x2FFF), 0);
+        assert_eq!(vm.loaded_segments(), [(0x3000, 0xD000)]);
+        // The file is cut, and then the words after xFFFF are left out
+        assert_eq!(vm.load_warnings().len(), 2);
+    }
+
+    #[test]
+    /// Test if an image going past the end of a smaller memory is loaded up
+    /// to its last address, with a warning, instead of failing
+    fn read_image_file_stops_at_end_of_smaller_memory() {
+        let mut vm = VM::new().with_memory_size(0x3002);
+        // Origin x3000 and 3 words, the last one after the end of the memory
+        let mut bytes = vec![0x30, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x03];
+
+        assert_eq!(vm.read_image_file(&mut bytes).unwrap(), 0x3000);
+
+        assert_eq!(vm.mem.peek(0x3000), 1);
+        assert_eq!(vm.mem.peek(0x3001), 2);
+        assert_eq!(vm.loaded_segments(), [(0x3000, 2)]);
+        assert_eq!(vm.load_warnings().len(), 1);
+    }
+
+    #[test]
+    /// Test if the memory map names the loaded segment and tells
+    /// which regions have something
+    fn memory_map_lists_loaded_segments() {
+        let mut vm = VM::new();
+        vm.assemble_and_load(".ORIG x3000\nAND R0, R0, #0\nHALT\n.END")
+            .unwrap();
+        vm.load_image(&[0x00, 0x25, 0x10, 0x00]).unwrap();
+
+        let map = vm.memory_map();
+
+        assert!(map.contains("x0000-x00FF  trap vector table      populated\n"));
+        assert!(map.contains("x0100-x01FF  interrupt vector table empty\n"));
+        assert!(map.contains("x3000-xFDFF  user space             populated\n"));
+        assert!(map.ends_with(
+            "Loaded segments:\nx3000-x3001  2 words  user space\nx0025-x0025  1 word  trap vector table\n"
+        ));
+    }
+
+    #[test]
+    /// Test if a digest given explicitly is checked against the image
+    fn read_file_verifies_given_checksum() {
+        let path = String::from("test_files/origin_x4000.obj");
+        let bytes = fs::read(&path).unwrap();
+
+        assert!(
+            VM::new()
+                .read_file(path.clone(), Some(&sha256_hex(&bytes)))
+                .is_ok()
+        );
+        assert!(matches!(
+            VM::new().read_file(path, Some(&sha256_hex(b""))),
+            Err(VMError::ChecksumMismatch(..))
+        ));
     }
 
     #[test]
-    /// Test if branch changes the PC for condition
-    /// flag set to positive
-    fn branch_changes_pc_with_pos_cond_flag() {
-        // Create the registers and set the value on register Cond
-        let mut vm = VM::default();
-        vm.regs[Register::Cond] = CondFlag::Pos.value();
-        // The instruction will have the following encoding:
-        // 0 0 0 0 0 0 1 0 0 0 0 0 0 0 0 1
-        let instr = 0x0201;
-        let _ = vm.branch(instr);
+    /// Test if executing any instruction word, with random values in the
+    /// registers, returns a Result instead of panicking
+    fn execute_never_panics() {
+        // Xorshift generator, so the test is reproducible without a crate
+        let mut seed: u32 = 0x2545_F491;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let mut vm = VM::new()
+            .with_quiet(true)
+            .with_input(empty())
+            .with_output(sink());
 
-        // Check if the PC register was set to 1
-        assert_eq!(vm.regs[Register::PC], 0x0001);
+        for instr in 0..=u16::MAX {
+            for reg in Register::all() {
+                let [high, low, ..] = next().to_be_bytes();
+                vm.regs[*reg] = u16::from_be_bytes([high, low]);
+            }
+            // Keep strings short, so PUTS and PUTSP reach their terminator
+            vm.mem
+                .write(vm.regs[Register::R0].wrapping_add(1), 0)
+                .unwrap();
+            vm.mem.write(vm.regs[Register::R0], 0).unwrap();
+            vm.running = true;
+            let _ = vm.execute(instr);
+        }
     }
 
     #[test]
-    /// Test if branch changes the PC for condition
-    /// flag set to zero
-    fn branch_changes_pc_with_zro_cond_flag() {
-        // Create the registers and set the value on register Cond
-        let mut vm = VM::default();
-        vm.regs[Register::Cond] = CondFlag::Zro.value();
-        // The instruction will have the following encoding:
-        // 0 0 0 0 0 1 0 0 0 0 0 0 0 0 0 1
-        let instr = 0x0401;
-        let _ = vm.branch(instr);
+    /// Test if the offset of JSR is added to the PC already incremented past
+    /// it, going through the fetch of `step` instead of calling the handler
+    fn jsr_offset_is_relative_to_incremented_pc() {
+        let jsr_address = 0x3005;
+        let mut vm = VM::new();
+        // JSR #10 and JSR #-6
+        vm.load_words(jsr_address, &[0x480A]).unwrap();
+        vm.load_words(0x3010, &[0x4FFA]).unwrap();
+        vm.regs[Register::PC] = jsr_address;
 
-        // Check if the PC register was set to 1
-        assert_eq!(vm.regs[Register::PC], 0x0001);
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::PC], jsr_address + 1 + 10);
+        assert_eq!(vm.regs[Register::R7], jsr_address + 1);
+
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::PC], 0x3010 + 1 - 6);
+        assert_eq!(vm.regs[Register::R7], 0x3011);
     }
 
     #[test]
-    /// Test if branch changes the PC for condition
-    /// flag set to negative
-    fn branch_changes_pc_with_neg_cond_flag() {
-        // Create the registers and set the value on register Cond
-        let mut vm = VM::default();
-        vm.regs[Register::Cond] = CondFlag::Neg.value();
-        // The instruction will have the following encoding:
-        // 0 0 0 0 1 0 0 0 0 0 0 0 0 0 0 1
-        let instr = 0x0801;
-        let _ = vm.branch(instr);
+    /// Test if every segment of a program is loaded at its origin and
+    /// the PC starts at the first one
+    fn assemble_and_load_loads_every_segment() {
+        let src = "
+            .ORIG x4000
+            LDI R0, PTR
+            HALT
+    PTR     .FILL DATA
+            .END
+            .ORIG x3000
+    DATA    .FILL x0007
+            .END
+        ";
+        let mut vm = VM::new().with_quiet(true).with_output(Vec::new());
+        vm.assemble_and_load(src).unwrap();
 
-        // Check if the PC register was set to 1
-        assert_eq!(vm.regs[Register::PC], 0x0001);
+        assert_eq!(vm.regs[Register::PC], 0x4000);
+        assert_eq!(vm.mem.peek(0x3000), 0x0007);
+        vm.run().unwrap();
+        assert_eq!(vm.regs[Register::R0], 0x0007);
     }
 
     #[test]
-    /// Test if jump changes the value of the PC is set
-    /// to the value of the register specified in the
-    /// encoding
-    fn jump_changes_pc() {
-        // Create the registers and set the value of R1 register
+    /// Test if the labels of an assembled program can be looked up
+    /// by name and by address
+    fn assemble_and_load_records_symbols() {
         let mut vm = VM::new();
-        let result = 0xFFFF;
-        vm.regs[Register::R1] = result;
-        // The instruction will have the following encoding:
-        // 1 1 0 0  0 0 0 0  0 1 0 0  0 0 0 0
-        let instr = 0xC040;
-        let _ = vm.jump(instr);
+        vm.assemble_and_load(".ORIG x3000\nMAIN LEA R0, MSG\nPUTS\nHALT\nMSG .STRINGZ \"hi\"")
+            .unwrap();
 
-        // Check if the PC was set with the value that R1 had
-        assert_eq!(vm.regs[Register::PC], result);
+        assert_eq!(vm.symbol("MAIN"), Some(0x3000));
+        assert_eq!(vm.symbol("MSG"), Some(0x3003));
+        assert_eq!(vm.symbol("NOWHERE"), None);
+        assert_eq!(vm.symbol_at(0x3003), Some("MSG"));
+        assert_eq!(vm.symbol_at(0x3001), None);
     }
 
     #[test]
-    /// Test if the PC register adds the desired value to the PC
-    /// when using the jump register instruction with the long
-    /// flag set. With this flag being set, means we have 11 bits
-    /// to choose a value for our PC register.
-    fn jump_register_adds_pc_with_long_flag() {
-        let mut vm = VM::default();
-        let result = 0x03FF;
-        // The instruction will have the following encoding:
-        // 0 1 0 0  1 0 1 1  1 1 1 1  1 1 1 1
-        let instr = 0x4BFF;
-        let _ = vm.jump_register(instr);
+    /// Test if the symbols of a .sym file can be queried and
+    /// are used by the disassembly
+    fn load_symbols_reads_sym_file() {
+        let path = std::env::temp_dir().join(format!("lc3-symbols-{}.sym", std::process::id()));
+        fs::write(
+            &path,
+            "// Symbol table\n//\tSTART   3000\n//\tDATA    3003\n//\tjunk\n",
+        )
+        .unwrap();
+        let mut vm = VM::new();
+        // LD R0, #2
+        vm.load_image(&[0x30, 0x00, 0x20, 0x02]).unwrap();
 
-        // Check if the PC register was set to the value
-        // embedded on the encoding
-        assert_eq!(vm.regs[Register::PC], result);
+        let warnings = vm.load_symbols(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("junk"));
+        assert_eq!(vm.symbol("DATA"), Some(0x3003));
+        assert_eq!(vm.symbol_at(0x3000), Some("START"));
+        assert_eq!(
+            vm.disassemble_range(0x3000, 1),
+            "x3000  x2002  LD R0, DATA\n"
+        );
     }
 
     #[test]
-    /// Test if the PC register substracts the desired value to the PC
-    /// when using the jump register instruction with the long
-    /// flag set and a negative value in the PCoffset11 section.
-    /// With this flag being set, means we have 11 bits
-    /// to choose a value for our PC register.
-    ///
-    /// In this case we will set the PC to 10 and we will substract 3
-    /// so it sets to 7.
-    fn jump_register_substracts_pc_with_long_flag() {
-        // Create the registers and set the pc to 10
+    /// Test if RTI pops the PC and the PSR from the supervisor stack
+    fn rti_pops_pc_and_psr() {
         let mut vm = VM::new();
-        vm.regs[Register::PC] = 0x000A;
-        let result = 0x0007;
-        // The instruction will have the following encoding:
-        // 0 1 0 0  1 1 1 1  1 1 1 1  1 1 0 1
-        let instr = 0x4FFD;
-        let _ = vm.jump_register(instr);
+        vm.regs[Register::R6] = 0x2FFE;
+        vm.mem.write(0x2FFE_u16, 0x3010).unwrap();
+        vm.mem.write(0x2FFF_u16, 0x0004).unwrap();
 
-        // Check if the PC register was set to the value
-        // embedded on the encoding
-        assert_eq!(vm.regs[Register::PC], result);
+        vm.execute(0x8000).unwrap();
+
+        assert_eq!(vm.regs[Register::PC], 0x3010);
+        assert_eq!(vm.regs[Register::Cond], CondFlag::Neg.value());
+        assert_eq!(vm.regs[Register::R6], 0x3000);
     }
 
     #[test]
-    /// Test if the PC register changes to the desired value
-    /// when using the jum register instruction without the long
-    /// flag set. This means that the value for the PC register
-    /// will be take from one of the registers.
-    fn jump_register_changes_pc_without_long_flag() {
-        // Create the registers and set the for the PC on R1
+    /// Test if RTI fails when the supervisor stack is empty, or R6
+    /// points outside of it, instead of popping garbage
+    fn rti_with_empty_stack_underflows() {
         let mut vm = VM::new();
-        let result = 0x07FF;
-        vm.regs[Register::R1] = result;
-        // The instruction will have the following encoding:
-        // 0 1 0 0  0 0 0 0  0 1 0 0  0 0 0 0
-        let instr = 0x4040;
-        let _ = vm.jump_register(instr);
+        vm.regs[Register::R6] = 0x3000;
 
-        // Check if the PC register was set to the value
-        // embedded on the encoding
-        assert_eq!(vm.regs[Register::PC], result);
+        assert!(matches!(
+            vm.execute(0x8000),
+            Err(VMError::StackUnderflow(0x3000))
+        ));
+        assert_eq!(vm.regs[Register::PC], PC_START);
+
+        let mut vm = VM::new().with_supervisor_stack(0x1000..0x1100);
+        vm.regs[Register::R6] = 0x2FFE;
+        assert!(matches!(
+            vm.execute(0x8000),
+            Err(VMError::StackUnderflow(0x2FFE))
+        ));
     }
 
     #[test]
-    /// Test if the jump register instruction stores the PC
-    /// value into the R7 register before it is changed to a
-    /// new value.
-    fn jump_register_stores_pc_on_r7() {
-        // Create the registers and set the value of the PC
-        let result = 0x000A;
-        let mut vm = VM::new();
-        vm.regs[Register::PC] = result;
+    /// Test if a TRAP from user mode moves to the supervisor stack and
+    /// mode, and the RTI at the end of the routine goes back to the
+    /// user ones
+    fn trap_and_rti_switch_privilege_and_stacks() {
+        let mut vm = VM::new()
+            .with_trap_mode(TrapMode::MemoryVector)
+            .with_privilege(Privilege::User);
+        // TRAP x25 jumps to a routine in x1000 that only has RTI
+        vm.load_words(0x0025, &[0x1000]).unwrap();
+        vm.load_words(0x1000, &[0x8000]).unwrap();
+        vm.load_words(0x3000, &[0xF025]).unwrap();
+        vm.regs[Register::R6] = 0xFD00;
+        assert_eq!(vm.psr(), 0x8002);
 
-        // Check if R7 has a different value before calling the instruction
-        assert_ne!(vm.regs[Register::R7], result);
-        // Run the instruction
-        let _ = vm.jump_register(0x4040);
-        // Check if R7 changed its value to the one the PC had
-        assert_eq!(vm.regs[Register::R7], result);
+        vm.step().unwrap();
+
+        assert_eq!(vm.privilege(), Privilege::Supervisor);
+        assert_eq!(vm.regs[Register::PC], 0x1000);
+        assert_eq!(vm.regs[Register::R6], 0x2FFE);
+        assert_eq!(vm.mem.peek(0x2FFE), 0x3001);
+        assert_eq!(vm.mem.peek(0x2FFF), 0x8002);
+
+        vm.step().unwrap();
+
+        assert_eq!(vm.privilege(), Privilege::User);
+        assert_eq!(vm.regs[Register::PC], 0x3001);
+        assert_eq!(vm.regs[Register::R6], 0xFD00);
+        assert_eq!(vm.psr(), 0x8002);
     }
 
     #[test]
-    /// Test if load indirect instruction changes the value of a register
-    /// with one that was in a place in memory.
-    ///
-    /// We seted in the PCoffset9 section the value 5 and the PC to 10.
-    /// So when adding this we get the memory address 15 where we
-    /// seted to be the address where the result will be found. This
-    /// address will be 20, so the instruction reads that memory address
-    /// and loads the value of it to the register indicated on the instruction
-    /// (register 1 in this case)
-    fn load_indirect_changes_register_value() {
-        // Create the memory and set the values for the addresses
-        let mut vm = VM::new();
-        let first_address: u16 = 0x000F;
-        let result_address = 0x0014;
-        let result = 0x0001;
-        let _ = vm.mem.write(first_address, result_address);
-        let _ = vm.mem.write(result_address, result);
-        // Set the value of pc to 10.
-        vm.regs[Register::PC] = 0x000A;
-        // The instruction will have the following encoding:
-        // 1 0 1 0  0 0 1 0  0 0 0 0  0 1 0 1
-        let instr = 0xA205;
-        let _ = vm.load_indirect(instr);
+    /// Test if RTI cannot run in user mode
+    fn rti_in_user_mode_is_a_privilege_violation() {
+        let mut vm = VM::new().with_privilege(Privilege::User);
+        vm.regs[Register::R6] = 0x2FFE;
 
-        // Check if R1 has the value that was on memory in 'result_address'
-        assert_eq!(vm.regs[Register::R1], result);
+        assert!(matches!(
+            vm.execute(0x8000),
+            Err(VMError::PrivilegeViolation(_))
+        ));
     }
 
     #[test]
-    /// Test if load (this time without indirection) instruction changes the
-    /// value of the desired register to the one on a memory address.
-    ///
-    /// This time there is no indirection, so we set the pc to 10, add the value
-    /// 5 into the PCoffset9 section and with that we get the address where
-    /// we will find the value for our register.
-    fn load_changes_register_value() {
-        // Create the memory and set the values for the address
-        let mut vm = VM::new();
-        let result = 0x0001;
-        let address: u16 = 0x000F;
-        let _ = vm.mem.write(address, result);
-        // Set the value of PC to 10.
-        vm.regs[Register::PC] = 0x000A;
-        // The instruction will have the following encoding:
-        // 0 0 1 0  0 0 1 0  0 0 0 0  0 1 0 1
-        let instr = 0x2205;
-        let _ = vm.load(instr);
+    /// Test if user mode code cannot touch the device registers nor
+    /// the system space, while supervisor mode code can
+    fn user_mode_access_to_privileged_memory_is_a_violation() {
+        let src = "
+            .ORIG x3000
+            LDI R0, KBSR
+            STI R0, SYSTEM
+            HALT
+KBSR        .FILL xFE00
+SYSTEM      .FILL x2000
+            .END
+        ";
+        let mut vm = VM::new()
+            .with_privilege(Privilege::User)
+            .with_input(Cursor::new(b"a".to_vec()));
+        vm.assemble_and_load(src).unwrap();
 
-        // Check if R1 has the value that was on memory in 'result_address'
-        assert_eq!(vm.regs[Register::R1], result);
+        assert!(matches!(vm.step(), Err(VMError::AccessViolation(0xFE00))));
+
+        vm.regs[Register::PC] = 0x3001;
+        assert!(matches!(vm.step(), Err(VMError::AccessViolation(0x2000))));
+
+        let mut vm = VM::new().with_input(Cursor::new(b"a".to_vec()));
+        vm.assemble_and_load(src).unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.mem.peek(0x2000), 0x8000);
     }
 
     #[test]
-    /// Test if load register instruction changes the value of the desired
-    /// register to the one on a memory address that is formed from the value
-    /// in a register plus the one on the offset6 section.
-    ///
-    /// Register 0 will have the value 10 while in the offset 6 section we will
-    /// have the value 5. So then in memory address 15 we will have the value
-    /// 1 that will be set to the register 1.
-    fn load_register_changes_register_value() {
-        // Create the memory and set the values for the address
+    /// Test if a ready key with the keyboard interrupt enabled makes the
+    /// VM run the handler from the interrupt vector table, which gets back
+    /// to the program with RTI
+    fn keyboard_interrupt_runs_handler() {
+        let src = "
+            .ORIG x3000
+            LD R0, ENABLE
+            STI R0, KBSR
+LOOP        ADD R1, R1, #1
+            BR LOOP
+ENABLE      .FILL x4000
+KBSR        .FILL xFE00
+            .END
+            .ORIG x1000
+HANDLER     LDI R2, KBDR
+            RTI
+KBDR        .FILL xFE02
+            .END
+            .ORIG x0180
+            .FILL HANDLER
+            .END
+        ";
         let mut vm = VM::new();
-        let result = 0x0001;
-        let address: u16 = 0x000F;
-        let _ = vm.mem.write(address, result);
-        // Set the value of pc to 10.
-        vm.regs[Register::R0] = 0x000A;
-        // The instruction will have the following encoding:
-        // 0 1 1 0  0 0 1 0  0 0 0 0  0 1 0 1
-        let instr = 0x6205;
-        let _ = vm.load_register(instr);
+        vm.assemble_and_load(src).unwrap();
+        vm.regs[Register::PC] = 0x3000;
+        vm.regs[Register::R6] = 0x3000;
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.regs[Register::R2], 0);
 
-        // Check if R1 has the value that was on memory in 'result_address'
-        assert_eq!(vm.regs[Register::R1], result);
+        vm.latch_key(b'k').unwrap();
+        vm.step().unwrap();
+
+        assert_eq!(vm.regs[Register::R2], u16::from(b'k'));
+        assert_eq!(vm.regs[Register::R6], 0x2FFE);
+        assert_eq!(vm.psr() >> 8, 4);
+
+        vm.step().unwrap();
+
+        assert_eq!(vm.regs[Register::PC], 0x3003);
+        assert_eq!(vm.regs[Register::R6], 0x3000);
+        assert_eq!(vm.psr() >> 8, 0);
+        // Reading the KBDR consumed the key, so the handler does not run again
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::PC], 0x3002);
     }
 
     #[test]
-    /// Test if load effective address instruction changes the
-    /// value of the desired register to the one that comes from
-    /// the addition of the PC and the PCoffset9 section.
-    ///
-    /// PC will have the value 10 and PCoffset9 will have the value 5
-    /// so after the call of the instruction, register 1 should
-    /// have the value 15
-    fn load_effective_changes_register_value() {
-        // Create the registers and set the value of pc to 10.
-        let mut vm = VM::new();
-        vm.regs[Register::PC] = 0x000A;
-        let result: u16 = 0x000A + 0x0005;
-        // The instruction will have the following encoding:
-        // 1 1 1 0  0 0 1 0  0 0 0 0  0 1 0 1
-        let instr = 0x6205;
-        let _ = vm.load_effective_address(instr);
+    /// Test if a key arriving on a polled input fires the keyboard interrupt
+    /// in a loop that never reads the KBSR, and the input is not read while
+    /// the interrupt is disabled
+    fn keyboard_interrupt_polls_input() {
+        /// Input that has no key for its first reads, and counts them
+        struct LateKey(Rc<Cell<u16>>);
+        impl Read for LateKey {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.set(self.0.get().wrapping_add(1));
+                match (self.0.get(), buf.first_mut()) {
+                    (3, Some(byte)) => {
+                        *byte = b'k';
+                        Ok(1)
+                    }
+                    _ => Ok(0),
+                }
+            }
+        }
+        let src = "
+            .ORIG x3000
+            ADD R1, R1, #1
+            LD R0, ENABLE
+            STI R0, KBSR
+LOOP        ADD R1, R1, #1
+            BR LOOP
+ENABLE      .FILL x4000
+KBSR        .FILL xFE00
+            .END
+            .ORIG x1000
+HANDLER     LDI R2, KBDR
+            RTI
+KBDR        .FILL xFE02
+            .END
+            .ORIG x0180
+            .FILL HANDLER
+            .END
+        ";
+        let reads = Rc::new(Cell::new(0));
+        let mut vm = VM::new()
+            .with_input(LateKey(Rc::clone(&reads)))
+            .with_polled_input(true);
+        vm.assemble_and_load(src).unwrap();
+        vm.regs[Register::PC] = 0x3000;
+        vm.regs[Register::R6] = 0x3000;
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(reads.get(), 0);
 
-        // Check if R1 has the value of PC + PCoffset9
-        assert_eq!(vm.regs[Register::R1], result);
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(reads.get(), 3);
+        assert_eq!(vm.regs[Register::R2], u16::from(b'k'));
+        assert_eq!(vm.psr() >> 8, 4);
     }
 
     #[test]
-    /// Test if store instruction changes the value in memory.
-    ///
-    /// Register 1 will have the value 1, while PC and PCoffset9 section
-    /// will both have the value 5. So memory address = PC + PCoffset9 = 10
-    /// will have the value of register 1
-    fn store_changes_value_in_memory() {
-        let pc_val = 0x0005;
-        let affected_address: u16 = pc_val + 0x0005; // PC + PCoffset9
-        // Create the registers and set the values for R1 and PC
-        let mut vm = VM::new();
-        vm.regs[Register::PC] = 0x0005;
-        vm.regs[Register::R1] = 0x0001;
-        // The instruction will have the following encoding:
-        // 0 0 1 1  0 0 1 0  0 0 0 0  0 1 0 1
-        let instr = 0x3205;
-        let _ = vm.store(instr);
+    /// Test if the keyboard interrupt waits while a handler with a higher
+    /// priority runs, and is dispatched once RTI restores the lower one
+    fn keyboard_interrupt_is_deferred_by_higher_priority() {
+        let src = "
+            .ORIG x3000
+            ADD R1, R1, #1
+            .END
+            .ORIG x1000
+            ADD R2, R2, #1
+            RTI
+HANDLER     ADD R3, R3, #1
+            .END
+            .ORIG x0180
+            .FILL HANDLER
+            .END
+        ";
+        let mut vm = VM::new().with_priority(6);
+        vm.assemble_and_load(src).unwrap();
+        // Running the handler of a priority 6 interrupt, that returns to x3000
+        // with priority 0
+        vm.regs[Register::PC] = 0x1000;
+        vm.regs[Register::R6] = 0x2FFE;
+        vm.load_words(0x2FFE, &[0x3000, 0x0002]).unwrap();
+        vm.mem
+            .write(MemoryRegister::KeyboardStatus, 0x4000)
+            .unwrap();
+        vm.latch_key(b'k').unwrap();
 
-        // Check if memory[PC + PCoffset9] = vm.regs[R1]
-        assert_eq!(
-            vm.mem.read(affected_address).unwrap(),
-            vm.regs[Register::R1]
-        );
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::R2], 1);
+        assert_eq!(vm.priority(), 6);
+        vm.step().unwrap();
+        assert_eq!(vm.priority(), 0);
+        assert_eq!(vm.regs[Register::PC], 0x3000);
+
+        vm.step().unwrap();
+
+        assert_eq!(vm.regs[Register::R3], 1);
+        assert_eq!(vm.regs[Register::R1], 0);
+        assert_eq!(vm.priority(), 4);
     }
 
     #[test]
-    /// Test if store indirect instruction changes the value in memory.
-    ///
-    /// Register 1 will have the value 1, address 0x000A will have the value
-    /// 0x000F, while PC and PCoffset9 section will both have the value 5.
-    /// So the first memory address = PC + PCoffset9 = 10 = 0x000A. When we
-    /// read that address we will get the final address = 0x000F and thats
-    /// the one that will be written with the value of register 1.
-    fn store_indirect_changes_value_in_memory() {
-        // Set the value of the address 0x000A to 0x000F
+    /// Test if the registers left by a multiplication done with
+    /// repeated additions are checked all at once
+    fn assert_registers_checks_computation() {
+        let src = "
+            .ORIG x3000
+            AND R2, R2, #0
+            LD R0, SIX
+            LD R1, SEVEN
+            LOOP ADD R2, R2, R0
+            ADD R1, R1, #-1
+            BRp LOOP
+            HALT
+            SIX .FILL #6
+            SEVEN .FILL #7
+            .END
+        ";
+        let mut vm = VM::new().with_output(Vec::new());
+        vm.assemble_and_load(src).unwrap();
+
+        vm.run().unwrap();
+
+        vm.assert_registers(&[
+            (Register::R0, 6),
+            (Register::R1, 0),
+            (Register::R2, 42),
+            (Register::Cond, CondFlag::Zro.value()),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "R1: expected x0005, found x0004\nPC: expected x3001")]
+    /// Test if every register that does not match is listed, in the
+    /// order of the registers
+    fn assert_registers_lists_mismatches() {
         let mut vm = VM::new();
-        let first_address: u16 = 0x000A;
-        let final_address: u16 = 0x000F;
-        let _ = vm.mem.write(first_address, final_address);
-        // Set the values of PC and R1
-        vm.regs[Register::R1] = 0x0001;
-        vm.regs[Register::PC] = 0x0005;
-        // The instruction will have the following encoding:
-        // 1 0 1 1  0 0 1 0  0 0 0 0  0 1 0 1
-        let instr = 0xB205;
-        let _ = vm.store_indirect(instr);
+        vm.regs[Register::R1] = 4;
+
+        vm.assert_registers(&[(Register::PC, 0x3001), (Register::R0, 0), (Register::R1, 5)]);
+    }
+
+    #[test]
+    /// Test if a VM runs its program on the memory model it was given,
+    /// which sees every write of the program
+    fn custom_memory_model_runs_program() {
+        /// Word memory that counts the writes done through it
+        struct CountingMemory {
+            inner: WordMemory,
+            writes: Rc<Cell<usize>>,
+        }
+        impl MemoryModel for CountingMemory {
+            fn read(&mut self, addr: u16) -> Result<u16, VMError> {
+                self.inner.read(addr)
+            }
+            fn write(&mut self, addr: u16, new_val: u16) -> Result<(), VMError> {
+                self.writes.set(self.writes.get().wrapping_add(1));
+                self.inner.write(addr, new_val)
+            }
+            fn peek(&self, addr: u16) -> u16 {
+                self.inner.peek(addr)
+            }
+            fn len(&self) -> usize {
+                self.inner.len()
+            }
+        }
+        let writes = Rc::new(Cell::new(0));
+        let mut vm = VM::new()
+            .with_output(Vec::new())
+            .with_memory(CountingMemory {
+                inner: WordMemory::new(),
+                writes: Rc::clone(&writes),
+            });
+        vm.assemble_and_load(".ORIG x3000\nADD R0, R0, #7\nST R0, DATA\nHALT\nDATA .FILL #0")
+            .unwrap();
+        let loaded = writes.get();
 
-        // Check if 0x000F has the value of register R1
-        assert_eq!(vm.mem.read(final_address).unwrap(), vm.regs[Register::R1]);
+        vm.run().unwrap();
+
+        assert_eq!(vm.mem.peek(0x3003), 7);
+        assert!(writes.get() > loaded);
+        // The model cannot lend its words, so they are copied
+        assert!(matches!(vm.memory_slice(), Cow::Owned(_)));
+        assert_eq!(vm.memory_slice()[0x3003], 7);
     }
 
     #[test]
-    /// Test if store register instruction changes the value in memory.
-    ///
-    /// Register 1 will have value 1, register 0 and offset6 section will both
-    /// have value 5. The address = offset6 + register 0 = 0x000A will be written
-    /// with the value of register 1.
-    fn store_register_changes_value_in_memory() {
-        let mut vm = VM::new();
-        // Set the values of R1 and R0
-        vm.regs[Register::R0] = 0x0005;
-        vm.regs[Register::R1] = 0x0001;
-        // The instruction will have the following encoding:
-        // 0 1 1 1  0 0 1  0 0 0  0 0 0 1 0 1
-        let instr = 0x7205;
-        let _ = vm.store_register(instr);
+    /// Test if a VM with a smaller memory runs programs inside of it,
+    /// but fails to access the addresses after its end
+    fn smaller_memory_rejects_out_of_range_addresses() {
+        let mut vm = VM::new().with_memory_size(0x4000);
+        assert!(matches!(
+            vm.load_words(0x3FFF, &[0x1021, 0x1021]),
+            Err(VMError::InvalidIndex(_))
+        ));
+        // ADD R0, R0, #1 and STR R0, R1, #0
+        vm.load_words(0x3000, &[0x1021, 0x7040]).unwrap();
+        vm.regs[Register::R1] = 0x4000;
 
-        // Check if address 0x000A = R0 + offset6 was written with R1's value
-        let affected_address: u16 = 0x000A;
-        assert_eq!(
-            vm.mem.read(affected_address).unwrap(),
-            vm.regs[Register::R1]
-        );
+        vm.step().unwrap();
+
+        assert!(matches!(vm.step(), Err(VMError::InvalidIndex(0x4000))));
+        assert_eq!(vm.memory_slice().len(), 0x4000);
     }
 
     #[test]
-    /// Test if by using the trap instruction, the register R7 gets the value
-    /// of the PC
-    fn trap_sets_register_7_with_pc_value() {
+    /// Test if polling the keyboard in headless mode keeps waiting while
+    /// there is no input, and gets the key once there is
+    fn headless_keyboard_polling() {
+        let src = "
+            .ORIG x3000
+POLL        LDI R1, KBSR
+            BRzp POLL
+            LDI R0, KBDR
+            HALT
+KBSR        .FILL xFE00
+KBDR        .FILL xFE02
+            .END
+        ";
+        let mut vm = VM::new().with_headless(true).with_input(empty());
+        vm.assemble_and_load(src).unwrap();
+        for _ in 0..10 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.regs[Register::R1], 0);
+        assert!(vm.regs[Register::PC] <= 0x3001);
+
+        let mut vm = VM::new()
+            .with_headless(true)
+            .with_input(Cursor::new(b"k".to_vec()))
+            .with_output(Vec::new());
+        vm.assemble_and_load(src).unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.regs[Register::R0], u16::from(b'k'));
+        assert_eq!(vm.regs[Register::R1], 0x8000);
+    }
+
+    #[test]
+    /// Test if only the locations of the range are cleared, and a range
+    /// that goes past the end of memory clears nothing
+    fn clear_memory_zeroes_only_the_range() {
         let mut vm = VM::new();
-        let pc_val = 0x0A0A;
-        vm.regs[Register::PC] = pc_val;
-        // The instruction will have the following encoding:
-        // 1 1 1 1  0 0 0 0  0 0 1 0  0 1 0 1
-        let instr = 0xF025;
+        vm.load_words(0x4000, &[1, 2, 3, 4, 5]).unwrap();
+        vm.load_words(0xFFFE, &[6, 7]).unwrap();
 
-        let _ = vm.trap(instr);
+        vm.clear_memory(0x4001, 3).unwrap();
 
-        assert_eq!(vm.regs[Register::R7], pc_val);
+        assert_eq!(vm.memory_slice()[0x4000..0x4005], [1, 0, 0, 0, 5]);
+        assert!(matches!(
+            vm.clear_memory(0xFFFE, 3),
+            Err(VMError::InvalidIndex(0x10000))
+        ));
+        assert_eq!(vm.memory_slice()[0xFFFE..], [6, 7]);
     }
 
     #[test]
-    /// Test trap instruction calls the halt trap routine
-    fn trap_calls_correct_subroutine() {
-        let mut vm = VM::new();
-        let pc_val = 0x0A0A;
-        vm.regs[Register::PC] = pc_val;
-        // The instruction will have the following encoding:
-        // 1 1 1 1  0 0 0 0  0 0 1 0  0 1 0 1
-        let instr = 0xF025;
+    /// Test if the flush trap makes the buffered output appear before
+    /// the program waits for input
+    fn flush_trap_writes_buffered_output() {
+        let buffer = SharedBuffer::new();
+        let mut vm = VM::new()
+            .with_output(BufWriter::new(buffer.clone()))
+            .with_input(Cursor::new(b"y".to_vec()));
+        vm.assemble_and_load(".ORIG x3000\nLD R0, CHAR\nOUT\nTRAP x26\nGETC\nCHAR .FILL x3F")
+            .unwrap();
 
-        let _ = vm.trap(instr);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert!(buffer.contents().is_empty());
 
-        // The running flag should change to false
-        assert!(!vm.running);
+        vm.step().unwrap();
+        assert_eq!(buffer.contents(), b"?");
+
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::R0], u16::from(b'y'));
     }
 
     #[test]
-    /// Test if the data is written in the memory, starting from
-    /// the indicated address and with the data in the correct
-    /// endianess
-    fn read_image_file_writes_memory_correctly() {
-        let mut vm = VM::new();
-        let mut data: Vec<u8> = vec![0xFA, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
-        let _ = vm.read_image_file(&mut data).unwrap();
+    /// Test if a custom trap runs its handler instead of failing
+    /// as an unknown trap routine
+    fn custom_trap_runs_its_handler() {
+        fn double_r0(vm: &mut VM) -> Result<(), VMError> {
+            vm.regs[Register::R0] = vm.regs[Register::R0].wrapping_mul(2);
+            Ok(())
+        }
+        let mut vm = VM::new().with_custom_trap(0x40, double_r0);
+        vm.regs[Register::R0] = 21;
 
-        let origin = 0xFA00;
-        assert_eq!(vm.mem.read(origin).unwrap(), 0x0102);
-        assert_eq!(vm.mem.read(origin + 1).unwrap(), 0x0304);
-        assert_eq!(vm.mem.read(origin + 2).unwrap(), 0x0506);
+        vm.execute(0xF040).unwrap();
+
+        assert_eq!(vm.regs[Register::R0], 42);
+        assert!(VM::new().execute(0xF040).is_err());
     }
 
     #[test]
-    /// Test if the data is written in the memory, starting from
-    /// the indicated address and with the data in the correct
-    /// endianess when opening a file
-    fn read_image_reads_file_correctly_into_memory() {
-        let mut vm = VM::new();
+    /// Test if a program calling the abort trap stops with its code,
+    /// without running what comes after it
+    fn abort_trap_stops_with_user_code() {
+        let src = "
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #15
+            ADD R0, R0, R0
+            ADD R0, R0, #12
+            TRAP x28
+            HALT
+            .END
+        ";
+        let mut vm = VM::new().with_output(Vec::new());
+        vm.assemble_and_load(src).unwrap();
 
-        let _ = vm.read_image("test_files/bytes.bin".to_string());
+        let result = vm.run();
 
-        let origin = 0xFA00;
-        assert_eq!(vm.mem.read(origin).unwrap(), 0x0102);
-        assert_eq!(vm.mem.read(origin + 1).unwrap(), 0x0304);
-        assert_eq!(vm.mem.read(origin + 2).unwrap(), 0x0506);
+        assert!(matches!(result, Err(VMError::UserAbort(42))));
+        assert_eq!(exit_code(&result), EXIT_USER_ABORT);
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::UserAbort(42)));
+        assert_eq!(vm.regs[Register::PC], 0x3005);
     }
 
     #[test]
-    /// Test result when adding two values in register mode.
-    /// One value will be in R1 and the other in R2, while
-    /// the destination register will be R0.
-    fn add_with_register_mode() {
-        let sr1 = 0x0001;
-        let sr2 = 0x0002;
-        let result = 0x0003;
-        // Create the registers and set the values on R1 and R2
-        let mut vm = VM::new();
-        vm.regs[Register::R1] = sr1;
-        vm.regs[Register::R2] = sr2;
-        // The instruction will have the following encoding:
-        // 0 0 0 1 0 0 0 0 0 1 0 0 0 0 1 0
-        let instr = 0x1042;
-        let _ = vm.add(instr);
+    /// Test if the queued characters are read in order, and the input
+    /// is used again once they run out
+    fn queued_input_is_read_before_the_input() {
+        let mut vm = VM::new().with_input(Cursor::new(b"d".to_vec()));
 
-        // Check if in R0 we have the desired result
-        assert_eq!(vm.regs[Register::R0], result);
+        vm.queue_input(b"abc");
+
+        for expected in b"abcd" {
+            // GETC
+            vm.execute(0xF020).unwrap();
+            assert_eq!(vm.regs[Register::R0], u16::from(*expected));
+        }
+        assert!(matches!(vm.execute(0xF020), Err(VMError::STDINRead(_))));
     }
 
     #[test]
-    /// Test result when adding the two values in immediate mode.
-    /// One value will be in R1 and the other will be embedded in
-    /// the instruction encoding, while the destination
-    /// register will be R0.
-    fn add_with_immediate_mode() {
-        let sr1 = 0x0001;
-        let result = 0x003;
-        // Create the registers and set the value on R1
-        let mut vm = VM::new();
-        vm.regs[Register::R1] = sr1;
-        // The instruction will have the following encoding:
-        // 0 0 0 1 0 0 0 0 0 1 1 0 0 0 1 0
-        let instr = 0x1062;
-        let _ = vm.add(instr);
+    /// Test if polling the keyboard registers gets the queued characters
+    fn queued_input_is_read_by_the_keyboard_registers() {
+        let mut vm = VM::new().with_headless(true).with_input(empty());
+        vm.queue_input(b"z");
 
-        // Check if in R0 we have the desired result
-        assert_eq!(vm.regs[Register::R0], result);
+        assert_eq!(vm.read_memory(0xFE00).unwrap(), 0x8000);
+        assert_eq!(vm.read_memory(0xFE02).unwrap(), u16::from(b'z'));
+        assert_eq!(vm.read_memory(0xFE00).unwrap(), 0);
     }
 
     #[test]
-    /// Test result when adding one positve value with a
-    /// negative one. To do this adding we need to use immediate mode.
-    fn add_with_negative_value() {
-        let sr1 = 0x0001;
-        let result = 0x000;
-        // Create the registers and set the value on R1
-        let mut vm = VM::new();
-        vm.regs[Register::R1] = sr1;
-        // The instruction will have the following encoding:
-        // 0 0 0 1 0 0 0 0 0 1 1 1 1 1 1 1
-        let instr = 0x107F;
-        let _ = vm.add(instr);
+    /// Test if a store into the next instruction is recorded, while
+    /// stores into data are not
+    fn self_modify_check_records_stores_into_next_instruction() {
+        let src = "
+            .ORIG x3000
+            ST R0, DATA
+            ST R0, NEXT
+NEXT        ADD R1, R1, #1
+            HALT
+DATA        .BLKW 1
+            .END
+        ";
+        let mut vm = VM::new().with_self_modify_check(true);
+        vm.assemble_and_load(src).unwrap();
 
-        // Check if in R0 we have the desired result
-        assert_eq!(vm.regs[Register::R0], result);
+        vm.step().unwrap();
+        vm.step().unwrap();
+
+        assert_eq!(vm.self_modifying_writes(), [(0x3001, 0x3002)]);
+        // The store still happened, so the zeroed word runs as a NOP
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::R1], 0);
     }
 
     #[test]
-    /// Test if the condition flag was set to POS when the
-    /// result of an addition is a positive number.
-    fn add_updates_cond_flag_to_pos() {
-        let sr1 = 0x0001;
-        let sr2 = 0x0002;
-        // Create the registers and set the values on R1 and R2
+    /// Test if the trap vector table of the operating system is valid,
+    /// and a zeroed vector is reported
+    fn validate_trap_table_reports_zeroed_vector() {
         let mut vm = VM::new();
-        vm.regs[Register::R1] = sr1;
-        vm.regs[Register::R2] = sr2;
-        // The instruction will have the following encoding:
-        // 0 0 0 1 0 0 0 0 0 1 0 0 0 0 1 0
-        let instr = 0x1042;
-        let _ = vm.add(instr);
+        assert!(matches!(
+            vm.validate_trap_table(),
+            Err(VMError::InvalidTrapVector(0x20))
+        ));
 
-        assert_eq!(vm.regs[Register::Cond], CondFlag::Pos.value());
+        vm.install_os().unwrap();
+        vm.validate_trap_table().unwrap();
+
+        vm.load_words(0x0022, &[0x0000]).unwrap();
+        assert!(matches!(
+            vm.validate_trap_table(),
+            Err(VMError::InvalidTrapVector(0x22))
+        ));
     }
 
     #[test]
-    /// Test if the condition flag was set to ZRO when the
-    /// result of an addition is a 0.
-    fn add_updates_cond_flag_to_zro() {
-        let sr1 = 0x0001;
-        // Create the registers and set the value on R1
-        let mut vm = VM::new();
-        vm.regs[Register::R1] = sr1;
-        // The instruction will have the following encoding:
-        // 0 0 0 1 0 0 0 0 0 1 1 1 1 1 1 1
-        let instr = 0x107F;
-        let _ = vm.add(instr);
+    /// Test if running until an address stops before executing it,
+    /// and the program can go on from there
+    fn run_until_stops_before_target() {
+        let src = "
+            .ORIG x3000
+            ADD R0, R0, #1
+            ADD R0, R0, #1
+MIDDLE      ADD R0, R0, #1
+            HALT
+            .END
+        ";
+        let mut vm = VM::new().with_output(Vec::new());
+        vm.assemble_and_load(src).unwrap();
 
-        assert_eq!(vm.regs[Register::Cond], CondFlag::Zro.value());
+        assert_eq!(vm.run_until(0x3002).unwrap(), RunOutcome::Reached);
+        assert_eq!(vm.regs[Register::PC], 0x3002);
+        assert_eq!(vm.regs[Register::R0], 2);
+
+        assert_eq!(vm.run_until(0x4000).unwrap(), RunOutcome::Halted);
+        assert_eq!(vm.regs[Register::R0], 3);
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::HaltTrap));
     }
 
     #[test]
-    fn get_c_modifies_register_0_with_input_char() {
-        let char = "c";
-        let char_bytes: u16 = char.as_bytes()[0].into();
-        let mut reader = Cursor::new(char);
-        let mut vm = VM::new();
-        let _ = vm.get_c(&mut reader);
+    /// Test if the cycle limit ends a run towards a target that is
+    /// never reached
+    fn run_until_respects_cycle_limit() {
+        let mut vm = VM::new().with_cycle_limit(10);
+        // BRnzp #-1
+        vm.load_words(0x3000, &[0x0FFF]).unwrap();
 
-        assert_eq!(vm.regs[Register::R0], char_bytes);
+        assert_eq!(vm.run_until(0x4000).unwrap(), RunOutcome::CycleLimit);
+        assert_eq!(vm.cycles(), 10);
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::CycleLimit));
     }
 
     #[test]
-    fn trap_out_writes_register_0_value() {
-        let char = "c";
-        let char_bytes: u16 = char.as_bytes()[0].into();
-        let mut writer: Vec<u8> = Vec::new();
-        let mut vm = VM::new();
-        vm.regs[Register::R0] = char_bytes;
-        let _ = vm.out(&mut writer);
+    /// Test if only the changes of the condition flag are recorded, and
+    /// only the latest ones are kept
+    fn cond_history_records_flag_changes() {
+        let src = "
+            .ORIG x3000
+            ADD R0, R0, #1
+            ADD R0, R0, #1
+            ADD R0, R0, #-2
+            ADD R0, R0, #-1
+            ADD R0, R0, #2
+            .END
+        ";
+        let mut vm = VM::new().with_cond_history(3);
+        vm.assemble_and_load(src).unwrap();
+        let (pos, zro, neg) = (
+            CondFlag::Pos.value(),
+            CondFlag::Zro.value(),
+            CondFlag::Neg.value(),
+        );
 
-        let written_val: u16 = writer[0].into();
-        assert_eq!(written_val, char_bytes);
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        assert_eq!(
+            vm.cond_history(),
+            [(0x3000, zro, pos), (0x3002, pos, zro), (0x3003, zro, neg)]
+        );
+
+        vm.step().unwrap();
+        assert_eq!(
+            vm.cond_history(),
+            [(0x3002, pos, zro), (0x3003, zro, neg), (0x3004, neg, pos)]
+        );
+        assert!(VM::new().cond_history().is_empty());
     }
 
     #[test]
-    fn trap_in_writes_register_0_with_reader_value() {
-        let char = "c";
-        let char_bytes: u16 = char.as_bytes()[0].into();
-        let mut reader = Cursor::new(char);
-        let mut writer: Vec<u8> = Vec::new();
-        let mut vm = VM::new();
-        let _ = vm.trap_in(&mut writer, &mut reader);
+    /// Test if PUTS and PUTSP stop writing a string without its x0000
+    /// once they reach the maximum length
+    fn puts_stops_at_max_string_len() {
+        let buffer = SharedBuffer::new();
+        let mut vm = VM::new().with_output(buffer.clone()).with_max_string_len(4);
+        vm.load_words(0x4000, &[0x0061; 10]).unwrap();
+        vm.regs[Register::R0] = 0x4000;
 
-        assert_eq!(vm.regs[Register::R0], char_bytes);
+        // PUTS
+        assert!(matches!(
+            vm.execute(0xF022),
+            Err(VMError::UnterminatedString(0x4000))
+        ));
+        assert_eq!(buffer.contents(), b"aaaa");
+
+        // PUTSP with a string that fits
+        vm.load_words(0x4003, &[0x0000]).unwrap();
+        vm.execute(0xF024).unwrap();
+        assert_eq!(buffer.contents(), b"aaaaaaa");
     }
 
     #[test]
-    fn trap_in_writes_writer_with_value() {
-        let char = "c";
-        let char_bytes: u16 = char.as_bytes()[0].into();
-        let mut reader = Cursor::new(char);
-        let mut writer: Vec<u8> = Vec::new();
+    /// Test if by default PUTS writes a string as long as it takes to find
+    /// its x0000, and a string is only too long when memory has no x0000
+    fn puts_writes_long_strings_by_default() {
+        let buffer = SharedBuffer::new();
+        let mut vm = VM::new().with_output(buffer.clone());
+        vm.load_words(0x3000, &[0x0061; 0x5000]).unwrap();
+        vm.regs[Register::R0] = 0x3000;
+
+        vm.execute(0xF022).unwrap();
+        assert_eq!(buffer.contents().len(), 0x5000);
+
+        vm.load_words(0, &vec![0x0061; MEMORY_MAX]).unwrap();
+        assert!(matches!(
+            vm.read_string(0x3000, MAX_STRING_LEN),
+            Err(VMError::UnterminatedString(0x3000))
+        ));
+    }
+
+    #[test]
+    /// Test the addresses that every load and store would access,
+    /// without accessing them
+    fn effective_address_of_loads_and_stores() {
         let mut vm = VM::new();
-        let _ = vm.trap_in(&mut writer, &mut reader);
+        vm.regs[Register::R1] = 0x4000;
+        vm.load_words(0x3006, &[0xFE00]).unwrap();
 
-        let written_val: u16 = writer[0].into();
-        assert_eq!(written_val, char_bytes);
+        // LD R0, #5 and ST R0, #-1
+        assert_eq!(vm.effective_address(0x2005, false).unwrap(), 0x3006);
+        assert_eq!(vm.effective_address(0x31FF, false).unwrap(), 0x3000);
+        // LDR R0, R1, #5 and STR R0, R1, #-2
+        assert_eq!(vm.effective_address(0x6045, false).unwrap(), 0x4005);
+        assert_eq!(vm.effective_address(0x707E, false).unwrap(), 0x3FFE);
+        // LDI R0, #5 and STI R0, #5, with and without following the pointer
+        assert_eq!(vm.effective_address(0xA005, false).unwrap(), 0x3006);
+        assert_eq!(vm.effective_address(0xA005, true).unwrap(), 0xFE00);
+        assert_eq!(vm.effective_address(0xB005, true).unwrap(), 0xFE00);
+        // The keyboard was not read
+        assert_eq!(vm.mem.peek(0xFE00), 0);
+        // ADD R0, R0, #1
+        assert!(vm.effective_address(0x1021, false).is_err());
     }
 
     #[test]
-    fn puts_writes_whole_string_on_writer() {
-        let mut writer: Vec<u8> = Vec::new();
-        let (char1, char2, char3) = ("1", "2", "3");
-        let char1_bytes: u16 = char1.as_bytes()[0].into();
-        let char2_bytes: u16 = char2.as_bytes()[0].into();
-        let char3_bytes: u16 = char3.as_bytes()[0].into();
-        let starting_address: u16 = 0x0005;
+    /// Test if a program that spins waiting for the keyboard interrupt
+    /// in headless mode gets out of the loop once input arrives
+    fn spin_loop_exits_when_input_arrives() {
+        let src = "
+            .ORIG x3000
+            LD R0, ENABLE
+            STI R0, KBSR
+SPIN        BRnzp SPIN
+ENABLE      .FILL x4000
+KBSR        .FILL xFE00
+            .END
+            .ORIG x1000
+HANDLER     LDI R2, KBDR
+            HALT
+KBDR        .FILL xFE02
+            .END
+            .ORIG x0180
+            .FILL HANDLER
+            .END
+        ";
+        let mut vm = VM::new()
+            .with_headless(true)
+            .with_input(empty())
+            .with_output(Vec::new())
+            .with_cycle_limit(5);
+        vm.assemble_and_load(src).unwrap();
+        vm.regs[Register::R6] = 0x3000;
+        vm.run().unwrap();
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::CycleLimit));
+        assert_eq!(vm.regs[Register::PC], 0x3002);
 
-        let mut vm = VM::new();
-        vm.regs[Register::R0] = starting_address;
-        let _ = vm.mem.write(starting_address, char1_bytes);
-        let _ = vm.mem.write(starting_address + 1, char2_bytes);
-        let _ = vm.mem.write(starting_address + 2, char3_bytes);
+        vm.queue_input(b"k");
+        vm.run().unwrap();
 
-        let _ = vm.puts(&mut writer);
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::HaltTrap));
+        assert_eq!(vm.regs[Register::R2], u16::from(b'k'));
+    }
 
-        let written_val_1: u16 = writer[0].into();
-        let written_val_2: u16 = writer[1].into();
-        let written_val_3: u16 = writer[2].into();
-        assert_eq!(written_val_1, char1_bytes);
-        assert_eq!(written_val_2, char2_bytes);
-        assert_eq!(written_val_3, char3_bytes);
+    #[test]
+    /// Test if a headless branch to itself does not wait between cycles when
+    /// the keyboard interrupt is disabled, since no key can get out of it
+    fn spin_loop_without_interrupt_does_not_wait() {
+        let mut vm = VM::new()
+            .with_headless(true)
+            .with_input(empty())
+            .with_output(Vec::new())
+            .with_cycle_limit(1000);
+        // BRnzp #-1
+        vm.load_words(0x3000, &[0x0FFF]).unwrap();
+
+        let start = std::time::Instant::now();
+        vm.run().unwrap();
+
+        assert_eq!(vm.last_stop_reason(), Some(StopReason::CycleLimit));
+        // Waiting in every cycle would take at least a second
+        assert!(start.elapsed() < SPIN_WAIT * 500);
     }
 
     #[test]
-    fn halt_changes_bool() {
-        let mut vm = VM::new();
-        let mut writer: Vec<u8> = Vec::new();
+    /// Test if the keys come from the key source, and GETC waits and runs
+    /// again while it has none
+    fn key_source_gives_the_keys() {
+        let keys = Rc::new(RefCell::new(VecDeque::from(b"ab".to_vec())));
+        let source_keys = Rc::clone(&keys);
+        let mut vm = VM::new().with_input(empty());
+        vm.set_key_source(Box::new(move || source_keys.borrow_mut().pop_front()));
+        // GETC, GETC, GETC
+        vm.load_words(0x3000, &[0xF020, 0xF020, 0xF020]).unwrap();
 
-        let _ = vm.halt(&mut writer);
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::R0], u16::from(b'a'));
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::R0], u16::from(b'b'));
+        let started = Instant::now();
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::PC], 0x3002);
+        // Without a key, it waits a bit instead of spinning
+        assert!(started.elapsed() >= SPIN_WAIT);
 
-        assert!(!vm.running);
+        keys.borrow_mut().push_back(b'c');
+        vm.step().unwrap();
+        assert_eq!(vm.regs[Register::R0], u16::from(b'c'));
+        assert_eq!(vm.regs[Register::PC], 0x3003);
+
+        assert_eq!(vm.read_memory(0xFE00).unwrap(), 0);
+        keys.borrow_mut().push_back(b'd');
+        assert_eq!(vm.read_memory(0xFE00).unwrap(), 0x8000);
+        assert_eq!(vm.read_memory(0xFE02).unwrap(), u16::from(b'd'));
     }
 
     #[test]
-    fn halt_writes_on_writer() {
+    /// Test if the listing shows the address, word and disassembly of
+    /// every word in the range
+    fn disassemble_range_lists_words() {
         let mut vm = VM::new();
-        let mut writer: Vec<u8> = Vec::new();
-        let expected_bytes = "HALT\n".as_bytes();
-        let _ = vm.halt(&mut writer);
+        vm.assemble_and_load(".ORIG x3000\nLEA R0, MSG\nPUTS\nHALT\nMSG .FILL xD000")
+            .unwrap();
 
-        assert_eq!(writer, expected_bytes);
+        let listing = vm.disassemble_range(0x3000, 4);
+        let lines: Vec<&str> = listing.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "x3000  xE002  LEA R0, MSG");
+        assert_eq!(lines[2], "x3002  xF025  HALT");
+        assert_eq!(lines[3], "x3003  xD000  .FILL xD000");
     }
 
     #[test]
-    fn puts_p_run_writes_on_writer() {
-        let mut writer: Vec<u8> = Vec::new();
-        let (char1, char2, char3, char4) = ("1", "2", "3", "4");
-        let char1_bytes: u16 = char1.as_bytes()[0].into();
-        let char2_bytes: u16 = char2.as_bytes()[0].into();
-        let char3_bytes: u16 = char3.as_bytes()[0].into();
-        let char4_bytes: u16 = char4.as_bytes()[0].into();
+    /// Test if returning from a program that never set R7 is
+    /// detected as a crash only when the guard is enabled
+    fn crash_guard_detects_jump_to_zero() {
+        let src = ".ORIG x3000\nADD R0, R0, #1\nRET";
+        let mut guarded = VM::new().with_output(Vec::new()).with_crash_guard(true);
+        guarded.assemble_and_load(src).unwrap();
+        guarded.run().unwrap();
 
-        let memory_location1: u16 = (char2_bytes << 8) | char1_bytes;
-        let memory_location2: u16 = (char4_bytes << 8) | char3_bytes;
+        let mut unguarded = VM::new().with_output(Vec::new());
+        unguarded.assemble_and_load(src).unwrap();
+        unguarded.step().unwrap();
+        unguarded.step().unwrap();
+        // The device region is not user space, like for the access checks
+        let mut device = VM::new().with_output(Vec::new()).with_crash_guard(true);
+        device
+            .assemble_and_load(".ORIG xFE10\nAND R7, R7, #0\nRET")
+            .unwrap();
+        device.step().unwrap();
+        device.step().unwrap();
 
-        let starting_address: u16 = 0x0005;
-        let mut vm = VM::new();
-        vm.regs[Register::R0] = starting_address;
-        let _ = vm.mem.write(starting_address, memory_location1);
-        let _ = vm.mem.write(starting_address + 1, memory_location2);
+        assert_eq!(guarded.last_stop_reason(), Some(StopReason::LikelyCrash));
+        assert_eq!(guarded.regs[Register::PC], 0x0000);
+        assert!(unguarded.running);
+        assert_eq!(device.regs[Register::PC], 0x0000);
+        assert_eq!(device.last_stop_reason(), None);
+    }
 
-        let _ = vm.puts_p(&mut writer);
+    #[test]
+    /// Test if the trace shows the registers changed by each instruction,
+    /// leaving out the PC
+    fn trace_annotates_changed_registers() {
+        let trace = SharedBuffer::new();
+        let src = ".ORIG x3000\nADD R1, R1, #1\nADD R0, R1, R2\nBRnzp #0";
+        let mut vm = VM::new().with_trace(trace.clone());
+        vm.assemble_and_load(src).unwrap();
+        vm.regs[Register::R2] = 2;
 
-        let written_val_1: u16 = writer[0].into();
-        let written_val_2: u16 = writer[1].into();
-        let written_val_3: u16 = writer[2].into();
-        let written_val_4: u16 = writer[3].into();
-        assert_eq!(written_val_1, char1_bytes);
-        assert_eq!(written_val_2, char2_bytes);
-        assert_eq!(written_val_3, char3_bytes);
-        assert_eq!(written_val_4, char4_bytes);
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(trace.contents()).unwrap(),
+            "x3000: ADD R1, R1, #1 ; R1=x0001 COND=x0001\n\
+             x3001: ADD R0, R1, R2 ; R0=x0003\n\
+             x3002: BRnzp #0\n"
+        );
     }
 }
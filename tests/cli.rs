@@ -0,0 +1,254 @@
+mod common;
+
+use std::{
+    env, fs,
+    process::{Command, Stdio},
+};
+
+use common::{run_vm, temp_path, write_image};
+
+#[test]
+/// Test if the output of the program is written into the file given with
+/// `--output`, with and without the HALT banner
+fn output_flag_writes_program_output_into_file() {
+    let quiet_path = temp_path("quiet.out");
+    let banner_path = temp_path("banner.out");
+
+    let quiet = Command::new(env!("CARGO_BIN_EXE_vm"))
+        .arg("--quiet")
+        .arg("--output")
+        .arg(&quiet_path)
+        .arg("test_files/hello.obj")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let banner = Command::new(env!("CARGO_BIN_EXE_vm"))
+        .arg("--output")
+        .arg(&banner_path)
+        .arg("test_files/hello.obj")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let quiet_contents = fs::read_to_string(&quiet_path).unwrap();
+    let banner_contents = fs::read_to_string(&banner_path).unwrap();
+    fs::remove_file(&quiet_path).unwrap();
+    fs::remove_file(&banner_path).unwrap();
+
+    assert!(quiet.status.success());
+    assert!(banner.status.success());
+    assert_eq!(quiet_contents, "Hello, World!\n");
+    assert_eq!(banner_contents, "Hello, World!\nHALT\n");
+    assert!(quiet.stdout.is_empty());
+}
+
+#[test]
+/// Test if the output of the program is written both on stdout and
+/// into the file given with `--tee`
+fn tee_flag_writes_output_on_stdout_and_file() {
+    let tee_path = temp_path("tee.out");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_vm"))
+        .arg("--tee")
+        .arg(&tee_path)
+        .arg("test_files/hello.obj")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let tee_contents = fs::read(&tee_path).unwrap();
+    fs::remove_file(&tee_path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Hello, World!\nHALT\n");
+    assert_eq!(tee_contents, output.stdout);
+}
+
+#[test]
+/// Test if a word poked into memory is the one the program reads
+fn poke_flag_patches_memory_before_running() {
+    let image = write_image(
+        "poke.obj",
+        ".ORIG x3000\nLD R0, CHAR\nOUT\nHALT\nCHAR .FILL x41",
+    );
+    let image = image.to_str().unwrap();
+
+    let original = run_vm(&["--quiet", image]);
+    let poked = run_vm(&["--quiet", "--poke", "x3003=x42", image]);
+    let poked_decimal = run_vm(&["--quiet", "--poke", "12291=67", image]);
+    fs::remove_file(image).unwrap();
+
+    assert_eq!(original, "A");
+    assert_eq!(poked, "B");
+    assert_eq!(poked_decimal, "C");
+}
+
+#[test]
+/// Test if a register set from the arguments holds its value when the
+/// program starts
+fn set_reg_flag_seeds_registers() {
+    let image = write_image("set_reg.obj", ".ORIG x3000\nOUT\nHALT");
+    let image = image.to_str().unwrap();
+
+    let output = run_vm(&["--quiet", "--set-reg", "R0=0x48", image]);
+    fs::remove_file(image).unwrap();
+
+    assert_eq!(output, "H");
+}
+
+#[test]
+/// Test if `--stats` writes the number of executed instructions on stderr,
+/// leaving the output of the program alone
+fn stats_flag_reports_instruction_count() {
+    let image = write_image(
+        "stats.obj",
+        ".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #1\nADD R0, R0, #1\nHALT\n.END",
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_vm"))
+        .arg("--stats")
+        .arg(&image)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    fs::remove_file(&image).unwrap();
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "HALT\n");
+    assert!(stderr.contains("instructions: 4\n"), "stderr: {}", stderr);
+    assert!(stderr.contains("instructions per second: "));
+}
+
+#[test]
+/// Test if `--strict-halt` makes a program that runs past its end, instead
+/// of executing HALT, fail with its own exit code, and lets a proper one pass
+fn strict_halt_flag_requires_halt() {
+    let no_halt = write_image("no_halt.obj", ".ORIG x3000\nAND R0, R0, #0\n.END");
+    let halt = write_image("halt.obj", ".ORIG x3000\nAND R0, R0, #0\nHALT\n.END");
+    let run = |image: &std::path::Path, strict: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_vm"));
+        if strict {
+            command.arg("--strict-halt");
+        }
+        command
+            .args(["--quiet", "--max-cycles", "100"])
+            .arg(image)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let lenient = run(&no_halt, false);
+    let strict = run(&no_halt, true);
+    let proper = run(&halt, true);
+    fs::remove_file(&no_halt).unwrap();
+    fs::remove_file(&halt).unwrap();
+
+    assert!(lenient.status.success());
+    assert_eq!(strict.status.code(), Some(3));
+    let stderr = String::from_utf8(strict.stderr).unwrap();
+    assert!(stderr.contains("MissingHalt"), "stderr: {}", stderr);
+    assert!(stderr.contains("CycleLimit"), "stderr: {}", stderr);
+    assert!(proper.status.success());
+}
+
+#[test]
+/// Test if the `disasm` subcommand lists every word of the image
+/// without running it
+fn disasm_subcommand_lists_image() {
+    let image = write_image(
+        "disasm.obj",
+        ".ORIG x3000\nAND R0, R0, #0\nADD R0, R0, #2\nOUT\nHALT\n.END",
+    );
+    let image = image.to_str().unwrap();
+
+    let listing = run_vm(&["disasm", image]);
+    fs::remove_file(image).unwrap();
+
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("x3000") && lines[0].contains("AND R0, R0, #0"));
+    assert!(lines[1].contains("ADD R0, R0, #2"));
+    assert!(lines[2].contains("OUT"));
+    assert!(lines[3].contains("HALT"));
+}
+
+#[test]
+/// Test if the binary exits with the code of the contract for a clean HALT,
+/// a runtime error, wrong arguments and a missing HALT in strict mode
+fn exit_codes_follow_contract() {
+    let halt = write_image("exit_halt.obj", ".ORIG x3000\nHALT\n.END");
+    // The reserved opcode cannot be executed
+    let error = write_image("exit_error.obj", ".ORIG x3000\n.FILL xD000\n.END");
+    let no_halt = write_image("exit_no_halt.obj", ".ORIG x3000\nBRnzp #-1\n.END");
+    let exit_code = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_vm"))
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+            .status
+            .code()
+    };
+    let path = |image: &std::path::Path| image.to_str().unwrap().to_string();
+
+    let clean = exit_code(&[&path(&halt)]);
+    let runtime_error = exit_code(&[&path(&error)]);
+    let no_arguments = exit_code(&[]);
+    let bad_flag = exit_code(&["--pc", "nowhere", &path(&halt)]);
+    let missing_image = exit_code(&["does_not_exist.obj"]);
+    let cycle_limit = exit_code(&["--max-cycles", "50", &path(&no_halt)]);
+    let strict_cycle_limit = exit_code(&["--strict-halt", "--max-cycles", "50", &path(&no_halt)]);
+    for image in [halt, error, no_halt] {
+        fs::remove_file(image).unwrap();
+    }
+
+    assert_eq!(clean, Some(0));
+    assert_eq!(runtime_error, Some(1));
+    assert_eq!(no_arguments, Some(2));
+    assert_eq!(bad_flag, Some(2));
+    assert_eq!(missing_image, Some(2));
+    assert_eq!(cycle_limit, Some(0));
+    assert_eq!(strict_cycle_limit, Some(3));
+}
+
+#[test]
+/// Test if the self test passes and exits with the success code
+fn selftest_flag_passes() {
+    let output = Command::new(env!("CARGO_BIN_EXE_vm"))
+        .arg("--selftest")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"selftest: pass\n");
+}
+
+#[test]
+/// Test if the output is raw by default and only escaped with
+/// `--sanitize-output`, but never in the file given with `--output`
+fn sanitize_output_flag_escapes_control_bytes() {
+    let image = write_image(
+        "bell.obj",
+        ".ORIG x3000\nLD R0, BELL\nOUT\nHALT\nBELL .FILL x07",
+    );
+    let image = image.to_str().unwrap();
+    let output_path = temp_path("bell.out");
+
+    let raw = run_vm(&["--quiet", image]);
+    let sanitized = run_vm(&["--quiet", "--sanitize-output", image]);
+    run_vm(&[
+        "--quiet",
+        "--sanitize-output",
+        "--output",
+        output_path.to_str().unwrap(),
+        image,
+    ]);
+    let file_contents = fs::read(&output_path).unwrap();
+    fs::remove_file(&output_path).unwrap();
+    fs::remove_file(image).unwrap();
+
+    assert_eq!(raw, "\u{7}");
+    assert_eq!(sanitized, "\\x07");
+    assert_eq!(file_contents, [0x07]);
+}
@@ -0,0 +1,135 @@
+// Test helpers are expected to panic when something goes wrong
+#![allow(clippy::expect_used)]
+// Each test crate only uses some of the helpers
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use lc3::{
+    asm::{assemble, image_bytes},
+    hardware::Register,
+    utils::SharedBuffer,
+    vm::{StopReason, VM},
+};
+
+/// Banner written by the HALT trap routine
+const HALT_BANNER: &[u8] = b"HALT\n";
+
+/// Runs the image found in `image_path` feeding it `input` as its stdin,
+/// and returns every byte the program wrote on its stdout.
+pub fn run_image(image_path: impl AsRef<Path>, input: &[u8]) -> Vec<u8> {
+    let mut image = fs::read(image_path).expect("cannot read image file");
+    let output = SharedBuffer::new();
+    let mut vm = VM::new()
+        .with_input(Cursor::new(input.to_vec()))
+        .with_output(output.clone());
+    vm.read_image_file(&mut image).expect("cannot load image");
+    vm.run().expect("program did not run until HALT");
+    output.contents()
+}
+
+/// Everything that a run of a program leaves behind
+#[derive(Debug, PartialEq)]
+pub struct RunResult {
+    pub output: Vec<u8>,
+    pub registers: Vec<u16>,
+    pub memory: Vec<u16>,
+    pub stop_reason: Option<StopReason>,
+}
+
+/// Runs the image with `input` as its stdin and returns its output
+/// together with the state the VM was left in.
+pub fn run_with_state(image: &[u8], input: &[u8]) -> RunResult {
+    let output = SharedBuffer::new();
+    let mut vm = VM::new()
+        .with_input(Cursor::new(input.to_vec()))
+        .with_output(output.clone());
+    vm.load_image(image).expect("cannot load image");
+    vm.run().expect("program did not run until HALT");
+    RunResult {
+        output: output.contents(),
+        registers: Register::all().iter().map(|r| vm.register(*r)).collect(),
+        memory: vm.memory_slice().to_vec(),
+        stop_reason: vm.last_stop_reason(),
+    }
+}
+
+/// Runs the image twice with the same input and checks that both runs wrote
+/// the same output and left the registers and memory in the same state.
+pub fn assert_deterministic(image: &[u8], input: &[u8]) {
+    let first = run_with_state(image, input);
+    let second = run_with_state(image, input);
+    assert_eq!(
+        first.output, second.output,
+        "the output changed between runs"
+    );
+    assert_eq!(
+        first.registers, second.registers,
+        "the registers changed between runs"
+    );
+    assert!(
+        first.memory == second.memory,
+        "the memory changed between runs"
+    );
+    assert_eq!(first.stop_reason, second.stop_reason);
+}
+
+/// Runs the image found in `image_path` with `input` as its stdin and checks that
+/// its output is byte-for-byte the same as the content of the golden file.
+///
+/// When `normalize_halt` is set, the trailing HALT banner is removed from the
+/// output before comparing, so the golden file only holds what the program printed.
+pub fn assert_golden(
+    image_path: impl AsRef<Path>,
+    input: &[u8],
+    golden_path: impl AsRef<Path>,
+    normalize_halt: bool,
+) {
+    let output = run_image(image_path, input);
+    let output = match output.strip_suffix(HALT_BANNER) {
+        Some(stripped) if normalize_halt => stripped.to_vec(),
+        _ => output,
+    };
+    let golden = fs::read(golden_path.as_ref()).expect("cannot read golden file");
+    assert_eq!(
+        String::from_utf8_lossy(&output),
+        String::from_utf8_lossy(&golden),
+        "output does not match golden file {}",
+        golden_path.as_ref().display()
+    );
+    assert_eq!(output, golden);
+}
+
+/// Returns a path in the temporary directory that is unique for the test
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("lc3-{}-{}", std::process::id(), name))
+}
+
+/// Assembles the source into an image file in the temporary directory. An
+/// image has a single origin, so the source must have exactly one segment.
+pub fn write_image(name: &str, src: &str) -> PathBuf {
+    let segments = assemble(src).expect("cannot assemble the image");
+    let [(origin, words)]: [_; 1] = segments
+        .try_into()
+        .expect("the image must have exactly one segment");
+    let path = temp_path(name);
+    fs::write(&path, image_bytes(origin, &words)).expect("cannot write the image");
+    path
+}
+
+/// Runs the VM binary with the arguments and no input, checking that it
+/// succeeds, and returns what it wrote on stdout
+pub fn run_vm(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_vm"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .expect("cannot run the VM binary");
+    assert!(output.status.success(), "the VM failed: {:?}", output);
+    String::from_utf8(output.stdout).expect("the output is not UTF-8")
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use std::fs;
+
+use common::{assert_deterministic, run_with_state};
+use lc3::{
+    asm::{assemble, image_bytes},
+    vm::StopReason,
+};
+
+#[test]
+/// Test if running the hello world program twice gives the same result
+fn hello_world_is_deterministic() {
+    let image = fs::read("test_files/hello.obj").unwrap();
+
+    assert_deterministic(&image, b"");
+}
+
+#[test]
+/// Test if a program that reads its input and writes into memory
+/// behaves the same way in every run
+fn echo_program_is_deterministic() {
+    let src = "
+        .ORIG x3000
+        LEA R1, BUF
+LOOP    GETC
+        ADD R2, R0, #-10
+        BRz DONE
+        STR R0, R1, #0
+        ADD R1, R1, #1
+        OUT
+        BR LOOP
+DONE    HALT
+BUF     .BLKW 8
+    ";
+    let [(origin, words)] = assemble(src).unwrap().try_into().unwrap();
+    let image = image_bytes(origin, &words);
+
+    assert_deterministic(&image, b"abc\n");
+    let result = run_with_state(&image, b"abc\n");
+    assert_eq!(result.output, b"abcHALT\n");
+    assert_eq!(result.stop_reason, Some(StopReason::HaltTrap));
+}
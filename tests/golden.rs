@@ -0,0 +1,10 @@
+mod common;
+
+use common::assert_golden;
+
+#[test]
+/// Test if the hello world program prints exactly the
+/// content of its golden file
+fn hello_world_matches_golden_output() {
+    assert_golden("test_files/hello.obj", b"", "tests/golden/hello.out", true);
+}
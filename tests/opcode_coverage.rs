@@ -0,0 +1,58 @@
+use lc3::{hardware::OpCode, vm::VM};
+
+/// Program that executes every operation at least once, ending with
+/// an RTI that returns to the HALT
+const ALL_OPCODES: &str = "
+    .ORIG x3000
+    LEA R0, DATA
+    LD R1, DATA
+    LDR R2, R0, #0
+    LDI R3, PTR
+    ST R1, SLOT
+    STR R2, R0, #1
+    STI R3, PTR
+    ADD R1, R1, #1
+    AND R2, R2, #0
+    NOT R3, R3
+    BRnzp SKIP
+SKIP JSR SUB
+    LEA R4, AFTER
+    JMP R4
+AFTER LD R6, STACK
+    LEA R5, DONE
+    STR R5, R6, #0
+    AND R5, R5, #0
+    STR R5, R6, #1
+    RTI
+DONE HALT
+SUB RET
+DATA .FILL #5
+SLOT .FILL #0
+PTR .FILL SLOT
+STACK .FILL x2FFE
+    .END
+";
+
+#[test]
+/// Test if every operation the VM supports gets executed by the crafted
+/// program, so a new operation cannot be added without being run here
+fn every_opcode_is_executed() {
+    let mut vm = VM::new()
+        .with_opcode_histogram(true)
+        .with_output(Vec::new());
+    vm.assemble_and_load(ALL_OPCODES).unwrap();
+
+    vm.run().unwrap();
+
+    let histogram = vm.opcode_histogram();
+    let missing: Vec<&OpCode> = OpCode::all()
+        .iter()
+        .filter(|op| histogram.get(op).copied().unwrap_or(0) == 0)
+        .collect();
+    assert!(
+        missing.is_empty(),
+        "operations never executed: {:?}",
+        missing
+    );
+    assert_eq!(OpCode::all().len(), 15);
+}
@@ -0,0 +1,67 @@
+use lc3::{hardware::MEMORY_MAX, vm::VM};
+
+/// Small xorshift generator, enough to get varied images that are
+/// the same in every run
+struct XorShift(u32);
+
+impl XorShift {
+    fn next_u16(&mut self) -> u16 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        let [high, low, _, _] = self.0.to_be_bytes();
+        u16::from_be_bytes([high, low])
+    }
+
+    /// Returns a value in `1..=max`, `max` cannot be 0
+    fn next_len(&mut self, max: usize) -> usize {
+        usize::from(self.next_u16())
+            .checked_rem(max)
+            .unwrap_or_default()
+            .saturating_add(1)
+    }
+}
+
+#[test]
+/// Test if words written at a random origin, exported as an image and
+/// loaded into a fresh VM end up at the same addresses with the same values
+fn exported_images_load_back_unchanged() {
+    let mut rng = XorShift(0x2F6B_1D3A);
+
+    for _ in 0..200 {
+        let origin = rng.next_u16();
+        let room = MEMORY_MAX.saturating_sub(usize::from(origin));
+        let len = rng.next_len(room.min(512));
+        let words: Vec<u16> = (0..len).map(|_| rng.next_u16()).collect();
+        let end = usize::from(origin).saturating_add(len);
+        let mut source = VM::new();
+        source.load_words(origin, &words).unwrap();
+
+        let bytes = source
+            .export_image(origin, u16::try_from(len).unwrap())
+            .unwrap();
+        let mut loaded = VM::new();
+        let loaded_origin = loaded.load_image(&bytes).unwrap();
+
+        assert_eq!(bytes.len(), words.len().saturating_add(1).saturating_mul(2));
+        assert_eq!(bytes[..2], origin.to_be_bytes());
+        assert_eq!(loaded_origin, origin);
+        assert_eq!(
+            loaded.memory_slice()[usize::from(origin)..end],
+            words,
+            "origin x{:04X}, {} words",
+            origin,
+            len
+        );
+        assert_eq!(loaded.memory_slice(), source.memory_slice());
+    }
+}
+
+#[test]
+/// Test if a range going past the last memory address cannot be exported
+fn export_past_end_of_memory_fails() {
+    let vm = VM::new();
+
+    assert!(vm.export_image(u16::MAX, 2).is_err());
+    assert_eq!(vm.export_image(u16::MAX, 1).unwrap(), [0xFF, 0xFF, 0, 0]);
+}